@@ -1,7 +1,13 @@
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, Token, TokenAccount};
-use solana_program::instruction::{AccountMeta, Instruction};
+use anchor_spl::associated_token::{self, AssociatedToken};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+use std::ops::{Deref, DerefMut};
+
+pub mod nav;
+pub mod replay;
+
+#[cfg(test)]
+mod tests;
 
 declare_id!("E5T9eLjbfeWRCegwocsrWfn1CkH4a5MnySjheUuwfNMt");
 
@@ -13,20 +19,97 @@ pub struct BasketState {
     pub total_supply: u64,
     pub bump: u8,
     pub max_tokens: u8,
-    pub paused: bool,
+    pub deposits_paused: bool, // Occupies the legacy `paused` slot
     pub reentrancy_guard: bool,
+    pub pending_authority: Option<Pubkey>,
+    pub basket_id: u64,
+    pub entry_fee_bps: u16,
+    pub fee_recipient: Pubkey,
+    pub exit_fee_bps: u16,
+    pub redemptions_paused: bool,
+    pub max_deposit_per_user: u64,
+    pub max_tvl_lamports: u64,
+    pub input_mints: Vec<Pubkey>, // SPL tokens accepted by deposit_token
+    pub min_weight: u8,
+    pub minimum_deposit: u64, // Lamports
+    pub max_slippage_bps: u16, // Ceiling on user-supplied slippage
+    pub management_fee_bps: u16, // Annualized
+    pub last_fee_collection: i64,
+    pub timelock_seconds: u64,
+    pub pending_action: Option<PendingAction>,
+    pub performance_fee_bps: u16, // Share of gains above the high-water mark
+    pub high_water_mark: u128, // Peak price per share, scaled by MAGNIFIER
+    pub auto_pause_threshold_bps: u16, // Realized slippage that aborts a trade (0 disables)
+    pub max_price_age_seconds: u64, // Oldest oracle price NAV will accept
+    pub guardian: Pubkey, // May pause immediately, nothing else
+    pub pending_deposit_lamports: u64, // SOL held for split deposits, outside NAV
+    pub min_swap_lamports: u64, // Smallest leg deposit will route (0 disables)
+    pub roll_small_legs: bool, // Carry a too-small leg into the next one instead of skipping it
+    pub initialized: bool, // Set once the basket mint exists
+    pub rebalance_cooldown_seconds: u64, // Minimum gap between rebalances
+    pub last_rebalance: i64, // Unix timestamp of the last rebalance
+    pub min_hold_seconds: u64, // Wait after a deposit before its user can redeem; 0 disables
+    pub pending_redeem_lamports: u64, // SOL owed to in-flight split redemptions
+    pub allowed_swap_programs: Vec<Pubkey>, // Routers swaps may be sent to
+    pub version: u8, // Layout version; accounts that predate the field read as 0
+    pub pause_reason: String, // Why the basket is paused; empty while nothing is
+    pub swap_discriminator: u8, // Instruction index of the router's route instruction
+    pub max_swaps_per_tx: u8, // Most weighted legs `deposit`/`redeem` will swap in one go (0 disables)
 }
 
 impl BasketState {
     pub const DISCRIMINATOR_SIZE: usize = 8;
     pub const AUTHORITY_SIZE: usize = 32;
-    pub const TOKEN_ENTRY_SIZE: usize = 32 + 1 + 32; // Pubkey + weight(u8) + token_account
+    pub const TOKEN_ENTRY_SIZE: usize = 32 + 1 + 32 + 32 + 1 + 8; // Pubkey + weight(u8) + token_account + price_feed + price_source + reserved_amount
     pub const VEC_PREFIX_SIZE: usize = 4; // For Vec length
     pub const TOTAL_SUPPLY_SIZE: usize = 8;
     pub const BUMP_SIZE: usize = 1;
     pub const MAX_TOKENS_SIZE: usize = 1;
-    pub const PAUSED_SIZE: usize = 1;
+    pub const DEPOSITS_PAUSED_SIZE: usize = 1;
     pub const REENTRANCY_GUARD_SIZE: usize = 1;
+    pub const PENDING_AUTHORITY_SIZE: usize = 1 + 32; // Option tag + Pubkey
+    pub const BASKET_ID_SIZE: usize = 8;
+    pub const ENTRY_FEE_BPS_SIZE: usize = 2;
+    pub const FEE_RECIPIENT_SIZE: usize = 32;
+    pub const EXIT_FEE_BPS_SIZE: usize = 2;
+    pub const REDEMPTIONS_PAUSED_SIZE: usize = 1;
+    pub const MAX_DEPOSIT_PER_USER_SIZE: usize = 8;
+    pub const MAX_TVL_LAMPORTS_SIZE: usize = 8;
+    pub const MAX_INPUT_MINTS: usize = 4;
+    pub const INPUT_MINTS_SIZE: usize = 4 + 32 * Self::MAX_INPUT_MINTS; // Vec length + Pubkeys
+    pub const MIN_WEIGHT_SIZE: usize = 1;
+    pub const MINIMUM_DEPOSIT_SIZE: usize = 8;
+    pub const MAX_SLIPPAGE_BPS_SIZE: usize = 2;
+    pub const MANAGEMENT_FEE_BPS_SIZE: usize = 2;
+    pub const LAST_FEE_COLLECTION_SIZE: usize = 8;
+    pub const TIMELOCK_SECONDS_SIZE: usize = 8;
+    pub const PENDING_ACTION_SIZE: usize = 1 + 1 + 33 + 8; // Option tag + action tag + largest payload + execute_after
+    pub const PERFORMANCE_FEE_BPS_SIZE: usize = 2;
+    pub const HIGH_WATER_MARK_SIZE: usize = 16;
+    pub const AUTO_PAUSE_THRESHOLD_BPS_SIZE: usize = 2;
+    pub const MAX_PRICE_AGE_SECONDS_SIZE: usize = 8;
+    pub const GUARDIAN_SIZE: usize = 32;
+    pub const PENDING_DEPOSIT_LAMPORTS_SIZE: usize = 8;
+    pub const MIN_SWAP_LAMPORTS_SIZE: usize = 8;
+    pub const ROLL_SMALL_LEGS_SIZE: usize = 1;
+    pub const INITIALIZED_SIZE: usize = 1;
+    pub const REBALANCE_COOLDOWN_SECONDS_SIZE: usize = 8;
+    pub const LAST_REBALANCE_SIZE: usize = 8;
+    pub const MIN_HOLD_SECONDS_SIZE: usize = 8;
+    pub const PENDING_REDEEM_LAMPORTS_SIZE: usize = 8;
+    pub const MAX_SWAP_PROGRAMS: usize = 4;
+    pub const ALLOWED_SWAP_PROGRAMS_SIZE: usize = 4 + 32 * Self::MAX_SWAP_PROGRAMS; // Vec length + Pubkeys
+    pub const VERSION_SIZE: usize = 1;
+    pub const MAX_PAUSE_REASON_LENGTH: usize = 64;
+    pub const PAUSE_REASON_SIZE: usize = 4 + Self::MAX_PAUSE_REASON_LENGTH; // String length + bytes
+    pub const SWAP_DISCRIMINATOR_SIZE: usize = 1;
+    pub const MAX_SWAPS_PER_TX_SIZE: usize = 1;
+
+    // Version 1 is every layout up to `pending_redeem_lamports`; version 2
+    // added `allowed_swap_programs` and `version`; version 3 `pause_reason`;
+    // version 4 `swap_discriminator`; version 5 `max_swaps_per_tx`; version 6
+    // widened `pending_action` to fit swap program changes
+    pub const CURRENT_VERSION: u8 = 6;
 
     pub fn required_space(max_tokens: usize) -> usize {
         Self::DISCRIMINATOR_SIZE
@@ -36,546 +119,6217 @@ impl BasketState {
             + Self::TOTAL_SUPPLY_SIZE
             + Self::BUMP_SIZE
             + Self::MAX_TOKENS_SIZE
-            + Self::PAUSED_SIZE
+            + Self::DEPOSITS_PAUSED_SIZE
             + Self::REENTRANCY_GUARD_SIZE
+            + Self::PENDING_AUTHORITY_SIZE
+            + Self::BASKET_ID_SIZE
+            + Self::ENTRY_FEE_BPS_SIZE
+            + Self::FEE_RECIPIENT_SIZE
+            + Self::EXIT_FEE_BPS_SIZE
+            + Self::REDEMPTIONS_PAUSED_SIZE
+            + Self::MAX_DEPOSIT_PER_USER_SIZE
+            + Self::MAX_TVL_LAMPORTS_SIZE
+            + Self::INPUT_MINTS_SIZE
+            + Self::MIN_WEIGHT_SIZE
+            + Self::MINIMUM_DEPOSIT_SIZE
+            + Self::MAX_SLIPPAGE_BPS_SIZE
+            + Self::MANAGEMENT_FEE_BPS_SIZE
+            + Self::LAST_FEE_COLLECTION_SIZE
+            + Self::TIMELOCK_SECONDS_SIZE
+            + Self::PENDING_ACTION_SIZE
+            + Self::PERFORMANCE_FEE_BPS_SIZE
+            + Self::HIGH_WATER_MARK_SIZE
+            + Self::AUTO_PAUSE_THRESHOLD_BPS_SIZE
+            + Self::MAX_PRICE_AGE_SECONDS_SIZE
+            + Self::GUARDIAN_SIZE
+            + Self::PENDING_DEPOSIT_LAMPORTS_SIZE
+            + Self::MIN_SWAP_LAMPORTS_SIZE
+            + Self::ROLL_SMALL_LEGS_SIZE
+            + Self::INITIALIZED_SIZE
+            + Self::REBALANCE_COOLDOWN_SECONDS_SIZE
+            + Self::LAST_REBALANCE_SIZE
+            + Self::MIN_HOLD_SECONDS_SIZE
+            + Self::PENDING_REDEEM_LAMPORTS_SIZE
+            + Self::ALLOWED_SWAP_PROGRAMS_SIZE
+            + Self::VERSION_SIZE
+            + Self::PAUSE_REASON_SIZE
+            + Self::SWAP_DISCRIMINATOR_SIZE
+            + Self::MAX_SWAPS_PER_TX_SIZE
     }
-}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
-pub struct TokenInfo {
-    pub mint: Pubkey,
-    pub weight: u8,            // Percentage weight in basket
-    pub token_account: Pubkey, // Associated token account owned by basket
-}
-pub mod jupiter {
-    use anchor_lang::prelude::*;
-
-    pub static JUPITER_V6_ID: Pubkey =
-        solana_program::pubkey!("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4");
 
-    #[derive(AnchorSerialize, AnchorDeserialize)]
-    pub struct RouteSwapParams {
-        pub in_amount: u64,
-        pub quote_id: [u8; 32],
-        pub slippage_bps: u16,
+    /// Whether `weight` clears the basket's per-token floor (0 disables it)
+    pub fn meets_min_weight(&self, weight: u8) -> bool {
+        self.min_weight == 0 || weight >= self.min_weight
     }
-}
-
-pub struct ReentrancyGuard<'info> {
-    guard_account: &'info mut Account<'info, BasketState>,
-}
 
-impl<'info> Drop for ReentrancyGuard<'info> {
-    fn drop(&mut self) {
-        self.guard_account.reentrancy_guard = false;
+    /// Queue `action` to run once the basket's timelock has elapsed,
+    /// replacing any action already pending
+    pub fn queue_action(&mut self, action: TimelockedAction) -> Result<()> {
+        let execute_after = Clock::get()?
+            .unix_timestamp
+            .checked_add(self.timelock_seconds as i64)
+            .ok_or(BasketError::MathOverflow)?;
+        self.pending_action = Some(PendingAction {
+            action,
+            execute_after,
+        });
+        Ok(())
     }
-}
 
-pub fn with_reentrancy_guard<'a, T, F>(account: &'a mut Account<'a, BasketState>, f: F) -> Result<T>
-where
-    F: FnOnce() -> Result<T>,
-{
-    if account.reentrancy_guard {
-        return err!(BasketError::ReentrancyDetected);
+    /// Remove and return the pending action if its timelock has elapsed
+    pub fn take_ready_action(&mut self) -> Result<TimelockedAction> {
+        let pending = self
+            .pending_action
+            .as_ref()
+            .ok_or(BasketError::NoPendingAction)?;
+        require!(
+            Clock::get()?.unix_timestamp >= pending.execute_after,
+            BasketError::TimelockNotElapsed
+        );
+        Ok(self.pending_action.take().unwrap().action)
     }
-    account.reentrancy_guard = true;
-    let _guard = ReentrancyGuard {
-        guard_account: account,
-    };
-    let result = f();
-    result
-}
 
-#[program]
-pub mod basket_token {
-    use super::*;
+    /// Whether realizing `realized` lamports of value where `expected` was due
+    /// loses more than `auto_pause_threshold_bps`
+    pub fn trips_circuit_breaker(&self, expected: u64, realized: u64) -> bool {
+        if self.auto_pause_threshold_bps == 0 || realized >= expected {
+            return false;
+        }
+        let shortfall_bps = (expected - realized) as u128 * basket_token::BPS_DENOMINATOR as u128
+            / expected as u128;
+        shortfall_bps > self.auto_pause_threshold_bps as u128
+    }
 
-    pub const MAGNIFIER: u128 = 1_000_000_000;
-    pub const MINIMUM_DEPOSIT: u64 = 10_000_000;
-    pub const MAX_TOKENS: usize = 10;
+    /// Reject a one-shot `deposit`/`redeem` that would swap more weighted
+    /// legs than `max_swaps_per_tx` allows (0 disables the cap)
+    pub fn check_swap_count(&self) -> Result<()> {
+        let swaps = self.tokens.iter().filter(|t| t.weight > 0).count();
+        if self.max_swaps_per_tx > 0 && swaps > self.max_swaps_per_tx as usize {
+            msg!(
+                "{} swaps exceed the limit of {}; use deposit_leg/redeem_leg instead",
+                swaps,
+                self.max_swaps_per_tx
+            );
+            return err!(BasketError::TooManySwapsForTx);
+        }
+        Ok(())
+    }
 
-    pub fn initialize(ctx: Context<Initialize>, max_tokens: u8) -> Result<()> {
-        require!(
-            max_tokens as usize <= MAX_TOKENS,
-            BasketError::TooManyTokens
-        );
+    /// Neither deposits nor redemptions are accepted
+    pub fn is_halted(&self) -> bool {
+        self.deposits_paused && self.redemptions_paused
+    }
 
-        let basket = &mut ctx.accounts.basket;
-        basket.authority = ctx.accounts.authority.key();
-        basket.tokens = vec![];
-        basket.total_supply = 0;
-        basket.bump = *ctx.bumps.get("basket").unwrap();
-        basket.max_tokens = max_tokens;
-        basket.paused = false;
-        basket.reentrancy_guard = false;
+    /// Record why the basket is paused; the reason is dropped once neither
+    /// deposits nor redemptions are
+    pub fn set_pause_reason(&mut self, reason: Option<String>) -> Result<()> {
+        if !self.deposits_paused && !self.redemptions_paused {
+            self.pause_reason.clear();
+            return Ok(());
+        }
+        if let Some(reason) = reason {
+            require!(
+                reason.len() <= Self::MAX_PAUSE_REASON_LENGTH,
+                BasketError::PauseReasonTooLong
+            );
+            self.pause_reason = reason;
+        }
         Ok(())
     }
 
-    pub fn add_token(ctx: Context<AddToken>, token_mint: Pubkey, weight: u8) -> Result<()> {
-        let basket = &mut ctx.accounts.basket;
-        require!(!basket.paused, BasketError::ProgramPaused);
-        require!(
-            basket.authority == ctx.accounts.authority.key(),
-            BasketError::Unauthorized
-        );
+    /// Stamp a rebalance, rejecting it if the previous one was less than
+    /// `rebalance_cooldown_seconds` ago
+    pub fn start_rebalance(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
         require!(
-            basket.tokens.len() < basket.max_tokens as usize,
-            BasketError::TooManyTokens
+            now.saturating_sub(self.last_rebalance) as u64 >= self.rebalance_cooldown_seconds,
+            BasketError::RebalanceCooldown
         );
+        self.last_rebalance = now;
+        Ok(())
+    }
 
-        // Validate total weights
-        let total_weight: u8 = basket
-            .tokens
-            .iter()
-            .map(|t| t.weight)
-            .sum::<u8>()
-            .checked_add(weight)
-            .ok_or(BasketError::WeightOverflow)?;
-        require!(total_weight <= 100, BasketError::WeightOverflow);
+    /// Fraction of every holding `shares` redeem, scaled by `MAGNIFIER`
+    pub fn redemption_ratio(&self, shares: u64) -> Result<u128> {
+        (shares as u128)
+            .checked_mul(MAGNIFIER)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(self.total_supply as u128)
+            .ok_or_else(|| error!(BasketError::MathOverflow))
+    }
 
-        // Check for duplicate token
+    /// Shares worth `value_added` lamports against `nav` lamports of existing
+    /// value; the first deposit bootstraps 1:1. More than `MAGNIFIER` shares
+    /// per lamport means the NAV is implausibly small and is rejected.
+    pub fn shares_for(&self, value_added: u64, nav: u64) -> Result<u64> {
+        let shares = if self.total_supply == 0 {
+            value_added
+        } else {
+            require!(nav > 0, BasketError::InvalidNav);
+            nav::to_u64(
+                (value_added as u128)
+                    .checked_mul(self.total_supply as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(nav as u128)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?
+        };
         require!(
-            !basket.tokens.iter().any(|t| t.mint == token_mint),
-            BasketError::DuplicateToken
+            shares as u128 <= (value_added as u128) * MAGNIFIER,
+            BasketError::MathOverflow
         );
+        // Dust against a high NAV would take the SOL and mint nothing
+        require!(shares > 0, BasketError::DepositTooSmallForShares);
+        Ok(shares)
+    }
 
-        basket.tokens.push(TokenInfo {
-            mint: token_mint,
-            weight,
-            token_account: Pubkey::default(),
-        });
-
-        Ok(())
+    /// Shares of a `shares` mint locked permanently in the basket: the first
+    /// deposit gives up `MINIMUM_LIQUIDITY` so supply can never be rounded
+    /// down to a handful of shares, later deposits lock nothing.
+    pub fn locked_liquidity(&self, shares: u64) -> Result<u64> {
+        if self.total_supply > 0 {
+            return Ok(0);
+        }
+        require!(shares > MINIMUM_LIQUIDITY, BasketError::InsufficientDeposit);
+        Ok(MINIMUM_LIQUIDITY)
     }
 
-    pub fn remove_token(ctx: Context<RemoveToken>, token_mint: Pubkey) -> Result<()> {
-        let basket = &mut ctx.accounts.basket;
-        require!(!basket.paused, BasketError::ProgramPaused);
-        require!(
-            basket.authority == ctx.accounts.authority.key(),
-            BasketError::Unauthorized
-        );
+    /// Exit fee charged on `proceeds` lamports of redemption
+    pub fn exit_fee(&self, proceeds: u64) -> Result<u64> {
+        nav::to_u64(
+            (proceeds as u128)
+                .checked_mul(self.exit_fee_bps as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(BPS_DENOMINATOR as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )
+    }
 
-        let token_index = basket
-            .tokens
-            .iter()
-            .position(|t| t.mint == token_mint)
-            .ok_or(BasketError::TokenNotFound)?;
+    /// Target weight of each token, ordered like `tokens`
+    pub fn weights(&self) -> Vec<u8> {
+        self.tokens.iter().map(|t| t.weight).collect()
+    }
 
-        basket.tokens.remove(token_index);
+    /// Every token has its basket-owned account registered
+    pub fn tokens_initialized(&self) -> bool {
+        self.tokens.iter().all(|t| t.token_account != Pubkey::default())
+    }
 
+    /// Fresh state for a new basket with every optional setting off
+    pub fn reset(&mut self, authority: Pubkey, bump: u8, basket_id: u64, max_tokens: u8) -> Result<()> {
+        self.authority = authority;
+        self.tokens = vec![];
+        self.total_supply = 0;
+        self.bump = bump;
+        self.max_tokens = max_tokens;
+        self.deposits_paused = false;
+        self.redemptions_paused = false;
+        self.reentrancy_guard = false;
+        self.pending_authority = None;
+        self.basket_id = basket_id;
+        self.entry_fee_bps = 0;
+        self.exit_fee_bps = 0;
+        self.fee_recipient = authority;
+        self.max_deposit_per_user = 0;
+        self.max_tvl_lamports = 0;
+        self.input_mints = vec![];
+        self.min_weight = 0;
+        self.minimum_deposit = MINIMUM_DEPOSIT;
+        self.max_slippage_bps = BPS_DENOMINATOR as u16;
+        self.management_fee_bps = 0;
+        self.last_fee_collection = Clock::get()?.unix_timestamp;
+        self.timelock_seconds = 0;
+        self.pending_action = None;
+        self.performance_fee_bps = 0;
+        self.high_water_mark = MAGNIFIER;
+        self.auto_pause_threshold_bps = 0;
+        self.max_price_age_seconds = DEFAULT_MAX_PRICE_AGE_SECONDS;
+        self.guardian = authority;
+        self.pending_deposit_lamports = 0;
+        self.min_swap_lamports = 0;
+        self.roll_small_legs = false;
+        self.initialized = false;
+        self.rebalance_cooldown_seconds = 0;
+        self.last_rebalance = 0;
+        self.min_hold_seconds = 0;
+        self.pending_redeem_lamports = 0;
+        self.allowed_swap_programs = vec![jupiter::JUPITER_V6_ID];
+        self.version = Self::CURRENT_VERSION;
+        self.pause_reason = String::new();
+        self.swap_discriminator = jupiter::ROUTE_DISCRIMINATOR;
+        self.max_swaps_per_tx = 0;
         Ok(())
     }
 
-    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
-        let basket = &mut ctx.accounts.basket;
-        require!(
-            basket.authority == ctx.accounts.authority.key(),
-            BasketError::Unauthorized
+    /// Read a basket written under any earlier layout. Versions only append
+    /// fields, so missing trailing bytes read as zeros.
+    pub fn read_any_version(data: &[u8]) -> Result<Self> {
+        let mut padded = data.to_vec();
+        padded.resize(
+            data.len()
+                + Self::ALLOWED_SWAP_PROGRAMS_SIZE
+                + Self::VERSION_SIZE
+                + Self::PAUSE_REASON_SIZE
+                + Self::SWAP_DISCRIMINATOR_SIZE
+                + Self::MAX_SWAPS_PER_TX_SIZE,
+            0,
         );
-        basket.paused = paused;
-        Ok(())
+        Self::try_deserialize(&mut &padded[..])
     }
 
-    pub fn deposit(
-        ctx: Context<Deposit>,
-        amount: u64,
-        jupiter_quote: [u8; 32],
-        slippage_bps: u16,
-        minimum_token_amounts: Vec<u64>,
-    ) -> Result<()> {
-        let basket = &mut ctx.accounts.basket;
-        require!(!basket.paused, BasketError::ProgramPaused);
-        require!(!basket.reentrancy_guard, BasketError::ReentrancyDetected);
-        require!(amount >= MINIMUM_DEPOSIT, BasketError::InsufficientDeposit);
+    /// Bring an older basket up to `CURRENT_VERSION`, giving each field the
+    /// versions in between added the default `reset` would
+    pub fn upgrade(&mut self) -> Result<()> {
         require!(
-            minimum_token_amounts.len() == basket.tokens.len(),
-            BasketError::InvalidTokenCount
+            self.version < Self::CURRENT_VERSION,
+            BasketError::AlreadyMigrated
         );
+        if self.version < 2 {
+            self.allowed_swap_programs = vec![jupiter::JUPITER_V6_ID];
+        }
+        if self.version < 3 {
+            self.pause_reason = String::new();
+        }
+        if self.version < 4 {
+            self.swap_discriminator = jupiter::ROUTE_DISCRIMINATOR;
+        }
+        if self.version < 5 {
+            self.max_swaps_per_tx = 0;
+        }
+        self.version = Self::CURRENT_VERSION;
+        Ok(())
+    }
+}
 
-        // Set reentrancy guard
-        basket.reentrancy_guard = true;
+#[account]
+#[derive(Default)]
+pub struct UserDeposit {
+    pub user: Pubkey,
+    pub total_deposited: u64, // Lamports deposited over the account's lifetime
+    pub last_nonce: u64, // Highest deposit nonce accepted so far
+    pub last_deposit_ts: i64, // Unix timestamp of the user's latest deposit
+}
 
-        // Validate remaining accounts count
-        let remaining_account_count = ctx.remaining_accounts.len();
-        require!(
-            remaining_account_count == basket.tokens.len() * 12,
-            BasketError::InvalidAccountCount
-        );
+impl UserDeposit {
+    pub const DISCRIMINATOR_SIZE: usize = 8;
+    pub const USER_SIZE: usize = 32;
+    pub const TOTAL_DEPOSITED_SIZE: usize = 8;
+    pub const LAST_NONCE_SIZE: usize = 8;
+    pub const LAST_DEPOSIT_TS_SIZE: usize = 8;
 
-        // Transfer SOL from user to basket first
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            &ctx.accounts.user.key(),
-            &basket.key(),
-            amount,
+    pub fn required_space() -> usize {
+        Self::DISCRIMINATOR_SIZE
+            + Self::USER_SIZE
+            + Self::TOTAL_DEPOSITED_SIZE
+            + Self::LAST_NONCE_SIZE
+            + Self::LAST_DEPOSIT_TS_SIZE
+    }
+
+    /// Rejects a redeem while the user's latest deposit is younger than
+    /// `min_hold_seconds`. Users without a deposit record aren't held.
+    pub fn check_hold_period(user_deposit_info: &AccountInfo, min_hold_seconds: u64) -> Result<()> {
+        if min_hold_seconds == 0
+            || user_deposit_info.owner != &crate::ID
+            || user_deposit_info.data_is_empty()
+        {
+            return Ok(());
+        }
+        let user_deposit = Self::try_deserialize(&mut &user_deposit_info.try_borrow_data()?[..])?;
+        let held = Clock::get()?
+            .unix_timestamp
+            .saturating_sub(user_deposit.last_deposit_ts);
+        require!(
+            held >= 0 && held as u64 >= min_hold_seconds,
+            BasketError::HoldPeriodNotMet
         );
+        Ok(())
+    }
+}
 
-        anchor_lang::solana_program::program::invoke(
-            &transfer_ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                basket.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
+/// A deposit split across transactions: `begin_deposit` moves the SOL in,
+/// `deposit_leg` buys one token at a time and `finalize_deposit` mints shares
+/// once every leg has run. Until then the SOL sits in the basket outside NAV.
+#[account]
+#[derive(Default)]
+pub struct PendingDeposit {
+    pub user: Pubkey,
+    pub basket: Pubkey,
+    pub sol_in: u64,         // Lamports the user paid, before the entry fee
+    pub amount: u64,         // Lamports held by the basket for the legs
+    pub amount_spent: u64,   // Lamports the completed legs have swapped
+    pub value_added: u64,    // Lamport value the completed legs bought
+    pub legs_completed: u32, // Bit i set once leg i has swapped
+    pub bump: u8,
+}
 
-        // Update state before external calls
-        basket.total_supply = basket
-            .total_supply
-            .checked_add(amount)
-            .ok_or(BasketError::MathOverflow)?;
+impl PendingDeposit {
+    pub const DISCRIMINATOR_SIZE: usize = 8;
+    pub const USER_SIZE: usize = 32;
+    pub const BASKET_SIZE: usize = 32;
+    pub const SOL_IN_SIZE: usize = 8;
+    pub const AMOUNT_SIZE: usize = 8;
+    pub const AMOUNT_SPENT_SIZE: usize = 8;
+    pub const VALUE_ADDED_SIZE: usize = 8;
+    pub const LEGS_COMPLETED_SIZE: usize = 4;
+    pub const BUMP_SIZE: usize = 1;
 
-        // Mint basket tokens to user
-        let seeds = &[b"basket".as_ref(), &[basket.bump]];
-        let cpi_accounts = token::MintTo {
-            mint: ctx.accounts.basket_mint.to_account_info(),
-            to: ctx.accounts.user_basket_token.to_account_info(),
-            authority: basket.to_account_info(),
-        };
+    pub fn required_space() -> usize {
+        Self::DISCRIMINATOR_SIZE
+            + Self::USER_SIZE
+            + Self::BASKET_SIZE
+            + Self::SOL_IN_SIZE
+            + Self::AMOUNT_SIZE
+            + Self::AMOUNT_SPENT_SIZE
+            + Self::VALUE_ADDED_SIZE
+            + Self::LEGS_COMPLETED_SIZE
+            + Self::BUMP_SIZE
+    }
 
-        token::mint_to(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                cpi_accounts,
-                &[seeds],
-            ),
-            amount,
-        )?;
+    /// Whether every leg with a non-zero weight has run
+    pub fn all_legs_completed(&self, weights: &[u8]) -> bool {
+        let required = weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0u64, |mask, (i, _)| mask | 1 << i);
+        self.legs_completed as u64 == required
+    }
+}
 
-        // Execute Jupiter swaps
-        let mut initial_lamports = basket.to_account_info().lamports();
+/// A redemption split across transactions: `begin_redeem` burns the shares
+/// and reserves the user's slice of each holding, `redeem_leg` sells one
+/// slice at a time and `finalize_redeem` pays out once every leg has run.
+/// Reserved tokens and proceeds sit outside NAV until then.
+#[account]
+#[derive(Default)]
+pub struct PendingRedeem {
+    pub user: Pubkey,
+    pub basket: Pubkey,
+    pub shares: u64,              // Shares burned by `begin_redeem`
+    pub legs: Vec<(Pubkey, u64)>, // Mint and reserved amount of each leg
+    pub sol_out: u64,             // Idle SOL share plus proceeds of completed legs
+    pub legs_completed: u32,      // Bit i set once leg i has sold
+    pub bump: u8,
+}
 
-        for (i, token_info) in basket.tokens.iter().enumerate() {
-            // Get token account from remaining accounts
-            let token_account = &ctx.remaining_accounts[i * 12 + 1];
-            let token_acc_data = Account::<TokenAccount>::try_from(&token_account)?;
-            require!(
-                token_acc_data.mint == token_info.mint,
-                BasketError::InvalidTokenMint
-            );
-            require!(
-                token_acc_data.owner == basket.key(),
-                BasketError::InvalidTokenOwner
-            );
+impl PendingRedeem {
+    pub const DISCRIMINATOR_SIZE: usize = 8;
+    pub const USER_SIZE: usize = 32;
+    pub const BASKET_SIZE: usize = 32;
+    pub const SHARES_SIZE: usize = 8;
+    pub const LEG_SIZE: usize = 32 + 8; // Mint + amount
+    pub const SOL_OUT_SIZE: usize = 8;
+    pub const LEGS_COMPLETED_SIZE: usize = 4;
+    pub const BUMP_SIZE: usize = 1;
 
-            let token_amount = token_acc_data.amount;
+    pub fn required_space(token_count: usize) -> usize {
+        Self::DISCRIMINATOR_SIZE
+            + Self::USER_SIZE
+            + Self::BASKET_SIZE
+            + Self::SHARES_SIZE
+            + 4 + Self::LEG_SIZE * token_count // Vec length + legs
+            + Self::SOL_OUT_SIZE
+            + Self::LEGS_COMPLETED_SIZE
+            + Self::BUMP_SIZE
+    }
 
-            // Create Jupiter swap instruction
-            let swap_ix = Instruction {
-                program_id: jupiter::JUPITER_V6_ID,
-                accounts: ctx.remaining_accounts[i * 12..(i + 1) * 12]
-                    .iter()
-                    .map(|acc| AccountMeta {
-                        pubkey: *acc.key,
-                        is_signer: acc.is_signer,
-                        is_writable: acc.is_writable,
-                    })
-                    .collect(),
-                data: AnchorSerialize::try_to_vec(&(
-                    4u8,
-                    jupiter::RouteSwapParams {
-                        in_amount: token_amount,
-                        quote_id: jupiter_quote,
-                        slippage_bps,
-                    },
-                ))
-                .unwrap(),
-            };
+    /// Whether every leg has sold
+    pub fn all_legs_completed(&self) -> bool {
+        let all = (1u64 << self.legs.len()) - 1;
+        self.legs_completed as u64 == all
+    }
+}
 
-            // Execute swap
-            anchor_lang::solana_program::program::invoke(
-                &swap_ix,
-                &ctx.remaining_accounts[i * 12..(i + 1) * 12],
-            )?;
+/// Optional per-basket list of mints the authority may add. Once created,
+/// `add_token` and `add_tokens` only accept listed mints.
+#[account]
+#[derive(Default)]
+pub struct TokenAllowlist {
+    pub basket: Pubkey,
+    pub allowlist_authority: Pubkey,
+    pub mints: Vec<Pubkey>,
+    pub bump: u8,
+}
 
-            // Verify minimum received based on lamports difference
-            let current_lamports = basket.to_account_info().lamports();
-            let lamports_spent = current_lamports
-                .checked_sub(initial_lamports)
-                .ok_or(BasketError::MathOverflow)?;
+impl TokenAllowlist {
+    pub const DISCRIMINATOR_SIZE: usize = 8;
+    pub const BASKET_SIZE: usize = 32;
+    pub const ALLOWLIST_AUTHORITY_SIZE: usize = 32;
+    pub const MAX_MINTS: usize = 32;
+    pub const MINTS_SIZE: usize = 4 + 32 * Self::MAX_MINTS; // Vec length + Pubkeys
+    pub const BUMP_SIZE: usize = 1;
 
-            require!(
-                lamports_spent >= minimum_token_amounts[i],
-                BasketError::SlippageExceeded
-            );
+    pub fn required_space() -> usize {
+        Self::DISCRIMINATOR_SIZE
+            + Self::BASKET_SIZE
+            + Self::ALLOWLIST_AUTHORITY_SIZE
+            + Self::MINTS_SIZE
+            + Self::BUMP_SIZE
+    }
 
-            initial_lamports = current_lamports;
+    /// Rejects `mint` if `allowlist_info` holds an initialized allowlist that
+    /// doesn't list it. An uncreated allowlist permits every mint.
+    pub fn check_mint(allowlist_info: &AccountInfo, mint: &Pubkey) -> Result<()> {
+        if allowlist_info.owner != &crate::ID || allowlist_info.data_is_empty() {
+            return Ok(());
         }
-
-        // Clear reentrancy guard
-        basket.reentrancy_guard = false;
-
+        let allowlist = Self::try_deserialize(&mut &allowlist_info.try_borrow_data()?[..])?;
+        require!(allowlist.mints.contains(mint), BasketError::MintNotAllowed);
         Ok(())
     }
+}
 
-    pub fn redeem(
-        ctx: Context<Redeem>,
-        amount: u64,
-        jupiter_quote: [u8; 32],
-        slippage_bps: u16,
-        minimum_sol_amount: u64,
-    ) -> Result<()> {
-        let basket = &mut ctx.accounts.basket;
-        require!(!basket.paused, BasketError::ProgramPaused);
-        require!(!basket.reentrancy_guard, BasketError::ReentrancyDetected);
+/// Program-wide index of basket ids, so clients can enumerate baskets
+#[account]
+#[derive(Default)]
+pub struct BasketRegistry {
+    pub basket_ids: Vec<u64>,
+    pub bump: u8,
+}
 
-        // Set reentrancy guard
-        basket.reentrancy_guard = true;
+impl BasketRegistry {
+    pub const DISCRIMINATOR_SIZE: usize = 8;
+    pub const MAX_BASKETS: usize = 256;
+    pub const BASKET_IDS_SIZE: usize = 4 + 8 * Self::MAX_BASKETS; // Vec length + ids
+    pub const BUMP_SIZE: usize = 1;
+
+    pub fn required_space() -> usize {
+        Self::DISCRIMINATOR_SIZE + Self::BASKET_IDS_SIZE + Self::BUMP_SIZE
+    }
 
-        // Validate remaining accounts count
-        let remaining_account_count = ctx.remaining_accounts.len();
+    pub fn register(&mut self, basket_id: u64, bump: u8) -> Result<()> {
         require!(
-            remaining_account_count == basket.tokens.len() * 12,
-            BasketError::InvalidAccountCount
+            self.basket_ids.len() < Self::MAX_BASKETS,
+            BasketError::RegistryFull
         );
+        self.bump = bump;
+        self.basket_ids.push(basket_id);
+        Ok(())
+    }
 
-        // Calculate redemption ratio with higher precision
-        let redemption_ratio = (amount as u128)
-            .checked_mul(MAGNIFIER)
-            .ok_or(BasketError::MathOverflow)?
-            .checked_div(basket.total_supply as u128)
-            .ok_or(BasketError::MathOverflow)?;
+    pub fn unregister(&mut self, basket_id: u64) {
+        self.basket_ids.retain(|id| *id != basket_id);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct TokenInfo {
+    pub mint: Pubkey,
+    pub weight: u8,            // Percentage weight in basket
+    pub token_account: Pubkey, // Associated token account owned by basket
+    pub price_feed: Pubkey,    // Oracle account quoting lamports per base unit
+    pub price_source: PriceSource, // Which oracle program `price_feed` belongs to
+    pub reserved_amount: u64,      // Held for pending split redemptions, outside NAV
+}
+
+impl TokenInfo {
+    /// Part of `balance` not reserved for pending split redemptions
+    pub fn available(&self, balance: u64) -> u64 {
+        balance.saturating_sub(self.reserved_amount)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PriceSource {
+    #[default]
+    Pyth,
+    Switchboard,
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum TimelockedAction {
+    WithdrawAuthoritySol { amount: u64 },
+    SetPauseState { deposits: bool, redemptions: bool },
+    UpdateSwapProgram { program_id: Pubkey, allowed: bool },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct PendingAction {
+    pub action: TimelockedAction,
+    pub execute_after: i64, // Unix timestamp
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct PricePerShare {
+    pub price_per_share: u128, // Lamports per share, scaled by MAGNIFIER
+    pub nav: u64,
+    pub total_supply: u64,
+    pub token_balances: Vec<u64>, // Ordered like `basket.tokens`
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct BasketValue {
+    pub nav: u64,
+    pub idle_lamports: u64,               // SOL above the rent-exempt reserve
+    pub token_values: Vec<(Pubkey, u64)>, // Lamport value per mint, ordered like `basket.tokens`
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct RedeemPreview {
+    pub shares: u64,
+    pub gross_sol: u64, // Idle SOL share plus oracle value of the legs sold
+    pub fee: u64,
+    pub net_sol: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct UserPosition {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub total_supply: u64,
+    pub token_amounts: Vec<u64>, // User's claim on each token, ordered like `basket.tokens`
+    pub value_lamports: u64,     // User's share of NAV
+}
+
+/// Lamports a deposit can still add before hitting each cap; `u64::MAX`
+/// where the cap is off
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct DepositCapacity {
+    pub tvl_remaining: u64,
+    pub user_remaining: u64,
+}
+
+/// A single token's config alongside its token account's current balance
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TokenLookup {
+    pub info: TokenInfo,
+    pub balance: u64,
+}
+
+/// One token's slice of the `deposit`/`redeem` remaining accounts
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct SwapLeg {
+    pub mint: Pubkey,
+    pub token_account: Pubkey, // Goes in the leg's balance slot
+    pub price_feed: Pubkey,    // Goes in the trailing price accounts
+    pub stride: u8,            // Accounts in the leg's Jupiter route; 0 for zero-weight legs
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SwapAccountLayout {
+    pub legs: Vec<SwapLeg>, // Ordered like `basket.tokens`
+    pub balance_slot: u8,   // Index of `token_account` within each leg
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BasketComposition {
+    pub tokens: Vec<TokenInfo>,
+    pub total_supply: u64,
+    pub deposits_paused: bool,
+    pub redemptions_paused: bool,
+}
+
+pub mod jupiter {
+    use anchor_lang::prelude::*;
+    use solana_program::instruction::{AccountMeta, Instruction};
+
+    use crate::BasketError;
+
+    pub static JUPITER_V6_ID: Pubkey =
+        solana_program::pubkey!("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4");
+
+    /// Accounts in a standard Jupiter route; callers may pass other strides
+    /// through `accounts_per_swap`
+    pub const DEFAULT_ACCOUNTS_PER_SWAP: u8 = 12;
+    /// Position of the basket's token account within each route
+    pub const BALANCE_SLOT: u8 = 1;
+    /// Jupiter's route instruction index; baskets can point at another
+    /// through `swap_discriminator`
+    pub const ROUTE_DISCRIMINATOR: u8 = 4;
+
+    #[derive(AnchorSerialize, AnchorDeserialize)]
+    pub struct RouteSwapParams {
+        pub in_amount: u64,
+        pub quote_id: [u8; 32],
+        pub slippage_bps: u16,
+    }
+
+    /// Checks that `remaining_accounts` holds one swap group per weighted token,
+    /// then the registered account of each zero-weight token, then one price
+    /// account per token, returning the number of swap accounts
+    pub fn swap_account_count(
+        remaining_accounts: &[AccountInfo],
+        weights: &[u8],
+        accounts_per_swap: &[u8],
+    ) -> Result<usize> {
+        let zero_weight_count = weights.iter().filter(|weight| **weight == 0).count();
+        require!(
+            accounts_per_swap.len() == weights.len() - zero_weight_count,
+            BasketError::InvalidTokenCount
+        );
+        let swap_account_count = accounts_per_swap
+            .iter()
+            .map(|count| *count as usize)
+            .sum::<usize>();
+        require!(
+            accounts_per_swap.iter().all(|count| *count >= 2)
+                && remaining_accounts.len()
+                    == swap_account_count + zero_weight_count + weights.len(),
+            BasketError::InvalidAccountCount
+        );
+        Ok(swap_account_count)
+    }
+
+    /// Token balance account of each token, ordered like `weights`: the second
+    /// slot of a weighted token's swap group, or a zero-weight token's own account
+    pub fn balance_accounts<'info>(
+        remaining_accounts: &[AccountInfo<'info>],
+        weights: &[u8],
+        accounts_per_swap: &[u8],
+    ) -> Result<Vec<AccountInfo<'info>>> {
+        let mut offset = 0;
+        let mut leg = 0;
+        let mut zero_weight_offset = accounts_per_swap
+            .iter()
+            .map(|count| *count as usize)
+            .sum::<usize>();
+        weights
+            .iter()
+            .map(|weight| {
+                let index = if *weight == 0 {
+                    zero_weight_offset += 1;
+                    zero_weight_offset - 1
+                } else {
+                    let count = *accounts_per_swap
+                        .get(leg)
+                        .ok_or(BasketError::InvalidTokenCount)?;
+                    let index = offset + BALANCE_SLOT as usize;
+                    offset += count as usize;
+                    leg += 1;
+                    index
+                };
+                remaining_accounts
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| error!(BasketError::InvalidAccountCount))
+            })
+            .collect()
+    }
+
+    /// The `count` route accounts starting at `offset`, erroring rather than
+    /// panicking when the caller passed fewer
+    pub fn route<'a, 'info>(
+        remaining_accounts: &'a [AccountInfo<'info>],
+        offset: usize,
+        count: u8,
+    ) -> Result<&'a [AccountInfo<'info>]> {
+        remaining_accounts
+            .get(offset..offset + count as usize)
+            .ok_or_else(|| error!(BasketError::InvalidAccountCount))
+    }
+
+    /// The basket's token account within a route
+    pub fn balance_account<'a, 'info>(
+        route: &'a [AccountInfo<'info>],
+    ) -> Result<&'a AccountInfo<'info>> {
+        route
+            .get(BALANCE_SLOT as usize)
+            .ok_or_else(|| error!(BasketError::InvalidAccountCount))
+    }
+
+    /// The trailing price account of each of `token_count` tokens
+    pub fn price_accounts<'a, 'info>(
+        remaining_accounts: &'a [AccountInfo<'info>],
+        token_count: usize,
+    ) -> Result<&'a [AccountInfo<'info>]> {
+        remaining_accounts
+            .len()
+            .checked_sub(token_count)
+            .and_then(|start| remaining_accounts.get(start..))
+            .ok_or_else(|| error!(BasketError::InvalidAccountCount))
+    }
+
+    /// Invoke a Jupiter route over `swap_accounts` on `swap_program`, which
+    /// callers check against the basket's allowlist. `pda_signer` marks the
+    /// basket PDA as signer and signs with its seeds when the route spends
+    /// tokens the basket owns.
+    pub fn swap<'info>(
+        swap_program: &Pubkey,
+        discriminator: u8,
+        swap_accounts: &[AccountInfo<'info>],
+        params: RouteSwapParams,
+        pda_signer: Option<(&Pubkey, &[&[u8]])>,
+    ) -> Result<()> {
+        let swap_ix = Instruction {
+            program_id: *swap_program,
+            accounts: swap_accounts
+                .iter()
+                .map(|acc| AccountMeta {
+                    pubkey: *acc.key,
+                    is_signer: acc.is_signer
+                        || matches!(pda_signer, Some((signer, _)) if signer == acc.key),
+                    is_writable: acc.is_writable,
+                })
+                .collect(),
+            data: AnchorSerialize::try_to_vec(&(discriminator, params))?,
+        };
+
+        match pda_signer {
+            Some((_, seeds)) => anchor_lang::solana_program::program::invoke_signed(
+                &swap_ix,
+                swap_accounts,
+                &[seeds],
+            )?,
+            None => anchor_lang::solana_program::program::invoke(&swap_ix, swap_accounts)?,
+        }
+        Ok(())
+    }
+
+    /// Move `amount` of the basket's lamports into its wSOL account and sync
+    /// them into the token balance for a route to spend. The basket rides
+    /// along in the SyncNative accounts so the runtime sees both sides of
+    /// the direct lamport move.
+    pub fn wrap_lamports<'info>(
+        basket: &AccountInfo<'info>,
+        basket_wsol: &AccountInfo<'info>,
+        token_program: &AccountInfo<'info>,
+        amount: u64,
+    ) -> Result<()> {
+        **basket.try_borrow_mut_lamports()? -= amount;
+        **basket_wsol.try_borrow_mut_lamports()? += amount;
+        let mut sync_ix = anchor_spl::token_2022::spl_token_2022::instruction::sync_native(
+            token_program.key,
+            basket_wsol.key,
+        )?;
+        sync_ix.accounts.push(AccountMeta::new(*basket.key, false));
+        anchor_lang::solana_program::program::invoke(
+            &sync_ix,
+            &[basket_wsol.clone(), basket.clone(), token_program.clone()],
+        )?;
+        Ok(())
+    }
+}
+
+pub mod metaplex {
+    use anchor_lang::prelude::*;
+    use solana_program::instruction::{AccountMeta, Instruction};
+
+    use crate::BasketError;
+
+    pub static TOKEN_METADATA_ID: Pubkey =
+        solana_program::pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+    // Token Metadata string limits
+    pub const MAX_NAME_LENGTH: usize = 32;
+    pub const MAX_SYMBOL_LENGTH: usize = 10;
+    pub const MAX_URI_LENGTH: usize = 200;
+
+    const CREATE_METADATA_ACCOUNT_V3: u8 = 33;
+
+    pub fn validate(name: &str, symbol: &str, uri: &str) -> Result<()> {
+        require!(
+            name.len() <= MAX_NAME_LENGTH
+                && symbol.len() <= MAX_SYMBOL_LENGTH
+                && uri.len() <= MAX_URI_LENGTH,
+            BasketError::MetadataTooLong
+        );
+        Ok(())
+    }
+
+    /// Invoke CreateMetadataAccountV3 for `mint` with `authority` (the basket
+    /// PDA, signing with `seeds`) as both mint and update authority
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_metadata_account<'info>(
+        metadata: &AccountInfo<'info>,
+        mint: &AccountInfo<'info>,
+        authority: &AccountInfo<'info>,
+        payer: &AccountInfo<'info>,
+        system_program: &AccountInfo<'info>,
+        rent: &AccountInfo<'info>,
+        token_metadata_program: &AccountInfo<'info>,
+        name: String,
+        symbol: String,
+        uri: String,
+        seeds: &[&[u8]],
+    ) -> Result<()> {
+        // DataV2 followed by is_mutable and collection_details; creators,
+        // collection and uses are all None
+        let mut data = vec![CREATE_METADATA_ACCOUNT_V3];
+        data.extend(AnchorSerialize::try_to_vec(&(name, symbol, uri, 0u16))?);
+        data.extend([0u8, 0, 0]);
+        data.push(1); // is_mutable
+        data.push(0);
+
+        let ix = Instruction {
+            program_id: TOKEN_METADATA_ID,
+            accounts: vec![
+                AccountMeta::new(*metadata.key, false),
+                AccountMeta::new_readonly(*mint.key, false),
+                AccountMeta::new_readonly(*authority.key, true),
+                AccountMeta::new(*payer.key, true),
+                AccountMeta::new_readonly(*authority.key, true),
+                AccountMeta::new_readonly(*system_program.key, false),
+                AccountMeta::new_readonly(*rent.key, false),
+            ],
+            data,
+        };
+
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[
+                metadata.clone(),
+                mint.clone(),
+                authority.clone(),
+                payer.clone(),
+                system_program.clone(),
+                rent.clone(),
+                token_metadata_program.clone(),
+            ],
+            &[seeds],
+        )?;
+        Ok(())
+    }
+}
+
+/// Associated token accounts the handlers create themselves. Anchor 0.28's
+/// `associated_token` constraints only derive classic Token addresses, which
+/// accounts under Token-2022 never match.
+pub mod token_accounts {
+    use anchor_lang::prelude::*;
+    use anchor_spl::associated_token;
+    use anchor_spl::token_interface::TokenAccount;
+
+    use crate::BasketError;
+
+    /// Create `authority`'s associated account for `mint` when it doesn't
+    /// exist yet and check it still belongs to them
+    pub fn create_associated<'info>(
+        token_account: &AccountInfo<'info>,
+        authority: &AccountInfo<'info>,
+        mint: &AccountInfo<'info>,
+        payer: &AccountInfo<'info>,
+        system_program: &AccountInfo<'info>,
+        token_program: &AccountInfo<'info>,
+        associated_token_program: &AccountInfo<'info>,
+    ) -> Result<()> {
+        if token_account.data_is_empty() {
+            associated_token::create(CpiContext::new(
+                associated_token_program.clone(),
+                associated_token::Create {
+                    payer: payer.clone(),
+                    associated_token: token_account.clone(),
+                    authority: authority.clone(),
+                    mint: mint.clone(),
+                    system_program: system_program.clone(),
+                    token_program: token_program.clone(),
+                },
+            ))?;
+        }
+        let account = InterfaceAccount::<TokenAccount>::try_from(token_account)?;
+        require_keys_eq!(account.owner, authority.key(), BasketError::InvalidTokenOwner);
+        Ok(())
+    }
+}
+
+/// Token-2022 transfer hook keeping basket shares from changing hands outside
+/// deposits and redeems. The bundled spl-token-2022 predates the TransferHook
+/// extension, so its instructions are built by hand.
+pub mod transfer_hook {
+    use anchor_lang::prelude::*;
+    use anchor_spl::token_2022::spl_token_2022::{
+        extension::StateWithExtensions,
+        state::{Account as TokenAccountState, Mint as MintState},
+    };
+    use solana_program::instruction::{AccountMeta, Instruction};
+    use solana_program::program_pack::Pack;
+
+    use crate::BasketError;
+
+    /// Seed of the ExtraAccountMetaList PDA Token-2022 looks up under the hook
+    /// program on every transfer
+    pub const EXTRA_ACCOUNT_METAS_SEED: &[u8] = b"extra-account-metas";
+
+    /// sha256("spl-transfer-hook-interface:execute")[..8], prefixing both the
+    /// Execute instruction and its ExtraAccountMetaList entry
+    pub const EXECUTE_DISCRIMINATOR: [u8; 8] = [105, 37, 101, 197, 75, 251, 102, 26];
+
+    /// Base mint padded to account length, the account type byte, then the
+    /// TransferHook entry: type, length, authority and program id
+    pub const MINT_LEN: usize = TokenAccountState::LEN + 1 + 2 + 2 + 64;
+
+    /// ExtraAccountMetaList entry for Execute listing no extra accounts
+    pub const EXTRA_ACCOUNT_METAS_LEN: usize = 8 + 4 + 4;
+
+    const TRANSFER_HOOK_EXTENSION: u8 = 36;
+    const INITIALIZE: u8 = 0;
+    const UPDATE: u8 = 1;
+    const TRANSFER_HOOK_EXTENSION_TYPE: u16 = 14;
+
+    /// Whether `data` is a mint created with the TransferHook extension as its
+    /// only extension, as `initialize_mint` does for Token-2022
+    pub fn has_extension(data: &[u8]) -> bool {
+        let tlv_start = TokenAccountState::LEN + 1;
+        data.len() == MINT_LEN
+            && data[tlv_start..tlv_start + 2] == TRANSFER_HOOK_EXTENSION_TYPE.to_le_bytes()
+    }
+
+    /// Invoke TransferHook Initialize on the not yet initialized `mint`, with
+    /// `authority` allowed to set a hook program later and none set for now
+    pub fn initialize<'info>(
+        token_program: &AccountInfo<'info>,
+        mint: &AccountInfo<'info>,
+        authority: &Pubkey,
+    ) -> Result<()> {
+        let mut data = vec![TRANSFER_HOOK_EXTENSION, INITIALIZE];
+        data.extend(authority.as_ref());
+        data.extend([0u8; 32]);
+
+        let ix = Instruction {
+            program_id: *token_program.key,
+            accounts: vec![AccountMeta::new(*mint.key, false)],
+            data,
+        };
+
+        anchor_lang::solana_program::program::invoke(
+            &ix,
+            &[mint.clone(), token_program.clone()],
+        )?;
+        Ok(())
+    }
+
+    /// Invoke TransferHook Update pointing `mint` at `program_id`, with
+    /// `authority` (the basket PDA, signing with `seeds`) as hook authority
+    pub fn update<'info>(
+        token_program: &AccountInfo<'info>,
+        mint: &AccountInfo<'info>,
+        authority: &AccountInfo<'info>,
+        program_id: &Pubkey,
+        seeds: &[&[u8]],
+    ) -> Result<()> {
+        let mut data = vec![TRANSFER_HOOK_EXTENSION, UPDATE];
+        data.extend(program_id.as_ref());
+
+        let ix = Instruction {
+            program_id: *token_program.key,
+            accounts: vec![
+                AccountMeta::new(*mint.key, false),
+                AccountMeta::new_readonly(*authority.key, true),
+            ],
+            data,
+        };
+
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[mint.clone(), authority.clone(), token_program.clone()],
+            &[seeds],
+        )?;
+        Ok(())
+    }
+
+    /// Write an ExtraAccountMetaList asking for nothing beyond the accounts
+    /// every Execute already gets
+    pub fn write_extra_account_metas(data: &mut [u8]) {
+        data[..8].copy_from_slice(&EXECUTE_DISCRIMINATOR);
+        data[8..12].copy_from_slice(&4u32.to_le_bytes());
+        data[12..16].copy_from_slice(&0u32.to_le_bytes());
+    }
+
+    /// Execute body: allow the transfer only when the source or destination
+    /// token account belongs to the mint authority, i.e. the basket PDA.
+    /// Deposits mint and redeems burn, so neither reaches the hook.
+    pub fn execute(accounts: &[AccountInfo]) -> Result<()> {
+        let [source, mint, destination, ..] = accounts else {
+            return err!(ErrorCode::AccountNotEnoughKeys);
+        };
+
+        let mint_authority: Option<Pubkey> =
+            StateWithExtensions::<MintState>::unpack(&mint.try_borrow_data()?)?
+                .base
+                .mint_authority
+                .into();
+        let basket = mint_authority.ok_or(BasketError::TransferRestricted)?;
+
+        let owner = |account: &AccountInfo| -> Result<Pubkey> {
+            let data = account.try_borrow_data()?;
+            let state = StateWithExtensions::<TokenAccountState>::unpack(&data)?.base;
+            require_keys_eq!(state.mint, mint.key(), BasketError::InvalidTokenAccount);
+            Ok(state.owner)
+        };
+        require!(
+            owner(source)? == basket || owner(destination)? == basket,
+            BasketError::TransferRestricted
+        );
+        Ok(())
+    }
+}
+
+/// Holds `reentrancy_guard` set for as long as it lives and clears it on drop,
+/// so early `?` returns can't leave the basket locked.
+pub struct ReentrancyGuard<'a, 'info> {
+    basket: &'a mut Account<'info, BasketState>,
+}
+
+impl<'a, 'info> ReentrancyGuard<'a, 'info> {
+    pub fn acquire(basket: &'a mut Account<'info, BasketState>) -> Result<Self> {
+        require!(!basket.reentrancy_guard, BasketError::ReentrancyDetected);
+        basket.reentrancy_guard = true;
+        Ok(Self { basket })
+    }
+}
+
+impl<'a, 'info> Deref for ReentrancyGuard<'a, 'info> {
+    type Target = Account<'info, BasketState>;
+
+    fn deref(&self) -> &Self::Target {
+        self.basket
+    }
+}
+
+impl<'a, 'info> DerefMut for ReentrancyGuard<'a, 'info> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.basket
+    }
+}
+
+impl<'a, 'info> Drop for ReentrancyGuard<'a, 'info> {
+    fn drop(&mut self) {
+        self.basket.reentrancy_guard = false;
+    }
+}
+
+#[program]
+pub mod basket_token {
+    use super::*;
+
+    // 1e18 keeps ratios of tiny redemptions against large supplies nonzero;
+    // u64 amounts times a ratio of at most 1e18 stay well inside u128
+    pub const MAGNIFIER: u128 = 1_000_000_000_000_000_000;
+    pub const MINIMUM_DEPOSIT: u64 = 10_000_000; // Default per-basket floor
+    pub const MINIMUM_LIQUIDITY: u64 = 1_000; // Shares the first deposit locks in the basket
+    pub const MAX_TOKENS: usize = 10;
+    pub const MAX_RESIZED_TOKENS: usize = 32; // Hard cap for resize_basket
+    pub const MAX_DECIMALS: u8 = 9;
+    pub const BPS_DENOMINATOR: u64 = 10_000;
+    pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+    pub const DEFAULT_MAX_PRICE_AGE_SECONDS: u64 = 60;
+
+    // `check_health` status bits; each set bit is a failing invariant
+    pub const HEALTH_WEIGHTS_INCOMPLETE: u8 = 1 << 0;
+    pub const HEALTH_SUPPLY_MISMATCH: u8 = 1 << 1;
+    pub const HEALTH_REENTRANCY_GUARD_SET: u8 = 1 << 2;
+    pub const HEALTH_TOKEN_ACCOUNT_MISSING: u8 = 1 << 3;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        basket_id: u64,
+        max_tokens: u8,
+        entry_fee_bps: u16,
+        exit_fee_bps: u16,
+        fee_recipient: Pubkey,
+        max_deposit_per_user: u64,
+        max_tvl_lamports: u64,
+        min_weight: u8,
+        minimum_deposit: u64,
+        management_fee_bps: u16,
+        timelock_seconds: u64,
+        decimals: u8,
+        performance_fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            max_tokens as usize <= MAX_TOKENS,
+            BasketError::TooManyTokens
+        );
+        require!(decimals <= MAX_DECIMALS, BasketError::InvalidDecimals);
+        require!(
+            entry_fee_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidFee
+        );
+        require!(
+            exit_fee_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidFee
+        );
+        require!(
+            management_fee_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidFee
+        );
+        require!(
+            performance_fee_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidFee
+        );
+
+        let basket = &mut ctx.accounts.basket;
+        basket.reset(
+            ctx.accounts.authority.key(),
+            *ctx.bumps.get("basket").unwrap(),
+            basket_id,
+            max_tokens,
+        )?;
+        basket.entry_fee_bps = entry_fee_bps;
+        basket.exit_fee_bps = exit_fee_bps;
+        basket.fee_recipient = fee_recipient;
+        basket.max_deposit_per_user = max_deposit_per_user;
+        basket.max_tvl_lamports = max_tvl_lamports;
+        basket.min_weight = min_weight;
+        if minimum_deposit > 0 {
+            basket.minimum_deposit = minimum_deposit;
+        }
+        basket.management_fee_bps = management_fee_bps;
+        basket.timelock_seconds = timelock_seconds;
+        basket.performance_fee_bps = performance_fee_bps;
+        basket.initialized = true;
+
+        ctx.accounts
+            .registry
+            .register(basket_id, *ctx.bumps.get("registry").unwrap())
+    }
+
+    /// First half of a two-step setup: create the basket state with default
+    /// settings. Deposits stay closed until `initialize_mint` runs.
+    pub fn initialize_state(
+        ctx: Context<InitializeState>,
+        basket_id: u64,
+        max_tokens: u8,
+    ) -> Result<()> {
+        require!(
+            max_tokens as usize <= MAX_TOKENS,
+            BasketError::TooManyTokens
+        );
+        ctx.accounts.basket.reset(
+            ctx.accounts.authority.key(),
+            *ctx.bumps.get("basket").unwrap(),
+            basket_id,
+            max_tokens,
+        )?;
+        ctx.accounts
+            .registry
+            .register(basket_id, *ctx.bumps.get("registry").unwrap())
+    }
+
+    /// Second half of a two-step setup: create the basket mint with
+    /// `decimals` and open the basket for deposits
+    pub fn initialize_mint(ctx: Context<InitializeMint>, decimals: u8) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(!basket.initialized, BasketError::AlreadyInitialized);
+        require!(decimals <= MAX_DECIMALS, BasketError::InvalidDecimals);
+
+        // Token-2022 mints get the TransferHook extension with no hook set, so
+        // `enable_transfer_restriction` can switch one on later
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let basket_mint = ctx.accounts.basket_mint.to_account_info();
+        let with_hook = token_program.key() == token_interface::ID;
+        let space = if with_hook {
+            transfer_hook::MINT_LEN
+        } else {
+            anchor_spl::token::Mint::LEN
+        };
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let mint_bump = [*ctx.bumps.get("basket_mint").unwrap()];
+        let mint_seeds = &[b"basket_mint".as_ref(), basket_id.as_ref(), &mint_bump];
+        anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::CreateAccount {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: basket_mint.clone(),
+                },
+                &[mint_seeds],
+            ),
+            Rent::get()?.minimum_balance(space),
+            space as u64,
+            token_program.key,
+        )?;
+
+        if with_hook {
+            transfer_hook::initialize(&token_program, &basket_mint, &basket.key())?;
+        }
+        token_interface::initialize_mint2(
+            CpiContext::new(
+                token_program,
+                token_interface::InitializeMint2 { mint: basket_mint },
+            ),
+            decimals,
+            &basket.key(),
+            None,
+        )?;
+
+        basket.initialized = true;
+        Ok(())
+    }
+
+    /// Close an empty basket, returning its rent to the authority and
+    /// dropping its id from the registry
+    pub fn close_basket(ctx: Context<CloseBasket>) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            basket.total_supply == 0
+                && basket.tokens.is_empty()
+                && basket.pending_deposit_lamports == 0
+                && basket.pending_redeem_lamports == 0,
+            BasketError::BasketNotEmpty
+        );
+        ctx.accounts.registry.unregister(basket.basket_id);
+        Ok(())
+    }
+
+    pub fn get_registry(ctx: Context<GetRegistry>) -> Result<Vec<u64>> {
+        Ok(ctx.accounts.registry.basket_ids.clone())
+    }
+
+    pub fn add_token(ctx: Context<AddToken>, token_mint: Pubkey, weight: u8) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            basket.tokens.len() < basket.max_tokens as usize,
+            BasketError::TooManyTokens
+        );
+        // Weight 0 reserves the slot for a later `activate_token`
+        require!(
+            weight == 0 || basket.meets_min_weight(weight),
+            BasketError::WeightBelowMinimum
+        );
+        TokenAllowlist::check_mint(&ctx.accounts.allowlist, &token_mint)?;
+
+        // Validate total weights
+        let total_weight: u8 = basket
+            .tokens
+            .iter()
+            .map(|t| t.weight)
+            .sum::<u8>()
+            .checked_add(weight)
+            .ok_or(BasketError::WeightOverflow)?;
+        require!(total_weight <= 100, BasketError::WeightOverflow);
+
+        // Check for duplicate token
+        require!(
+            !basket.tokens.iter().any(|t| t.mint == token_mint),
+            BasketError::DuplicateToken
+        );
+
+        basket.tokens.push(TokenInfo {
+            mint: token_mint,
+            weight,
+            token_account: Pubkey::default(),
+            price_feed: Pubkey::default(),
+            price_source: PriceSource::Pyth,
+            reserved_amount: 0,
+        });
+
+        emit!(TokenAddedEvent {
+            authority: ctx.accounts.authority.key(),
+            mint: token_mint,
+            weight,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn add_tokens(ctx: Context<AddToken>, tokens: Vec<(Pubkey, u8)>) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            basket.tokens.len() + tokens.len() <= basket.max_tokens as usize,
+            BasketError::TooManyTokens
+        );
+
+        // Validate the whole batch before pushing anything
+        let mut total_weight: u8 = basket.tokens.iter().map(|t| t.weight).sum();
+        for (i, (token_mint, weight)) in tokens.iter().enumerate() {
+            require!(
+                *weight == 0 || basket.meets_min_weight(*weight),
+                BasketError::WeightBelowMinimum
+            );
+            TokenAllowlist::check_mint(&ctx.accounts.allowlist, token_mint)?;
+            require!(
+                !basket.tokens.iter().any(|t| t.mint == *token_mint)
+                    && !tokens[..i].iter().any(|(mint, _)| mint == token_mint),
+                BasketError::DuplicateToken
+            );
+            total_weight = total_weight
+                .checked_add(*weight)
+                .ok_or(BasketError::WeightOverflow)?;
+        }
+        require!(total_weight <= 100, BasketError::WeightOverflow);
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        for (token_mint, weight) in tokens {
+            basket.tokens.push(TokenInfo {
+                mint: token_mint,
+                weight,
+                token_account: Pubkey::default(),
+                price_feed: Pubkey::default(),
+                price_source: PriceSource::Pyth,
+                reserved_amount: 0,
+            });
+            emit!(TokenAddedEvent {
+                authority: ctx.accounts.authority.key(),
+                mint: token_mint,
+                weight,
+                timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn initialize_allowlist(
+        ctx: Context<InitializeAllowlist>,
+        allowlist_authority: Pubkey,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let allowlist = &mut ctx.accounts.allowlist;
+        allowlist.basket = ctx.accounts.basket.key();
+        allowlist.allowlist_authority = allowlist_authority;
+        allowlist.mints = vec![];
+        allowlist.bump = *ctx.bumps.get("allowlist").unwrap();
+        Ok(())
+    }
+
+    pub fn add_allowed_mint(ctx: Context<UpdateAllowlist>, mint: Pubkey) -> Result<()> {
+        let allowlist = &mut ctx.accounts.allowlist;
+        require!(
+            !allowlist.mints.contains(&mint),
+            BasketError::DuplicateToken
+        );
+        require!(
+            allowlist.mints.len() < TokenAllowlist::MAX_MINTS,
+            BasketError::TooManyTokens
+        );
+        allowlist.mints.push(mint);
+        Ok(())
+    }
+
+    pub fn remove_allowed_mint(ctx: Context<UpdateAllowlist>, mint: Pubkey) -> Result<()> {
+        let allowlist = &mut ctx.accounts.allowlist;
+        let index = allowlist
+            .mints
+            .iter()
+            .position(|m| *m == mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        allowlist.mints.remove(index);
+        Ok(())
+    }
+
+    pub fn initialize_token_account(
+        ctx: Context<InitializeTokenAccount>,
+        token_mint: Pubkey,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let token_info = basket
+            .tokens
+            .iter_mut()
+            .find(|t| t.mint == token_mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        token_info.token_account = ctx.accounts.basket_token_account.key();
+
+        Ok(())
+    }
+
+    /// Create whichever basket-owned ATAs are missing and record every
+    /// token's account. Remaining accounts: each token's mint followed by the
+    /// basket's ATA for it, ordered like `basket.tokens`. Safe to re-run.
+    pub fn initialize_all_token_accounts<'info>(
+        ctx: Context<'_, '_, '_, 'info, InitializeAllTokenAccounts<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(
+            ctx.remaining_accounts.len() == basket.tokens.len() * 2,
+            BasketError::InvalidAccountCount
+        );
+
+        let basket_key = basket.key();
+        let token_program = ctx.accounts.token_program.key();
+        let mut token_accounts = Vec::with_capacity(basket.tokens.len());
+        for (token_info, pair) in basket.tokens.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let (mint, token_account) = (&pair[0], &pair[1]);
+            require!(mint.key() == token_info.mint, BasketError::InvalidTokenMint);
+            require!(
+                token_account.key()
+                    == associated_token::get_associated_token_address_with_program_id(
+                        &basket_key,
+                        &token_info.mint,
+                        &token_program,
+                    ),
+                BasketError::InvalidTokenAccount
+            );
+
+            if token_account.data_is_empty() {
+                associated_token::create_idempotent(CpiContext::new(
+                    ctx.accounts.associated_token_program.to_account_info(),
+                    associated_token::Create {
+                        payer: ctx.accounts.authority.to_account_info(),
+                        associated_token: token_account.clone(),
+                        authority: basket.to_account_info(),
+                        mint: mint.clone(),
+                        system_program: ctx.accounts.system_program.to_account_info(),
+                        token_program: ctx.accounts.token_program.to_account_info(),
+                    },
+                ))?;
+            }
+            token_accounts.push(token_account.key());
+        }
+
+        for (token_info, token_account) in basket.tokens.iter_mut().zip(token_accounts) {
+            token_info.token_account = token_account;
+        }
+        Ok(())
+    }
+
+    pub fn remove_token(ctx: Context<RemoveToken>, token_mint: Pubkey) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let token_index = basket
+            .tokens
+            .iter()
+            .position(|t| t.mint == token_mint)
+            .ok_or(BasketError::TokenNotFound)?;
+
+        // Holdings must be drained first; the account is only optional for
+        // tokens whose account was never initialized
+        let registered_account = basket.tokens[token_index].token_account;
+        match &ctx.accounts.basket_token {
+            Some(basket_token) => {
+                require!(
+                    basket_token.mint == token_mint,
+                    BasketError::InvalidTokenMint
+                );
+                require!(
+                    registered_account == Pubkey::default()
+                        || basket_token.key() == registered_account,
+                    BasketError::InvalidTokenAccount
+                );
+                require!(basket_token.amount == 0, BasketError::TokenBalanceNonZero);
+            }
+            None => require!(
+                registered_account == Pubkey::default(),
+                BasketError::InvalidTokenAccount
+            ),
+        }
+
+        basket.tokens.remove(token_index);
+
+        emit!(TokenRemovedEvent {
+            authority: ctx.accounts.authority.key(),
+            mint: token_mint,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn update_token_weight(
+        ctx: Context<UpdateTokenWeight>,
+        token_mint: Pubkey,
+        new_weight: u8,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let token_index = basket
+            .tokens
+            .iter()
+            .position(|t| t.mint == token_mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        require!(
+            basket.meets_min_weight(new_weight),
+            BasketError::WeightBelowMinimum
+        );
+
+        // Validate total weights with the replaced entry
+        let total_weight: u8 = basket
+            .tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != token_index)
+            .map(|(_, t)| t.weight)
+            .sum::<u8>()
+            .checked_add(new_weight)
+            .ok_or(BasketError::WeightOverflow)?;
+        require!(total_weight <= 100, BasketError::WeightOverflow);
+
+        basket.tokens[token_index].weight = new_weight;
+
+        Ok(())
+    }
+
+    /// Give a slot reserved by `add_token` with weight 0 its first weight
+    pub fn activate_token(
+        ctx: Context<UpdateTokenWeight>,
+        token_mint: Pubkey,
+        weight: u8,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let token_index = basket
+            .tokens
+            .iter()
+            .position(|t| t.mint == token_mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        require!(
+            basket.tokens[token_index].weight == 0,
+            BasketError::TokenAlreadyActive
+        );
+        require!(
+            weight > 0 && basket.meets_min_weight(weight),
+            BasketError::WeightBelowMinimum
+        );
+
+        let total_weight: u8 = basket
+            .tokens
+            .iter()
+            .map(|t| t.weight)
+            .sum::<u8>()
+            .checked_add(weight)
+            .ok_or(BasketError::WeightOverflow)?;
+        require!(total_weight <= 100, BasketError::WeightOverflow);
+
+        basket.tokens[token_index].weight = weight;
+
+        Ok(())
+    }
+
+    pub fn set_price_feed(
+        ctx: Context<SetPriceFeed>,
+        token_mint: Pubkey,
+        price_feed: Pubkey,
+        price_source: PriceSource,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let token_info = basket
+            .tokens
+            .iter_mut()
+            .find(|t| t.mint == token_mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        token_info.price_feed = price_feed;
+        token_info.price_source = price_source;
+
+        Ok(())
+    }
+
+    pub fn set_max_slippage(ctx: Context<SetMaxSlippage>, max_slippage_bps: u16) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            max_slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        basket.max_slippage_bps = max_slippage_bps;
+        Ok(())
+    }
+
+    pub fn resize_basket(ctx: Context<ResizeBasket>, new_max_tokens: u8) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            new_max_tokens as usize >= basket.tokens.len()
+                && new_max_tokens as usize <= MAX_RESIZED_TOKENS,
+            BasketError::TooManyTokens
+        );
+        basket.max_tokens = new_max_tokens;
+        Ok(())
+    }
+
+    /// Upgrade a basket written under an older `BasketState` layout in place,
+    /// growing the account if the current layout needs more room
+    pub fn migrate(ctx: Context<Migrate>) -> Result<()> {
+        let basket_info = ctx.accounts.basket.to_account_info();
+        let mut basket = BasketState::read_any_version(&basket_info.try_borrow_data()?)?;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        let basket_id = basket.basket_id.to_le_bytes();
+        let expected = Pubkey::create_program_address(
+            &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]],
+            ctx.program_id,
+        )
+        .map_err(|_| error!(anchor_lang::error::ErrorCode::ConstraintSeeds))?;
+        require_keys_eq!(
+            expected,
+            basket_info.key(),
+            anchor_lang::error::ErrorCode::ConstraintSeeds
+        );
+
+        basket.upgrade()?;
+
+        let space = BasketState::required_space(basket.max_tokens as usize);
+        if basket_info.data_len() < space {
+            let shortfall = Rent::get()?
+                .minimum_balance(space)
+                .saturating_sub(basket_info.lamports());
+            if shortfall > 0 {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.authority.to_account_info(),
+                            to: basket_info.clone(),
+                        },
+                    ),
+                    shortfall,
+                )?;
+            }
+            basket_info.realloc(space, false)?;
+        }
+
+        basket.try_serialize(&mut &mut basket_info.try_borrow_mut_data()?[..])?;
+        Ok(())
+    }
+
+    pub fn set_auto_pause_threshold(
+        ctx: Context<SetAutoPauseThreshold>,
+        auto_pause_threshold_bps: u16,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            auto_pause_threshold_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        basket.auto_pause_threshold_bps = auto_pause_threshold_bps;
+        Ok(())
+    }
+
+    pub fn set_rebalance_cooldown(
+        ctx: Context<SetRebalanceCooldown>,
+        rebalance_cooldown_seconds: u64,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.rebalance_cooldown_seconds = rebalance_cooldown_seconds;
+        Ok(())
+    }
+
+    pub fn set_min_hold_seconds(
+        ctx: Context<SetMinHoldSeconds>,
+        min_hold_seconds: u64,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.min_hold_seconds = min_hold_seconds;
+        Ok(())
+    }
+
+    pub fn set_min_swap_lamports(
+        ctx: Context<SetMinSwapLamports>,
+        min_swap_lamports: u64,
+        roll_small_legs: bool,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.min_swap_lamports = min_swap_lamports;
+        basket.roll_small_legs = roll_small_legs;
+        Ok(())
+    }
+
+    pub fn set_swap_discriminator(
+        ctx: Context<SetSwapDiscriminator>,
+        swap_discriminator: u8,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.swap_discriminator = swap_discriminator;
+        Ok(())
+    }
+
+    pub fn set_max_swaps_per_tx(
+        ctx: Context<SetMaxSwapsPerTx>,
+        max_swaps_per_tx: u8,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.max_swaps_per_tx = max_swaps_per_tx;
+        Ok(())
+    }
+
+    pub fn set_max_price_age(
+        ctx: Context<SetMaxPriceAge>,
+        max_price_age_seconds: u64,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(max_price_age_seconds > 0, BasketError::InvalidPriceAge);
+        basket.max_price_age_seconds = max_price_age_seconds;
+        Ok(())
+    }
+
+    /// Overwrite the tracked `total_supply` with the basket mint's real supply
+    pub fn reconcile_supply(ctx: Context<ReconcileSupply>) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let previous_supply = basket.total_supply;
+        basket.total_supply = ctx.accounts.basket_mint.supply;
+
+        emit!(SupplyReconciled {
+            basket: basket.key(),
+            previous_supply,
+            reconciled_supply: basket.total_supply,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn create_metadata(
+        ctx: Context<CreateMetadata>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        metaplex::validate(&name, &symbol, &uri)?;
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        metaplex::create_metadata_account(
+            &ctx.accounts.metadata.to_account_info(),
+            &ctx.accounts.basket_mint.to_account_info(),
+            &basket.to_account_info(),
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.rent.to_account_info(),
+            &ctx.accounts.token_metadata_program.to_account_info(),
+            name,
+            symbol,
+            uri,
+            seeds,
+        )
+    }
+
+    /// Point the basket mint's transfer hook at this program so shares can
+    /// only move into or out of the basket. Needs a mint created by
+    /// `initialize_mint` under Token-2022.
+    pub fn enable_transfer_restriction(ctx: Context<EnableTransferRestriction>) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        let basket_mint = ctx.accounts.basket_mint.to_account_info();
+        require!(
+            transfer_hook::has_extension(&basket_mint.try_borrow_data()?),
+            BasketError::TransferHookUnavailable
+        );
+
+        transfer_hook::write_extra_account_metas(
+            &mut ctx.accounts.extra_account_metas.try_borrow_mut_data()?,
+        );
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        transfer_hook::update(
+            &ctx.accounts.token_program.to_account_info(),
+            &basket_mint,
+            &basket.to_account_info(),
+            &crate::ID,
+            seeds,
+        )
+    }
+
+    /// Anchor hands unrecognized instructions here. The only one expected is
+    /// the transfer hook's Execute, which Token-2022 invokes on every share
+    /// transfer once `enable_transfer_restriction` has run.
+    pub fn fallback(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> Result<()> {
+        require!(
+            data.starts_with(&transfer_hook::EXECUTE_DISCRIMINATOR),
+            anchor_lang::error::ErrorCode::InstructionFallbackNotFound
+        );
+        transfer_hook::execute(accounts)
+    }
+
+    pub fn set_input_mint(ctx: Context<SetInputMint>, input_mint: Pubkey, allowed: bool) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        let position = basket.input_mints.iter().position(|m| *m == input_mint);
+        match (position, allowed) {
+            (None, true) => {
+                require!(
+                    basket.input_mints.len() < BasketState::MAX_INPUT_MINTS,
+                    BasketError::TooManyTokens
+                );
+                basket.input_mints.push(input_mint);
+            }
+            (Some(index), false) => {
+                basket.input_mints.remove(index);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Swaps sign as the basket PDA, so allowlisting a router is as
+    /// privileged as withdrawing and waits out the timelock
+    pub fn queue_add_swap_program(
+        ctx: Context<UpdateSwapPrograms>,
+        program_id: Pubkey,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.queue_action(TimelockedAction::UpdateSwapProgram {
+            program_id,
+            allowed: true,
+        })
+    }
+
+    pub fn queue_remove_swap_program(
+        ctx: Context<UpdateSwapPrograms>,
+        program_id: Pubkey,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.queue_action(TimelockedAction::UpdateSwapProgram {
+            program_id,
+            allowed: false,
+        })
+    }
+
+    pub fn execute_update_swap_program(ctx: Context<UpdateSwapPrograms>) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        let (program_id, allowed) = match basket.take_ready_action()? {
+            TimelockedAction::UpdateSwapProgram {
+                program_id,
+                allowed,
+            } => (program_id, allowed),
+            _ => return err!(BasketError::NoPendingAction),
+        };
+
+        if !allowed {
+            basket.allowed_swap_programs.retain(|p| *p != program_id);
+        } else if !basket.allowed_swap_programs.contains(&program_id) {
+            require!(
+                basket.allowed_swap_programs.len() < BasketState::MAX_SWAP_PROGRAMS,
+                BasketError::TooManySwapPrograms
+            );
+            basket.allowed_swap_programs.push(program_id);
+        }
+
+        Ok(())
+    }
+
+    pub fn queue_set_pause_state(
+        ctx: Context<SetPauseState>,
+        deposits: bool,
+        redemptions: bool,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.queue_action(TimelockedAction::SetPauseState {
+            deposits,
+            redemptions,
+        })
+    }
+
+    pub fn execute_set_pause_state(
+        ctx: Context<SetPauseState>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        match basket.take_ready_action()? {
+            TimelockedAction::SetPauseState {
+                deposits,
+                redemptions,
+            } => {
+                basket.deposits_paused = deposits;
+                basket.redemptions_paused = redemptions;
+            }
+            _ => return err!(BasketError::NoPendingAction),
+        }
+        basket.set_pause_reason(reason)?;
+
+        emit!(PauseStateChangedEvent {
+            authority: ctx.accounts.authority.key(),
+            paused: basket.deposits_paused || basket.redemptions_paused,
+            deposits_paused: basket.deposits_paused,
+            redemptions_paused: basket.redemptions_paused,
+            reason: basket.pause_reason.clone(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Pause deposits and/or redemptions without waiting out the timelock.
+    /// Callable by the guardian or the authority; unpausing still goes
+    /// through `queue_set_pause_state`
+    pub fn guardian_pause(
+        ctx: Context<GuardianPause>,
+        deposits: bool,
+        redemptions: bool,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        let signer = ctx.accounts.guardian.key();
+        require!(
+            signer == basket.guardian || signer == basket.authority,
+            BasketError::Unauthorized
+        );
+        basket.deposits_paused |= deposits;
+        basket.redemptions_paused |= redemptions;
+        basket.set_pause_reason(reason)?;
+
+        emit!(PauseStateChangedEvent {
+            authority: signer,
+            paused: basket.deposits_paused || basket.redemptions_paused,
+            deposits_paused: basket.deposits_paused,
+            redemptions_paused: basket.redemptions_paused,
+            reason: basket.pause_reason.clone(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.guardian = guardian;
+        Ok(())
+    }
+
+    pub fn set_fee_recipient(ctx: Context<SetFeeRecipient>, new_recipient: Pubkey) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            new_recipient != Pubkey::default(),
+            BasketError::InvalidFeeRecipient
+        );
+        let previous_recipient = basket.fee_recipient;
+        basket.fee_recipient = new_recipient;
+
+        emit!(FeeRecipientUpdated {
+            authority: ctx.accounts.authority.key(),
+            previous_recipient,
+            new_recipient,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.pending_authority = Some(new_authority);
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.pending_authority == Some(ctx.accounts.new_authority.key()),
+            BasketError::Unauthorized
+        );
+        basket.authority = ctx.accounts.new_authority.key();
+        basket.pending_authority = None;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit(
+        ctx: Context<Deposit>,
+        amount: u64,
+        jupiter_quotes: Vec<[u8; 32]>,
+        slippage_bps: u16,
+        minimum_shares_out: u64,
+        accounts_per_swap: Vec<u8>,
+        nonce: Option<u64>,
+        continue_on_leg_failure: bool,
+        max_allocation_lamports: Option<u64>,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+
+        // Share and wSOL accounts are created here rather than by
+        // `init_if_needed` constraints, which can't derive Token-2022 addresses
+        let accounts = &ctx.accounts;
+        for (token_account, authority, mint) in [
+            (
+                &accounts.user_basket_token,
+                accounts.user.to_account_info(),
+                accounts.basket_mint.to_account_info(),
+            ),
+            (
+                &accounts.basket_share_token,
+                accounts.basket.to_account_info(),
+                accounts.basket_mint.to_account_info(),
+            ),
+            (
+                &accounts.basket_wsol,
+                accounts.basket.to_account_info(),
+                accounts.native_mint.to_account_info(),
+            ),
+        ] {
+            token_accounts::create_associated(
+                token_account,
+                &authority,
+                &mint,
+                &accounts.user.to_account_info(),
+                &accounts.system_program.to_account_info(),
+                &accounts.token_program.to_account_info(),
+                &accounts.associated_token_program.to_account_info(),
+            )?;
+        }
+
+        let basket = &mut ctx.accounts.basket;
+        require!(basket.initialized, BasketError::NotInitialized);
+        require!(!basket.deposits_paused, BasketError::DepositsPaused);
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+        require!(
+            basket.tokens.iter().map(|t| t.weight).sum::<u8>() == 100,
+            BasketError::WeightsNotComplete
+        );
+        require!(
+            basket.tokens_initialized(),
+            BasketError::TokenAccountNotInitialized
+        );
+        basket.check_swap_count()?;
+
+        // Take and allocate at most `max_allocation_lamports`; the rest of
+        // `amount` stays with the user
+        let amount = max_allocation_lamports.map_or(amount, |max| amount.min(max));
+        require!(
+            amount >= basket.minimum_deposit,
+            BasketError::InsufficientDeposit
+        );
+        require!(
+            jupiter_quotes.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+
+        // Reject rebroadcasts: a supplied nonce must beat the last one accepted
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        if let Some(nonce) = nonce {
+            require!(nonce > user_deposit.last_nonce, BasketError::StaleNonce);
+            user_deposit.last_nonce = nonce;
+        }
+
+        // Enforce the per-user cap (0 means unlimited)
+        user_deposit.user = ctx.accounts.user.key();
+        user_deposit.last_deposit_ts = Clock::get()?.unix_timestamp;
+        user_deposit.total_deposited = user_deposit
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(
+            basket.max_deposit_per_user == 0
+                || user_deposit.total_deposited <= basket.max_deposit_per_user,
+            BasketError::DepositCapExceeded
+        );
+
+        // Enforce the basket-wide cap on lamports above the rent-exempt reserve (0 means unlimited)
+        if basket.max_tvl_lamports > 0 {
+            let basket_info = basket.to_account_info();
+            let rent_exempt_lamports = Rent::get()?.minimum_balance(basket_info.data_len());
+            let tvl = basket_info
+                .lamports()
+                .saturating_sub(rent_exempt_lamports)
+                .checked_add(amount)
+                .ok_or(BasketError::MathOverflow)?;
+            require!(
+                tvl <= basket.max_tvl_lamports,
+                BasketError::TvlCapExceeded
+            );
+        }
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+
+        // Validate remaining accounts count: swap accounts for weighted legs,
+        // zero-weight token accounts, then one price account per token
+        let weights = basket.weights();
+        jupiter::swap_account_count(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+        let price_accounts = jupiter::price_accounts(ctx.remaining_accounts, weights.len())?;
+        let balance_accounts =
+            jupiter::balance_accounts(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+
+        // Value the basket before this deposit lands
+        let current_nav = nav::compute_nav(&basket, &balance_accounts, price_accounts)?;
+
+        // Route the entry fee to the fee recipient
+        let fee = nav::to_u64(
+            (amount as u128)
+                .checked_mul(basket.entry_fee_bps as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(BPS_DENOMINATOR as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        let net_amount = amount.checked_sub(fee).ok_or(BasketError::MathOverflow)?;
+
+        if fee > 0 {
+            let fee_ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.user.key(),
+                &ctx.accounts.fee_recipient.key(),
+                fee,
+            );
+
+            anchor_lang::solana_program::program::invoke(
+                &fee_ix,
+                &[
+                    ctx.accounts.user.to_account_info(),
+                    ctx.accounts.fee_recipient.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        // Jupiter routes spend wSOL, so transfer the deposit straight into the
+        // basket's wSOL account, confirming it was credited exactly `net_amount`
+        let basket_wsol = ctx.accounts.basket_wsol.to_account_info();
+        let lamports_before = basket_wsol.lamports();
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.user.key(),
+            basket_wsol.key,
+            net_amount,
+        );
+
+        anchor_lang::solana_program::program::invoke(
+            &transfer_ix,
+            &[
+                ctx.accounts.user.to_account_info(),
+                basket_wsol.clone(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+        let lamports_received = basket_wsol
+            .lamports()
+            .checked_sub(lamports_before)
+            .ok_or(BasketError::TransferFailed)?;
+        require!(
+            lamports_received == net_amount,
+            BasketError::TransferFailed
+        );
+        token_interface::sync_native(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::SyncNative {
+                account: basket_wsol.clone(),
+            },
+        ))?;
+
+        // Buy each basket token, signing as the basket
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        let mut offset = 0;
+        let mut leg = 0;
+        let mut unallocated: u64 = 0;
+        let mut carried: u64 = 0;
+        let last_leg = basket
+            .tokens
+            .iter()
+            .rposition(|t| t.weight > 0)
+            .unwrap_or_default();
+
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            // Zero-weight placeholders take no allocation and have no route
+            if token_info.weight == 0 {
+                continue;
+            }
+            let swap_accounts = jupiter::route(ctx.remaining_accounts, offset, accounts_per_swap[leg])?;
+            offset += accounts_per_swap[leg] as usize;
+            leg += 1;
+
+            // Legs below Jupiter's practical minimum are rolled into the next
+            // leg or left as idle SOL instead of attempting a doomed swap
+            let leg_allocation = nav::to_u64(
+                (net_amount as u128)
+                    .checked_mul(token_info.weight as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(100)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            let leg_allocation = leg_allocation
+                .checked_add(std::mem::take(&mut carried))
+                .ok_or(BasketError::MathOverflow)?;
+            if leg_allocation < basket.min_swap_lamports {
+                if basket.roll_small_legs && i < last_leg {
+                    msg!("Leg {}: {} lamports below minimum; rolling into next leg", i, leg_allocation);
+                    carried = leg_allocation;
+                } else {
+                    msg!("Leg {}: {} lamports below minimum; skipping", i, leg_allocation);
+                }
+                continue;
+            }
+
+            // Get token account from remaining accounts
+            let token_account = jupiter::balance_account(swap_accounts)?;
+            let token_acc_data = nav::load_token_account(token_account, i)?;
+            require!(
+                token_acc_data.mint == token_info.mint,
+                BasketError::InvalidTokenMint
+            );
+            require!(
+                token_acc_data.owner == basket_key,
+                BasketError::InvalidTokenOwner
+            );
+
+            let token_amount = token_acc_data.amount;
+            let wsol_before = nav::load_token_account(&basket_wsol, i)?.amount;
+            let lamports_before = basket.to_account_info().lamports();
+
+            // Execute swap
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                swap_accounts,
+                jupiter::RouteSwapParams {
+                    in_amount: leg_allocation,
+                    quote_id: jupiter_quotes[i],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+
+            // A buy spends the basket's wSOL and SOL, so neither can go up; the
+            // leg's tokens must be worth what it spent, less slippage
+            let wsol_spent = wsol_before
+                .checked_sub(nav::load_token_account(&basket_wsol, i)?.amount)
+                .ok_or(BasketError::MathOverflow)?;
+            let lamports_spent = lamports_before
+                .checked_sub(basket.to_account_info().lamports())
+                .ok_or(BasketError::MathOverflow)?
+                .checked_add(wsol_spent)
+                .ok_or(BasketError::MathOverflow)?;
+            require!(
+                lamports_spent <= leg_allocation,
+                BasketError::SlippageExceeded
+            );
+            let received = nav::load_token_account(token_account, i)?
+                .amount
+                .checked_sub(token_amount)
+                .ok_or(BasketError::SlippageExceeded)?;
+            let price = nav::read_fresh_price(&basket, token_info, &price_accounts[i])?;
+            let min_out = (lamports_spent as u128)
+                .checked_mul((BPS_DENOMINATOR - slippage_bps as u64) as u128)
+                .ok_or(BasketError::MathOverflow)?
+                / BPS_DENOMINATOR as u128;
+            require!(
+                nav::token_value(received, &price)? as u128 >= min_out,
+                BasketError::SlippageExceeded
+            );
+
+            // A reverting CPI aborts the whole transaction, so in permissive
+            // mode a leg fails when its route returns without delivering
+            // tokens. Its share of the deposit is refunded below.
+            if continue_on_leg_failure && received == 0 {
+                msg!("Leg {}: route for {} delivered nothing; skipping", i, token_info.mint);
+                unallocated = unallocated
+                    .checked_add(leg_allocation)
+                    .ok_or(BasketError::MathOverflow)?;
+            }
+        }
+
+        // Unwrap whatever the legs left back into the basket as SOL. The
+        // account's rent goes back to the user who paid it once shares are minted
+        let wsol_rent = Rent::get()?.minimum_balance(basket_wsol.data_len());
+        token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::CloseAccount {
+                account: basket_wsol,
+                destination: basket.to_account_info(),
+                authority: basket.to_account_info(),
+            },
+            &[seeds],
+        ))?;
+
+        let allocated = net_amount
+            .checked_sub(unallocated)
+            .ok_or(BasketError::MathOverflow)?;
+
+        // Shares reflect what the swaps actually added to NAV, so a bad fill
+        // shows up as fewer shares rather than diluting existing holders.
+        // Unallocated capital and the wSOL rent still sit in the PDA until
+        // they are refunded below.
+        let refund = unallocated
+            .checked_add(wsol_rent)
+            .ok_or(BasketError::MathOverflow)?;
+        let nav_after = nav::compute_nav(&basket, &balance_accounts, price_accounts)?;
+        let value_added = nav_after
+            .checked_sub(current_nav)
+            .and_then(|value| value.checked_sub(refund))
+            .ok_or(BasketError::SlippageExceeded)?;
+        require!(value_added > 0, BasketError::InsufficientDeposit);
+        require!(
+            !basket.trips_circuit_breaker(allocated, value_added),
+            BasketError::CircuitBreakerTripped
+        );
+
+        // Price shares against NAV; the first deposit bootstraps 1:1
+        let shares_to_mint = basket.shares_for(value_added, current_nav)?;
+
+        // The first deposit locks MINIMUM_LIQUIDITY shares in the basket for good
+        let locked_shares = basket.locked_liquidity(shares_to_mint)?;
+        let user_shares = shares_to_mint - locked_shares;
+        require!(
+            user_shares >= minimum_shares_out,
+            BasketError::SlippageExceeded
+        );
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_add(shares_to_mint)
+            .ok_or(BasketError::MathOverflow)?;
+
+        if locked_shares > 0 {
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token_interface::MintTo {
+                        mint: ctx.accounts.basket_mint.to_account_info(),
+                        to: ctx.accounts.basket_share_token.to_account_info(),
+                        authority: basket.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                locked_shares,
+            )?;
+        }
+
+        // Mint basket tokens to user
+        let cpi_accounts = token_interface::MintTo {
+            mint: ctx.accounts.basket_mint.to_account_info(),
+            to: ctx.accounts.user_basket_token.to_account_info(),
+            authority: basket.to_account_info(),
+        };
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                &[seeds],
+            ),
+            user_shares,
+        )?;
+
+        // Return the wSOL rent and capital that no leg took up, so shares
+        // cover only what was allocated
+        **basket.to_account_info().try_borrow_mut_lamports()? -= refund;
+        **ctx
+            .accounts
+            .user
+            .to_account_info()
+            .try_borrow_mut_lamports()? += refund;
+        ctx.accounts.user_deposit.total_deposited = ctx
+            .accounts
+            .user_deposit
+            .total_deposited
+            .checked_sub(unallocated)
+            .ok_or(BasketError::MathOverflow)?;
+
+        // Clear reentrancy guard
+        drop(basket);
+
+        emit!(DepositEvent {
+            user: ctx.accounts.user.key(),
+            sol_in: amount - unallocated,
+            shares_minted: user_shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit_token(
+        ctx: Context<DepositToken>,
+        token_mint: Pubkey,
+        amount: u64,
+        jupiter_quotes: Vec<[u8; 32]>,
+        slippage_bps: u16,
+        minimum_token_amounts: Vec<u64>,
+        expected_token_amounts: Vec<u64>,
+        accounts_per_swap: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+
+        // Share accounts are created here rather than by `init_if_needed`
+        // constraints, which can't derive Token-2022 addresses
+        let accounts = &ctx.accounts;
+        for (token_account, authority) in [
+            (&accounts.user_basket_token, accounts.user.to_account_info()),
+            (&accounts.basket_share_token, accounts.basket.to_account_info()),
+        ] {
+            token_accounts::create_associated(
+                token_account,
+                &authority,
+                &accounts.basket_mint.to_account_info(),
+                &accounts.user.to_account_info(),
+                &accounts.system_program.to_account_info(),
+                &accounts.token_program.to_account_info(),
+                &accounts.associated_token_program.to_account_info(),
+            )?;
+        }
+
+        let basket = &mut ctx.accounts.basket;
+        require!(basket.initialized, BasketError::NotInitialized);
+        require!(!basket.deposits_paused, BasketError::DepositsPaused);
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+        require!(
+            basket.input_mints.contains(&token_mint),
+            BasketError::InputMintNotAllowed
+        );
+        require!(
+            ctx.accounts.input_mint.key() == token_mint
+                && ctx.accounts.user_input_token.mint == token_mint
+                && ctx.accounts.basket_input_token.mint == token_mint,
+            BasketError::InvalidTokenMint
+        );
+        require!(
+            basket.tokens.iter().map(|t| t.weight).sum::<u8>() == 100,
+            BasketError::WeightsNotComplete
+        );
+        require!(
+            basket.tokens_initialized(),
+            BasketError::TokenAccountNotInitialized
+        );
+        require!(amount > 0, BasketError::InsufficientDeposit);
+        require!(
+            minimum_token_amounts.len() == basket.tokens.len()
+                && expected_token_amounts.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+
+        // Each floor must sit within slippage_bps of the quoted output, so a
+        // zero floor can't switch slippage protection off
+        for (minimum, expected) in minimum_token_amounts.iter().zip(&expected_token_amounts) {
+            let floor = (*expected as u128)
+                .checked_mul((BPS_DENOMINATOR - slippage_bps as u64) as u128)
+                .ok_or(BasketError::MathOverflow)?
+                / BPS_DENOMINATOR as u128;
+            require!(
+                *minimum as u128 >= floor,
+                BasketError::SlippageFloorTooLow
+            );
+        }
+        require!(
+            jupiter_quotes.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+
+        // Validate remaining accounts count: swap accounts for weighted legs,
+        // zero-weight token accounts, then one price account per token
+        let weights = basket.weights();
+        jupiter::swap_account_count(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+        let price_accounts = jupiter::price_accounts(ctx.remaining_accounts, weights.len())?;
+        let balance_accounts =
+            jupiter::balance_accounts(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+
+        // Value the basket before the swaps land
+        let nav_before = nav::compute_nav(&basket, &balance_accounts, price_accounts)?;
+
+        // Pull the input tokens into the basket's input account. Token-2022
+        // transfer fees can withhold part of `amount`, so only what actually
+        // arrived is swapped.
+        let input_before = ctx.accounts.basket_input_token.amount;
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.input_token_program.to_account_info(),
+                token_interface::TransferChecked {
+                    from: ctx.accounts.user_input_token.to_account_info(),
+                    mint: ctx.accounts.input_mint.to_account_info(),
+                    to: ctx.accounts.basket_input_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.input_mint.decimals,
+        )?;
+        ctx.accounts.basket_input_token.reload()?;
+        let received = ctx
+            .accounts
+            .basket_input_token
+            .amount
+            .checked_sub(input_before)
+            .ok_or(BasketError::MathOverflow)?;
+
+        // Swap the input into each basket token by weight, signing as the basket
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        let mut offset = 0;
+        let mut leg = 0;
+
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            if token_info.weight == 0 {
+                continue;
+            }
+            let swap_accounts = jupiter::route(ctx.remaining_accounts, offset, accounts_per_swap[leg])?;
+            offset += accounts_per_swap[leg] as usize;
+            leg += 1;
+
+            let token_acc_data = InterfaceAccount::<TokenAccount>::try_from(jupiter::balance_account(swap_accounts)?)?;
+            require!(
+                token_acc_data.mint == token_info.mint,
+                BasketError::InvalidTokenMint
+            );
+            require!(
+                token_acc_data.owner == basket_key,
+                BasketError::InvalidTokenOwner
+            );
+            let balance_before = token_acc_data.amount;
+
+            let in_amount = nav::to_u64(
+                (received as u128)
+                    .checked_mul(token_info.weight as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(100)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                swap_accounts,
+                jupiter::RouteSwapParams {
+                    in_amount,
+                    quote_id: jupiter_quotes[i],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+
+            let balance_after = InterfaceAccount::<TokenAccount>::try_from(jupiter::balance_account(swap_accounts)?)?.amount;
+            let received = balance_after
+                .checked_sub(balance_before)
+                .ok_or(BasketError::MathOverflow)?;
+            require!(
+                received >= minimum_token_amounts[i],
+                BasketError::SlippageExceeded
+            );
+        }
+
+        // The deposit's value is whatever the swaps added to NAV
+        let nav_after = nav::compute_nav(&basket, &balance_accounts, price_accounts)?;
+        let value_added = nav_after
+            .checked_sub(nav_before)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(value_added > 0, BasketError::InsufficientDeposit);
+        require!(
+            basket.max_tvl_lamports == 0 || nav_after <= basket.max_tvl_lamports,
+            BasketError::TvlCapExceeded
+        );
+
+        // Enforce the per-user cap (0 means unlimited)
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        user_deposit.user = ctx.accounts.user.key();
+        user_deposit.last_deposit_ts = Clock::get()?.unix_timestamp;
+        user_deposit.total_deposited = user_deposit
+            .total_deposited
+            .checked_add(value_added)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(
+            basket.max_deposit_per_user == 0
+                || user_deposit.total_deposited <= basket.max_deposit_per_user,
+            BasketError::DepositCapExceeded
+        );
+
+        // Price shares against NAV; the first deposit bootstraps 1:1
+        let shares_to_mint = basket.shares_for(value_added, nav_before)?;
+
+        // The first deposit locks MINIMUM_LIQUIDITY shares in the basket for good
+        let locked_shares = basket.locked_liquidity(shares_to_mint)?;
+        let user_shares = shares_to_mint - locked_shares;
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_add(shares_to_mint)
+            .ok_or(BasketError::MathOverflow)?;
+
+        if locked_shares > 0 {
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token_interface::MintTo {
+                        mint: ctx.accounts.basket_mint.to_account_info(),
+                        to: ctx.accounts.basket_share_token.to_account_info(),
+                        authority: basket.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                locked_shares,
+            )?;
+        }
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    to: ctx.accounts.user_basket_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            user_shares,
+        )?;
+
+        // Clear reentrancy guard
+        drop(basket);
+
+        emit!(DepositEvent {
+            user: ctx.accounts.user.key(),
+            sol_in: value_added,
+            shares_minted: user_shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// First step of a split deposit: take the user's SOL into the basket,
+    /// held outside NAV until `finalize_deposit`
+    pub fn begin_deposit(ctx: Context<BeginDeposit>, amount: u64) -> Result<()> {
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(basket.initialized, BasketError::NotInitialized);
+        require!(!basket.deposits_paused, BasketError::DepositsPaused);
+        require!(
+            basket.tokens.len() <= MAX_RESIZED_TOKENS,
+            BasketError::TooManyTokens
+        );
+        require!(
+            basket.tokens.iter().map(|t| t.weight).sum::<u8>() == 100,
+            BasketError::WeightsNotComplete
+        );
+        require!(
+            basket.tokens_initialized(),
+            BasketError::TokenAccountNotInitialized
+        );
+        require!(
+            amount >= basket.minimum_deposit,
+            BasketError::InsufficientDeposit
+        );
+
+        // Enforce the per-user cap (0 means unlimited)
+        let user_deposit = &mut ctx.accounts.user_deposit;
+        user_deposit.user = ctx.accounts.user.key();
+        user_deposit.last_deposit_ts = Clock::get()?.unix_timestamp;
+        user_deposit.total_deposited = user_deposit
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(
+            basket.max_deposit_per_user == 0
+                || user_deposit.total_deposited <= basket.max_deposit_per_user,
+            BasketError::DepositCapExceeded
+        );
+
+        // Enforce the basket-wide cap on lamports above the rent-exempt reserve (0 means unlimited)
+        let idle_lamports = nav::idle_lamports(&basket)?;
+        require!(
+            basket.max_tvl_lamports == 0
+                || idle_lamports
+                    .checked_add(amount)
+                    .ok_or(BasketError::MathOverflow)?
+                    <= basket.max_tvl_lamports,
+            BasketError::TvlCapExceeded
+        );
+
+        // Route the entry fee to the fee recipient and the rest to the basket
+        let fee = nav::to_u64(
+            (amount as u128)
+                .checked_mul(basket.entry_fee_bps as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(BPS_DENOMINATOR as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        let net_amount = amount.checked_sub(fee).ok_or(BasketError::MathOverflow)?;
+        if fee > 0 {
+            anchor_lang::solana_program::program::invoke(
+                &anchor_lang::solana_program::system_instruction::transfer(
+                    &ctx.accounts.user.key(),
+                    &ctx.accounts.fee_recipient.key(),
+                    fee,
+                ),
+                &[
+                    ctx.accounts.user.to_account_info(),
+                    ctx.accounts.fee_recipient.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
+        }
+        anchor_lang::solana_program::program::invoke(
+            &anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.user.key(),
+                &basket.key(),
+                net_amount,
+            ),
+            &[
+                ctx.accounts.user.to_account_info(),
+                basket.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let pending_deposit = &mut ctx.accounts.pending_deposit;
+        pending_deposit.user = ctx.accounts.user.key();
+        pending_deposit.basket = basket.key();
+        pending_deposit.sol_in = amount;
+        pending_deposit.amount = net_amount;
+        pending_deposit.amount_spent = 0;
+        pending_deposit.value_added = 0;
+        pending_deposit.legs_completed = 0;
+        pending_deposit.bump = *ctx.bumps.get("pending_deposit").unwrap();
+
+        basket.pending_deposit_lamports = basket
+            .pending_deposit_lamports
+            .checked_add(net_amount)
+            .ok_or(BasketError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Buy leg `leg_index` of a pending deposit with its weighted share of the
+    /// SOL. Remaining accounts are the leg's route (token account in slot 1)
+    /// followed by the leg's price feed.
+    pub fn deposit_leg(
+        ctx: Context<DepositLeg>,
+        leg_index: u8,
+        jupiter_quote: [u8; 32],
+        slippage_bps: u16,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        token_accounts::create_associated(
+            &ctx.accounts.basket_wsol,
+            &ctx.accounts.basket.to_account_info(),
+            &ctx.accounts.native_mint.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.associated_token_program.to_account_info(),
+        )?;
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(basket.initialized, BasketError::NotInitialized);
+        require!(!basket.deposits_paused, BasketError::DepositsPaused);
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+        let index = leg_index as usize;
+        require!(index < basket.tokens.len(), BasketError::InvalidTokenCount);
+
+        // Zero-weight placeholders take no allocation and have no leg to run
+        let token_info = &basket.tokens[index];
+        require!(token_info.weight > 0, BasketError::ZeroWeightLeg);
+
+        let pending_deposit = &mut ctx.accounts.pending_deposit;
+        require!(
+            pending_deposit.legs_completed & (1 << index) == 0,
+            BasketError::LegAlreadyCompleted
+        );
+        pending_deposit.legs_completed |= 1 << index;
+
+        // The route spends the basket's wSOL and delivers into the leg's account
+        let (price_account, route) = ctx
+            .remaining_accounts
+            .split_last()
+            .ok_or(BasketError::InvalidAccountCount)?;
+        require!(route.len() >= 2, BasketError::InvalidAccountCount);
+        require!(
+            route[0].key() == ctx.accounts.basket_wsol.key()
+                && route[1].key() == token_info.token_account,
+            BasketError::InvalidTokenAccount
+        );
+        require!(
+            price_account.key() == token_info.price_feed,
+            BasketError::InvalidPriceAccount
+        );
+        let price = nav::read_fresh_price(&basket, token_info, price_account)?;
+        let in_amount = nav::to_u64(
+            (pending_deposit.amount as u128)
+                .checked_mul(token_info.weight as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(100)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        basket.pending_deposit_lamports = basket
+            .pending_deposit_lamports
+            .checked_sub(in_amount)
+            .ok_or(BasketError::MathOverflow)?;
+
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+
+        // Wrap the leg's SOL into the basket's wSOL account and swap out of
+        // it, signing as the basket
+        let basket_info = basket.to_account_info();
+        let basket_wsol = ctx.accounts.basket_wsol.to_account_info();
+        let balance_before = nav::load_token_account(&route[1], index)?.amount;
+        jupiter::wrap_lamports(
+            &basket_info,
+            &basket_wsol,
+            &ctx.accounts.token_program.to_account_info(),
+            in_amount,
+        )?;
+        jupiter::swap(
+            ctx.accounts.swap_program.key,
+            basket.swap_discriminator,
+            route,
+            jupiter::RouteSwapParams {
+                in_amount,
+                quote_id: jupiter_quote,
+                slippage_bps,
+            },
+            Some((&basket_key, seeds)),
+        )?;
+        let tokens_received = nav::load_token_account(&route[1], index)?
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(BasketError::SlippageExceeded)?;
+        let min_out = (in_amount as u128)
+            .checked_mul((BPS_DENOMINATOR - slippage_bps as u64) as u128)
+            .ok_or(BasketError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        let leg_value = nav::token_value(tokens_received, &price)?;
+        require!(leg_value as u128 >= min_out, BasketError::SlippageExceeded);
+
+        // Unwrap whatever the route left back into the basket, then hand the
+        // account's rent back to the user who paid it
+        let wsol_rent = Rent::get()?.minimum_balance(basket_wsol.data_len());
+        token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::CloseAccount {
+                account: basket_wsol,
+                destination: basket_info.clone(),
+                authority: basket_info.clone(),
+            },
+            &[seeds],
+        ))?;
+        **basket_info.try_borrow_mut_lamports()? -= wsol_rent;
+        **ctx
+            .accounts
+            .user
+            .to_account_info()
+            .try_borrow_mut_lamports()? += wsol_rent;
+
+        pending_deposit.amount_spent = pending_deposit
+            .amount_spent
+            .checked_add(in_amount)
+            .ok_or(BasketError::MathOverflow)?;
+        pending_deposit.value_added = pending_deposit
+            .value_added
+            .checked_add(leg_value)
+            .ok_or(BasketError::MathOverflow)?;
+
+        drop(basket);
+        Ok(())
+    }
+
+    /// Mint shares for a pending deposit once every leg has run. Shares are
+    /// priced against current NAV less what this deposit added, so other
+    /// deposits and redemptions between the legs don't skew the price.
+    /// Remaining accounts are the basket's token accounts followed by their
+    /// price feeds.
+    pub fn finalize_deposit(ctx: Context<FinalizeDeposit>, minimum_shares_out: u64) -> Result<()> {
+        // Share accounts are created here rather than by `init_if_needed`
+        // constraints, which can't derive Token-2022 addresses
+        let accounts = &ctx.accounts;
+        for (token_account, authority) in [
+            (&accounts.user_basket_token, accounts.user.to_account_info()),
+            (&accounts.basket_share_token, accounts.basket.to_account_info()),
+        ] {
+            token_accounts::create_associated(
+                token_account,
+                &authority,
+                &accounts.basket_mint.to_account_info(),
+                &accounts.user.to_account_info(),
+                &accounts.system_program.to_account_info(),
+                &accounts.token_program.to_account_info(),
+                &accounts.associated_token_program.to_account_info(),
+            )?;
+        }
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        let pending_deposit = &ctx.accounts.pending_deposit;
+        let token_count = basket.tokens.len();
+        require!(
+            pending_deposit.all_legs_completed(&basket.weights()),
+            BasketError::LegsIncomplete
+        );
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        // Rounding dust the legs didn't spend joins NAV with the deposit
+        let unspent = pending_deposit
+            .amount
+            .checked_sub(pending_deposit.amount_spent)
+            .ok_or(BasketError::MathOverflow)?;
+        basket.pending_deposit_lamports = basket
+            .pending_deposit_lamports
+            .checked_sub(unspent)
+            .ok_or(BasketError::MathOverflow)?;
+        let value_added = pending_deposit
+            .value_added
+            .checked_add(unspent)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(value_added > 0, BasketError::InsufficientDeposit);
+        require!(
+            !basket.trips_circuit_breaker(pending_deposit.amount, value_added),
+            BasketError::CircuitBreakerTripped
+        );
+        let nav_before = nav::compute_nav(&basket, token_accounts, price_accounts)?
+            .saturating_sub(value_added);
+
+        // Price shares against NAV; the first deposit bootstraps 1:1
+        let shares_to_mint = basket.shares_for(value_added, nav_before)?;
+
+        // The first deposit locks MINIMUM_LIQUIDITY shares in the basket for good
+        let locked_shares = basket.locked_liquidity(shares_to_mint)?;
+        let user_shares = shares_to_mint - locked_shares;
+        require!(
+            user_shares >= minimum_shares_out,
+            BasketError::SlippageExceeded
+        );
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_add(shares_to_mint)
+            .ok_or(BasketError::MathOverflow)?;
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        if locked_shares > 0 {
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token_interface::MintTo {
+                        mint: ctx.accounts.basket_mint.to_account_info(),
+                        to: ctx.accounts.basket_share_token.to_account_info(),
+                        authority: basket.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                locked_shares,
+            )?;
+        }
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    to: ctx.accounts.user_basket_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            user_shares,
+        )?;
+
+        emit!(DepositEvent {
+            user: ctx.accounts.user.key(),
+            sol_in: pending_deposit.sol_in,
+            shares_minted: user_shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn redeem(
+        ctx: Context<Redeem>,
+        amount: u64,
+        jupiter_quotes: Vec<[u8; 32]>,
+        slippage_bps: u16,
+        minimum_sol_amounts: Vec<u64>,
+        minimum_sol_amount: u64,
+        accounts_per_swap: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.redemptions_paused, BasketError::RedemptionsPaused);
+        UserDeposit::check_hold_period(&ctx.accounts.user_deposit, basket.min_hold_seconds)?;
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+        require!(
+            jupiter_quotes.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+        require!(
+            minimum_sol_amounts.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+        basket.check_swap_count()?;
+        require!(basket.total_supply > 0, BasketError::NoSharesOutstanding);
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+
+        // Validate remaining accounts count: swap accounts for weighted legs,
+        // zero-weight token accounts, then one price account per token
+        let weights = basket.weights();
+        jupiter::swap_account_count(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+        let price_accounts = jupiter::price_accounts(ctx.remaining_accounts, weights.len())?;
+        let balance_accounts =
+            jupiter::balance_accounts(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+
+        // Pre-burn value of the redeemed shares, for comparison with realized proceeds
+        let current_nav = nav::compute_nav(&basket, &balance_accounts, price_accounts)?;
+        let expected_sol = (current_nav as u128)
+            .checked_mul(amount as u128)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(basket.total_supply as u128)
+            .ok_or(BasketError::MathOverflow)?;
+        msg!("Expected SOL out: {}", expected_sol);
+
+        // The user's slice of idle SOL is paid out directly; only token legs are sold
+        let idle_sol_share = nav::to_u64(
+            (nav::idle_lamports(&basket)? as u128)
+                .checked_mul(amount as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(basket.total_supply as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+
+        // Calculate redemption ratio with higher precision
+        let redemption_ratio = basket.redemption_ratio(amount)?;
+
+        // Update state before external calls
+        basket.total_supply = basket
+            .total_supply
+            .checked_sub(amount)
+            .ok_or(BasketError::MathOverflow)?;
+
+        // Burn basket tokens
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    from: ctx.accounts.user_basket_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+        )?;
+
+        // Sell tokens back to SOL, signing as the basket
+        let basket_key = basket.key();
+        let mut total_sol_received: u64 = 0;
+        let mut expected_leg_value: u64 = 0;
+        let mut offset = 0;
+        let mut leg = 0;
+
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            // Zero-weight legs have no route; holdings there can't be sold here
+            // and must go through `redeem_partial` instead
+            if token_info.weight == 0 {
+                let balance = nav::load_token_account(&balance_accounts[i], i)?.amount;
+                if balance > 0 {
+                    msg!("Leg {}: zero-weight {} still holds {}", i, token_info.mint, balance);
+                    return err!(BasketError::TokenBalanceNonZero);
+                }
+                continue;
+            }
+            let swap_accounts = jupiter::route(ctx.remaining_accounts, offset, accounts_per_swap[leg])?;
+            offset += accounts_per_swap[leg] as usize;
+            leg += 1;
+
+            // Verify basket-owned token account mint and owner
+            let token_account = jupiter::balance_account(swap_accounts)?;
+            let token_acc_data = InterfaceAccount::<TokenAccount>::try_from(token_account)?;
+            require!(
+                token_acc_data.mint == token_info.mint,
+                BasketError::InvalidTokenMint
+            );
+            require!(
+                token_acc_data.owner == basket_key,
+                BasketError::InvalidTokenOwner
+            );
+
+            let token_amount = token_info.available(token_acc_data.amount);
+            let redeem_amount = nav::to_u64(
+                (token_amount as u128)
+                    .checked_mul(redemption_ratio)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(MAGNIFIER)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            let price = nav::read_fresh_price(&basket, token_info, &price_accounts[i])?;
+            expected_leg_value = expected_leg_value
+                .checked_add(nav::token_value(redeem_amount, &price)?)
+                .ok_or(BasketError::MathOverflow)?;
+
+            // Execute Jupiter swap
+            let lamports_before = basket.to_account_info().lamports();
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                swap_accounts,
+                jupiter::RouteSwapParams {
+                    in_amount: redeem_amount,
+                    quote_id: jupiter_quotes[i],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+
+            // Track SOL received by this leg alone; a route that spends the
+            // basket's lamports counts as receiving nothing
+            let sol_received = basket
+                .to_account_info()
+                .lamports()
+                .saturating_sub(lamports_before);
+
+            require!(
+                sol_received >= minimum_sol_amounts[i],
+                BasketError::SlippageExceeded
+            );
+
+            total_sol_received = total_sol_received
+                .checked_add(sol_received)
+                .ok_or(BasketError::MathOverflow)?;
+        }
+        require!(
+            !basket.trips_circuit_breaker(expected_leg_value, total_sol_received),
+            BasketError::CircuitBreakerTripped
+        );
+
+        // Proceeds must land within slippage_bps of the NAV-implied amount on
+        // either side, so lamports pushed into the PDA mid-route can't be paid out
+        let proceeds = idle_sol_share
+            .checked_add(total_sol_received)
+            .ok_or(BasketError::MathOverflow)?;
+        let tolerance = expected_sol
+            .checked_mul(slippage_bps as u128)
+            .ok_or(BasketError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        require!(
+            proceeds as u128 >= expected_sol.saturating_sub(tolerance)
+                && proceeds as u128 <= expected_sol + tolerance,
+            BasketError::SlippageExceeded
+        );
+
+        // Split the exit fee from the user's proceeds
+        let fee = basket.exit_fee(proceeds)?;
+        let net_sol = proceeds
+            .checked_sub(fee)
+            .ok_or(BasketError::MathOverflow)?;
+
+        require!(
+            net_sol >= minimum_sol_amount,
+            BasketError::SlippageExceeded
+        );
+
+        // Transfer SOL to user and fee recipient
+        **basket.to_account_info().try_borrow_mut_lamports()? -= proceeds;
+        **ctx
+            .accounts
+            .user
+            .to_account_info()
+            .try_borrow_mut_lamports()? += net_sol;
+        **ctx
+            .accounts
+            .fee_recipient
+            .to_account_info()
+            .try_borrow_mut_lamports()? += fee;
+
+        // Clear reentrancy guard
+        drop(basket);
+
+        emit!(RedeemEvent {
+            user: ctx.accounts.user.key(),
+            shares_burned: amount,
+            sol_out: net_sol,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Redeem `shares` worth of only the legs in `token_mints`. Remaining
+    /// accounts: one swap account group per listed mint (in that order), then
+    /// every token's registered account, then every token's price feed, both
+    /// ordered like `basket.tokens`. Only the oracle value of the legs sold is
+    /// burned; the rest of `shares` stays with the user as their claim on the
+    /// unsold legs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn redeem_partial(
+        ctx: Context<Redeem>,
+        shares: u64,
+        token_mints: Vec<Pubkey>,
+        jupiter_quotes: Vec<[u8; 32]>,
+        slippage_bps: u16,
+        minimum_sol_amount: u64,
+        accounts_per_swap: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.redemptions_paused, BasketError::RedemptionsPaused);
+        UserDeposit::check_hold_period(&ctx.accounts.user_deposit, basket.min_hold_seconds)?;
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+        require!(basket.total_supply > 0, BasketError::NoSharesOutstanding);
+        require!(
+            !token_mints.is_empty()
+                && jupiter_quotes.len() == token_mints.len()
+                && accounts_per_swap.len() == token_mints.len(),
+            BasketError::InvalidTokenCount
+        );
+
+        // Resolve each listed mint to its basket index, rejecting repeats
+        let mut leg_indices: Vec<usize> = Vec::with_capacity(token_mints.len());
+        for mint in token_mints.iter() {
+            let index = basket
+                .tokens
+                .iter()
+                .position(|t| t.mint == *mint)
+                .ok_or(BasketError::TokenNotFound)?;
+            require!(!leg_indices.contains(&index), BasketError::DuplicateToken);
+            leg_indices.push(index);
+        }
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+
+        let token_count = basket.tokens.len();
+        let swap_account_count = accounts_per_swap
+            .iter()
+            .map(|count| *count as usize)
+            .sum::<usize>();
+        require!(
+            accounts_per_swap.iter().all(|count| *count >= 2)
+                && ctx.remaining_accounts.len() == swap_account_count + token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) =
+            ctx.remaining_accounts[swap_account_count..].split_at(token_count);
+        let current_nav = nav::compute_nav(&basket, token_accounts, price_accounts)?;
+        require!(current_nav > 0, BasketError::InvalidNav);
+
+        // The user's pro-rata slice of each listed leg, and its oracle value
+        let mut leg_amounts = Vec::with_capacity(leg_indices.len());
+        let mut leg_values = Vec::with_capacity(leg_indices.len());
+        let mut claim_value: u64 = 0;
+        for index in leg_indices.iter() {
+            let balance = basket.tokens[*index].available(
+                InterfaceAccount::<TokenAccount>::try_from(&token_accounts[*index])?.amount,
+            );
+            let leg_amount = nav::to_u64(
+                (balance as u128)
+                    .checked_mul(shares as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(basket.total_supply as u128)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            let price = nav::read_fresh_price(
+                &basket,
+                &basket.tokens[*index],
+                &price_accounts[*index],
+            )?;
+            let leg_value = nav::token_value(leg_amount, &price)?;
+            claim_value = claim_value
+                .checked_add(leg_value)
+                .ok_or(BasketError::MathOverflow)?;
+            leg_amounts.push(leg_amount);
+            leg_values.push(leg_value);
+        }
+
+        // Burn only the shares backing the value withdrawn, so every other
+        // holder's claim per share is unchanged
+        let shares_burned = nav::to_u64(
+            (claim_value as u128)
+                .checked_mul(basket.total_supply as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(current_nav as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        require!(
+            shares_burned > 0 && shares_burned <= shares,
+            BasketError::InsufficientBalance
+        );
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_sub(shares_burned)
+            .ok_or(BasketError::MathOverflow)?;
+
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    from: ctx.accounts.user_basket_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            shares_burned,
+        )?;
+
+        // Sell the listed legs back to SOL, signing as the basket; each leg
+        // must fetch its oracle value less slippage
+        let initial_basket_lamports = basket.to_account_info().lamports();
+        let mut offset = 0;
+        for (j, index) in leg_indices.iter().enumerate() {
+            let swap_accounts = jupiter::route(ctx.remaining_accounts, offset, accounts_per_swap[j])?;
+            offset += accounts_per_swap[j] as usize;
+            require!(
+                jupiter::balance_account(swap_accounts)?.key()
+                    == basket.tokens[*index].token_account,
+                BasketError::InvalidTokenAccount
+            );
+
+            let lamports_before = basket.to_account_info().lamports();
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                swap_accounts,
+                jupiter::RouteSwapParams {
+                    in_amount: leg_amounts[j],
+                    quote_id: jupiter_quotes[j],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+            let sol_received = basket
+                .to_account_info()
+                .lamports()
+                .checked_sub(lamports_before)
+                .ok_or(BasketError::SlippageExceeded)?;
+            let min_out = (leg_values[j] as u128)
+                .checked_mul((BPS_DENOMINATOR - slippage_bps as u64) as u128)
+                .ok_or(BasketError::MathOverflow)?
+                / BPS_DENOMINATOR as u128;
+            require!(
+                sol_received as u128 >= min_out,
+                BasketError::SlippageExceeded
+            );
+        }
+        let total_sol_received = basket
+            .to_account_info()
+            .lamports()
+            .checked_sub(initial_basket_lamports)
+            .ok_or(BasketError::SlippageExceeded)?;
+
+        // Split the exit fee from the user's proceeds
+        let fee = basket.exit_fee(total_sol_received)?;
+        let net_sol = total_sol_received
+            .checked_sub(fee)
+            .ok_or(BasketError::MathOverflow)?;
+
+        require!(
+            net_sol >= minimum_sol_amount,
+            BasketError::SlippageExceeded
+        );
+
+        **basket.to_account_info().try_borrow_mut_lamports()? -= total_sol_received;
+        **ctx
+            .accounts
+            .user
+            .to_account_info()
+            .try_borrow_mut_lamports()? += net_sol;
+        **ctx
+            .accounts
+            .fee_recipient
+            .to_account_info()
+            .try_borrow_mut_lamports()? += fee;
+
+        // Clear reentrancy guard
+        drop(basket);
+
+        emit!(RedeemEvent {
+            user: ctx.accounts.user.key(),
+            shares_burned,
+            sol_out: net_sol,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// First step of a split redemption: burn `shares` and reserve the user's
+    /// slice of idle SOL and of every holding. Remaining accounts are the
+    /// basket's registered token accounts, ordered like `basket.tokens`.
+    pub fn begin_redeem(ctx: Context<BeginRedeem>, shares: u64) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.redemptions_paused, BasketError::RedemptionsPaused);
+        UserDeposit::check_hold_period(&ctx.accounts.user_deposit, basket.min_hold_seconds)?;
+        require!(basket.total_supply > 0, BasketError::NoSharesOutstanding);
+        require!(shares > 0, BasketError::InsufficientBalance);
+        require!(
+            basket.tokens.len() <= MAX_RESIZED_TOKENS,
+            BasketError::TooManyTokens
+        );
+        require!(
+            ctx.remaining_accounts.len() == basket.tokens.len(),
+            BasketError::InvalidAccountCount
+        );
+
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+        let idle_sol_share = nav::to_u64(
+            (nav::idle_lamports(&basket)? as u128)
+                .checked_mul(shares as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(basket.total_supply as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        let redemption_ratio = basket.redemption_ratio(shares)?;
+
+        let mut legs = Vec::with_capacity(basket.tokens.len());
+        for (i, token_info) in basket.tokens.iter_mut().enumerate() {
+            require!(
+                ctx.remaining_accounts[i].key() == token_info.token_account,
+                BasketError::InvalidTokenAccount
+            );
+            let balance = nav::load_token_account(&ctx.remaining_accounts[i], i)?.amount;
+            let amount = nav::to_u64(
+                (token_info.available(balance) as u128)
+                    .checked_mul(redemption_ratio)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(MAGNIFIER)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            token_info.reserved_amount = token_info
+                .reserved_amount
+                .checked_add(amount)
+                .ok_or(BasketError::MathOverflow)?;
+            legs.push((token_info.mint, amount));
+        }
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_sub(shares)
+            .ok_or(BasketError::MathOverflow)?;
+        basket.pending_redeem_lamports = basket
+            .pending_redeem_lamports
+            .checked_add(idle_sol_share)
+            .ok_or(BasketError::MathOverflow)?;
+
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    from: ctx.accounts.user_basket_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            shares,
+        )?;
+        drop(basket);
+
+        let pending_redeem = &mut ctx.accounts.pending_redeem;
+        pending_redeem.user = ctx.accounts.user.key();
+        pending_redeem.basket = ctx.accounts.basket.key();
+        pending_redeem.shares = shares;
+        pending_redeem.legs = legs;
+        pending_redeem.sol_out = idle_sol_share;
+        pending_redeem.legs_completed = 0;
+        pending_redeem.bump = *ctx.bumps.get("pending_redeem").unwrap();
+        Ok(())
+    }
+
+    /// Sell leg `leg_index` of a pending redemption into the basket.
+    /// Remaining accounts are the leg's sell route (token account in the
+    /// balance slot) followed by the leg's price feed; legs with nothing
+    /// reserved take none.
+    pub fn redeem_leg(
+        ctx: Context<RedeemLeg>,
+        leg_index: u8,
+        jupiter_quote: [u8; 32],
+        slippage_bps: u16,
+    ) -> Result<()> {
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        let basket = &mut ctx.accounts.basket;
+        require!(!basket.redemptions_paused, BasketError::RedemptionsPaused);
+        require!(
+            slippage_bps <= basket.max_slippage_bps,
+            BasketError::SlippageTooHigh
+        );
+
+        let pending_redeem = &mut ctx.accounts.pending_redeem;
+        let index = leg_index as usize;
+        require!(index < pending_redeem.legs.len(), BasketError::InvalidTokenCount);
+        require!(
+            pending_redeem.legs_completed & (1 << index) == 0,
+            BasketError::LegAlreadyCompleted
+        );
+        pending_redeem.legs_completed |= 1 << index;
+        let (mint, amount) = pending_redeem.legs[index];
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let token_index = basket
+            .tokens
+            .iter()
+            .position(|t| t.mint == mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        let (price_account, route) = ctx
+            .remaining_accounts
+            .split_last()
+            .ok_or(BasketError::InvalidAccountCount)?;
+        require!(route.len() >= 2, BasketError::InvalidAccountCount);
+        let token_info = &basket.tokens[token_index];
+        require!(
+            jupiter::balance_account(route)?.key() == token_info.token_account,
+            BasketError::InvalidTokenAccount
+        );
+        require!(
+            price_account.key() == token_info.price_feed,
+            BasketError::InvalidPriceAccount
+        );
+        let price = nav::read_fresh_price(basket, token_info, price_account)?;
+
+        let mut basket = ReentrancyGuard::acquire(basket)?;
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+
+        // Sell the reserved slice, signing as the basket
+        let lamports_before = basket.to_account_info().lamports();
+        jupiter::swap(
+            ctx.accounts.swap_program.key,
+            basket.swap_discriminator,
+            route,
+            jupiter::RouteSwapParams {
+                in_amount: amount,
+                quote_id: jupiter_quote,
+                slippage_bps,
+            },
+            Some((&basket_key, seeds)),
+        )?;
+        let sol_received = basket
+            .to_account_info()
+            .lamports()
+            .checked_sub(lamports_before)
+            .ok_or(BasketError::SlippageExceeded)?;
+        let min_out = (nav::token_value(amount, &price)? as u128)
+            .checked_mul((BPS_DENOMINATOR - slippage_bps as u64) as u128)
+            .ok_or(BasketError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        require!(sol_received as u128 >= min_out, BasketError::SlippageExceeded);
+
+        let token_info = &mut basket.tokens[token_index];
+        token_info.reserved_amount = token_info
+            .reserved_amount
+            .checked_sub(amount)
+            .ok_or(BasketError::MathOverflow)?;
+        basket.pending_redeem_lamports = basket
+            .pending_redeem_lamports
+            .checked_add(sol_received)
+            .ok_or(BasketError::MathOverflow)?;
+        drop(basket);
+
+        pending_redeem.sol_out = pending_redeem
+            .sol_out
+            .checked_add(sol_received)
+            .ok_or(BasketError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Pay out a pending redemption once every leg has sold
+    pub fn finalize_redeem(ctx: Context<FinalizeRedeem>, minimum_sol_amount: u64) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        let pending_redeem = &ctx.accounts.pending_redeem;
+        require!(
+            pending_redeem.all_legs_completed(),
+            BasketError::LegsIncomplete
+        );
+
+        let proceeds = pending_redeem.sol_out;
+        let fee = basket.exit_fee(proceeds)?;
+        let net_sol = proceeds
+            .checked_sub(fee)
+            .ok_or(BasketError::MathOverflow)?;
+        require!(
+            net_sol >= minimum_sol_amount,
+            BasketError::SlippageExceeded
+        );
+        basket.pending_redeem_lamports = basket
+            .pending_redeem_lamports
+            .checked_sub(proceeds)
+            .ok_or(BasketError::MathOverflow)?;
+
+        **basket.to_account_info().try_borrow_mut_lamports()? -= proceeds;
+        **ctx
+            .accounts
+            .user
+            .to_account_info()
+            .try_borrow_mut_lamports()? += net_sol;
+        **ctx
+            .accounts
+            .fee_recipient
+            .to_account_info()
+            .try_borrow_mut_lamports()? += fee;
+
+        emit!(RedeemEvent {
+            user: ctx.accounts.user.key(),
+            shares_burned: pending_redeem.shares,
+            sol_out: net_sol,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn queue_withdraw_authority_sol(
+        ctx: Context<WithdrawAuthoritySol>,
+        amount: u64,
+    ) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        basket.queue_action(TimelockedAction::WithdrawAuthoritySol { amount })
+    }
+
+    pub fn execute_withdraw_authority_sol(ctx: Context<WithdrawAuthoritySol>) -> Result<()> {
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        let amount = match basket.take_ready_action()? {
+            TimelockedAction::WithdrawAuthoritySol { amount } => amount,
+            _ => return err!(BasketError::NoPendingAction),
+        };
+
+        let basket_lamports = basket.to_account_info().lamports();
+        require!(basket_lamports >= amount, BasketError::InsufficientBalance);
+        let rent_exempt_minimum =
+            Rent::get()?.minimum_balance(basket.to_account_info().data_len());
+        require!(
+            basket_lamports - amount >= rent_exempt_minimum,
+            BasketError::WouldBreakRentExemption
+        );
+
+        // While shares are outstanding every idle lamport is part of the NAV
+        // backing them; only SOL in a basket nobody holds shares of is surplus
+        let surplus = if basket.total_supply == 0 {
+            nav::idle_lamports(basket)?
+        } else {
+            0
+        };
+        require!(amount <= surplus, BasketError::WouldUndercollateralize);
+
+        **basket.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx
+            .accounts
+            .authority
+            .to_account_info()
+            .try_borrow_mut_lamports()? += amount;
+
+        emit!(AuthoritySolWithdrawnEvent {
+            authority: ctx.accounts.authority.key(),
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn collect_management_fee(ctx: Context<CollectManagementFee>) -> Result<()> {
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        let now = Clock::get()?.unix_timestamp;
+        let elapsed = now.saturating_sub(basket.last_fee_collection) as u64;
+
+        // Shares diluting holders by management_fee_bps per year, pro rata
+        let fee_shares = nav::to_u64(
+            (basket.total_supply as u128)
+                .checked_mul(basket.management_fee_bps as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_mul(elapsed as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+
+        basket.last_fee_collection = now;
+        if fee_shares == 0 {
+            return Ok(());
+        }
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_add(fee_shares)
+            .ok_or(BasketError::MathOverflow)?;
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    to: ctx.accounts.fee_recipient_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            fee_shares,
+        )?;
+
+        Ok(())
+    }
+
+    /// Remaining accounts: each token's registered account, then each token's
+    /// price feed, both ordered like `basket.tokens`
+    pub fn collect_performance_fee(ctx: Context<CollectManagementFee>) -> Result<()> {
+        let token_count = ctx.accounts.basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+        let nav = nav::compute_nav(&ctx.accounts.basket, token_accounts, price_accounts)?;
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let mut basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        if basket.total_supply == 0 {
+            return Ok(());
+        }
+        let supply = basket.total_supply as u128;
+        let price_per_share = (nav as u128)
+            .checked_mul(MAGNIFIER)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(supply)
+            .ok_or(BasketError::MathOverflow)?;
+        let high_water_mark = basket.high_water_mark;
+        if price_per_share <= high_water_mark {
+            return Ok(());
+        }
+
+        // Fee is performance_fee_bps of the gain above the mark, paid by
+        // minting shares worth that many lamports at the current NAV
+        // Descale before applying the fee so the product stays inside u128
+        let fee_lamports = (price_per_share - high_water_mark)
+            .checked_mul(supply)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(MAGNIFIER)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_mul(basket.performance_fee_bps as u128)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(BasketError::MathOverflow)?;
+        let fee_shares = fee_lamports
+            .checked_mul(supply)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(
+                (nav as u128)
+                    .checked_sub(fee_lamports)
+                    .ok_or(BasketError::MathOverflow)?,
+            )
+            .ok_or(BasketError::MathOverflow)?;
+        let fee_shares = u64::try_from(fee_shares).map_err(|_| error!(BasketError::MathOverflow))?;
+
+        basket.total_supply = basket
+            .total_supply
+            .checked_add(fee_shares)
+            .ok_or(BasketError::MathOverflow)?;
+
+        // The mark moves to the post-fee price so the same gain is never charged twice
+        basket.high_water_mark = (nav as u128)
+            .checked_mul(MAGNIFIER)
+            .ok_or(BasketError::MathOverflow)?
+            .checked_div(basket.total_supply as u128)
+            .ok_or(BasketError::MathOverflow)?;
+
+        if fee_shares == 0 {
+            return Ok(());
+        }
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.basket_mint.to_account_info(),
+                    to: ctx.accounts.fee_recipient_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            fee_shares,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_composition(ctx: Context<GetComposition>) -> Result<BasketComposition> {
+        let basket = &ctx.accounts.basket;
+        let composition = BasketComposition {
+            tokens: basket.tokens.clone(),
+            total_supply: basket.total_supply,
+            deposits_paused: basket.deposits_paused,
+            redemptions_paused: basket.redemptions_paused,
+        };
+
+        // Logged as base64 for log readers; Anchor also publishes the return
+        // value through set_return_data for simulateTransaction callers
+        solana_program::log::sol_log_data(&[&composition.try_to_vec()?]);
+
+        Ok(composition)
+    }
+
+    /// Remaining-account contract for `deposit` and `redeem`: one route per
+    /// weighted leg with the leg's token account in `balance_slot`, then the
+    /// token account of each zero-weight leg, then every price feed
+    pub fn describe_swap_accounts(ctx: Context<DescribeSwapAccounts>) -> Result<SwapAccountLayout> {
+        let basket = &ctx.accounts.basket;
+        Ok(SwapAccountLayout {
+            legs: basket
+                .tokens
+                .iter()
+                .map(|token| SwapLeg {
+                    mint: token.mint,
+                    token_account: token.token_account,
+                    price_feed: token.price_feed,
+                    stride: if token.weight == 0 {
+                        0
+                    } else {
+                        jupiter::DEFAULT_ACCOUNTS_PER_SWAP
+                    },
+                })
+                .collect(),
+            balance_slot: jupiter::BALANCE_SLOT,
+        })
+    }
+
+    /// Check that every token account in remaining accounts holds a basket
+    /// mint. Empty orphans are only logged; funded ones fail.
+    pub fn verify_holdings(ctx: Context<VerifyHoldings>) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        let mut funded_orphans = 0;
+        for (i, account) in ctx.remaining_accounts.iter().enumerate() {
+            let token_account = nav::load_token_account(account, i)?;
+            require!(
+                token_account.owner == basket.key(),
+                BasketError::InvalidTokenOwner
+            );
+            if basket.tokens.iter().any(|t| t.mint == token_account.mint) {
+                continue;
+            }
+            msg!(
+                "Orphan holding: {} holds {} of {}",
+                account.key(),
+                token_account.amount,
+                token_account.mint
+            );
+            if token_account.amount > 0 {
+                funded_orphans += 1;
+            }
+        }
+        require!(funded_orphans == 0, BasketError::UnexpectedHolding);
+        Ok(())
+    }
+
+    /// Remaining accounts: each token's registered account, then each token's
+    /// price feed, both ordered like `basket.tokens`
+    pub fn get_price_per_share(ctx: Context<GetPricePerShare>) -> Result<PricePerShare> {
+        let basket = &ctx.accounts.basket;
+        let token_count = basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        let nav = nav::compute_nav(basket, token_accounts, price_accounts)?;
+        let token_balances = token_accounts
+            .iter()
+            .map(|account| Ok(InterfaceAccount::<TokenAccount>::try_from(account)?.amount))
+            .collect::<Result<Vec<u64>>>()?;
+
+        let price_per_share = if basket.total_supply == 0 {
+            MAGNIFIER
+        } else {
+            (nav as u128)
+                .checked_mul(MAGNIFIER)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(basket.total_supply as u128)
+                .ok_or(BasketError::MathOverflow)?
+        };
+
+        Ok(PricePerShare {
+            price_per_share,
+            nav,
+            total_supply: basket.total_supply,
+            token_balances,
+        })
+    }
+
+    pub fn get_basket_value(ctx: Context<GetBasketValue>) -> Result<BasketValue> {
+        let basket = &ctx.accounts.basket;
+        let token_count = basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        let idle_lamports = nav::idle_lamports(basket)?;
+        let leg_values = nav::compute_leg_values(basket, token_accounts, price_accounts)?;
+        let nav = leg_values
+            .iter()
+            .try_fold(idle_lamports, |nav, value| nav.checked_add(*value))
+            .ok_or(BasketError::MathOverflow)?;
+
+        Ok(BasketValue {
+            nav,
+            idle_lamports,
+            token_values: basket
+                .tokens
+                .iter()
+                .map(|t| t.mint)
+                .zip(leg_values)
+                .collect(),
+        })
+    }
+
+    /// Each token's oracle-priced share of the basket's token value minus its
+    /// target weight, in signed basis points. Remaining accounts: each token's
+    /// registered account, then each token's price feed, both ordered like
+    /// `basket.tokens`. Baskets holding nothing report zero drift.
+    pub fn get_weight_drift(ctx: Context<GetWeightDrift>) -> Result<Vec<(Pubkey, i16)>> {
+        let basket = &ctx.accounts.basket;
+        let token_count = basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        let leg_values = nav::compute_leg_values(basket, token_accounts, price_accounts)?;
+        let total_value = leg_values
+            .iter()
+            .try_fold(0u64, |total, value| total.checked_add(*value))
+            .ok_or(BasketError::MathOverflow)?;
+
+        basket
+            .tokens
+            .iter()
+            .zip(leg_values)
+            .map(|(token_info, value)| {
+                if total_value == 0 {
+                    return Ok((token_info.mint, 0));
+                }
+                let actual_bps = (value as u128)
+                    .checked_mul(BPS_DENOMINATOR as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(total_value as u128)
+                    .ok_or(BasketError::MathOverflow)? as i64;
+                let target_bps = token_info.weight as i64 * 100;
+                Ok((token_info.mint, (actual_bps - target_bps) as i16))
+            })
+            .collect()
+    }
+
+    /// Oracle-priced SOL that redeeming `shares` would pay, before and after
+    /// the exit fee, using the same per-leg amounts `redeem` sells. Remaining
+    /// accounts: each token's registered account, then each token's price
+    /// feed, both ordered like `basket.tokens`.
+    pub fn preview_redeem(ctx: Context<PreviewRedeem>, shares: u64) -> Result<RedeemPreview> {
+        let basket = &ctx.accounts.basket;
+        let token_count = basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        require!(basket.total_supply > 0, BasketError::NoSharesOutstanding);
+        require!(shares <= basket.total_supply, BasketError::InsufficientBalance);
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        let idle_sol_share = nav::to_u64(
+            (nav::idle_lamports(basket)? as u128)
+                .checked_mul(shares as u128)
+                .ok_or(BasketError::MathOverflow)?
+                .checked_div(basket.total_supply as u128)
+                .ok_or(BasketError::MathOverflow)?,
+        )?;
+        let redemption_ratio = basket.redemption_ratio(shares)?;
+
+        let mut gross_sol = idle_sol_share;
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            require!(
+                token_accounts[i].key() == token_info.token_account,
+                BasketError::InvalidTokenAccount
+            );
+            let token_amount =
+                token_info.available(nav::load_token_account(&token_accounts[i], i)?.amount);
+            let redeem_amount = nav::to_u64(
+                (token_amount as u128)
+                    .checked_mul(redemption_ratio)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(MAGNIFIER)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            let price = nav::read_fresh_price(basket, token_info, &price_accounts[i])?;
+            gross_sol = gross_sol
+                .checked_add(nav::token_value(redeem_amount, &price)?)
+                .ok_or(BasketError::MathOverflow)?;
+        }
+
+        let fee = basket.exit_fee(gross_sol)?;
+        Ok(RedeemPreview {
+            shares,
+            gross_sol,
+            fee,
+            net_sol: gross_sol.checked_sub(fee).ok_or(BasketError::MathOverflow)?,
+        })
+    }
+
+    pub fn get_user_position(ctx: Context<GetUserPosition>, user: Pubkey) -> Result<UserPosition> {
+        let basket = &ctx.accounts.basket;
+        let token_count = basket.tokens.len();
+        require!(
+            ctx.remaining_accounts.len() == token_count * 2,
+            BasketError::InvalidAccountCount
+        );
+        let (token_accounts, price_accounts) = ctx.remaining_accounts.split_at(token_count);
+
+        // A user without a basket-token ATA holds no shares
+        let user_basket_token = &ctx.accounts.user_basket_token;
+        let shares = if user_basket_token.data_is_empty() {
+            0
+        } else {
+            InterfaceAccount::<TokenAccount>::try_from(&user_basket_token.to_account_info())?
+                .amount
+        };
+
+        let mut position = UserPosition {
+            user,
+            shares,
+            total_supply: basket.total_supply,
+            token_amounts: vec![0; token_count],
+            value_lamports: 0,
+        };
+        if shares == 0 || basket.total_supply == 0 {
+            return Ok(position);
+        }
+
+        let pro_rata = |amount: u64| -> Result<u64> {
+            nav::to_u64(
+                (amount as u128)
+                    .checked_mul(shares as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(basket.total_supply as u128)
+                    .ok_or(BasketError::MathOverflow)?,
+            )
+        };
+
+        let nav = nav::compute_nav(basket, token_accounts, price_accounts)?;
+        position.value_lamports = pro_rata(nav)?;
+        for (i, account) in token_accounts.iter().enumerate() {
+            // Tokens reserved for pending split redemptions belong to those users
+            let balance = InterfaceAccount::<TokenAccount>::try_from(account)?.amount;
+            position.token_amounts[i] = pro_rata(basket.tokens[i].available(balance))?;
+        }
+
+        Ok(position)
+    }
+
+    /// Headroom left under the basket's TVL cap and `user`'s per-user cap,
+    /// measured the way `deposit` enforces them
+    pub fn get_deposit_capacity(
+        ctx: Context<GetDepositCapacity>,
+        _user: Pubkey,
+    ) -> Result<DepositCapacity> {
+        let basket = &ctx.accounts.basket;
+
+        let tvl_remaining = if basket.max_tvl_lamports == 0 {
+            u64::MAX
+        } else {
+            let basket_info = basket.to_account_info();
+            let rent_exempt_lamports = Rent::get()?.minimum_balance(basket_info.data_len());
+            let tvl = basket_info.lamports().saturating_sub(rent_exempt_lamports);
+            basket.max_tvl_lamports.saturating_sub(tvl)
+        };
+
+        // A user without a deposit record hasn't used any of their cap
+        let user_remaining = if basket.max_deposit_per_user == 0 {
+            u64::MAX
+        } else {
+            let user_deposit = &ctx.accounts.user_deposit;
+            let total_deposited = if user_deposit.data_is_empty() {
+                0
+            } else {
+                Account::<UserDeposit>::try_from(&user_deposit.to_account_info())?.total_deposited
+            };
+            basket.max_deposit_per_user.saturating_sub(total_deposited)
+        };
+
+        Ok(DepositCapacity {
+            tvl_remaining,
+            user_remaining,
+        })
+    }
+
+    /// Report which of the basket's invariants fail as `HEALTH_*` bits.
+    /// Reports only: a broken basket returns nonzero rather than reverting.
+    pub fn check_health(ctx: Context<CheckHealth>) -> Result<u8> {
+        let basket = &ctx.accounts.basket;
+        let mut health_status = 0;
+
+        if basket.tokens.iter().map(|t| t.weight as u16).sum::<u16>() != 100 {
+            health_status |= HEALTH_WEIGHTS_INCOMPLETE;
+        }
+        if basket.total_supply != ctx.accounts.basket_mint.supply {
+            health_status |= HEALTH_SUPPLY_MISMATCH;
+        }
+        if basket.reentrancy_guard {
+            health_status |= HEALTH_REENTRANCY_GUARD_SET;
+        }
+        if !basket.tokens_initialized() {
+            health_status |= HEALTH_TOKEN_ACCOUNT_MISSING;
+        }
+
+        Ok(health_status)
+    }
+
+    /// Config and current balance of one token. Remaining accounts: the
+    /// token's registered account.
+    pub fn get_token_info(ctx: Context<GetTokenInfo>, mint: Pubkey) -> Result<TokenLookup> {
+        let basket = &ctx.accounts.basket;
+        let (index, info) = basket
+            .tokens
+            .iter()
+            .enumerate()
+            .find(|(_, t)| t.mint == mint)
+            .ok_or(BasketError::TokenNotFound)?;
+        require!(
+            ctx.remaining_accounts.len() == 1,
+            BasketError::InvalidAccountCount
+        );
+        require!(
+            ctx.remaining_accounts[0].key() == info.token_account,
+            BasketError::InvalidTokenAccount
+        );
+        let balance = nav::load_token_account(&ctx.remaining_accounts[0], index)?.amount;
+
+        Ok(TokenLookup {
+            info: info.clone(),
+            balance,
+        })
+    }
+
+    /// Run `deposit`'s precondition checks against a remaining-account
+    /// layout without moving any SOL or invoking any swap, failing with the
+    /// error the deposit itself would hit
+    pub fn validate_deposit(
+        ctx: Context<ValidateDeposit>,
+        amount: u64,
+        minimum_token_amounts: Vec<u64>,
+        accounts_per_swap: Vec<u8>,
+    ) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        require!(basket.initialized, BasketError::NotInitialized);
+        require!(!basket.deposits_paused, BasketError::DepositsPaused);
+        require!(
+            basket.tokens.iter().map(|t| t.weight).sum::<u8>() == 100,
+            BasketError::WeightsNotComplete
+        );
+        require!(
+            basket.tokens_initialized(),
+            BasketError::TokenAccountNotInitialized
+        );
+        basket.check_swap_count()?;
+        require!(
+            amount >= basket.minimum_deposit,
+            BasketError::InsufficientDeposit
+        );
+        require!(
+            minimum_token_amounts.len() == basket.tokens.len(),
+            BasketError::InvalidTokenCount
+        );
+
+        let weights = basket.weights();
+        jupiter::swap_account_count(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+        let price_accounts = jupiter::price_accounts(ctx.remaining_accounts, weights.len())?;
+        let balance_accounts =
+            jupiter::balance_accounts(ctx.remaining_accounts, &weights, &accounts_per_swap)?;
+
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            let token_acc_data = nav::load_token_account(&balance_accounts[i], i)?;
+            require!(
+                token_acc_data.mint == token_info.mint,
+                BasketError::InvalidTokenMint
+            );
+            require!(
+                token_acc_data.owner == basket.key(),
+                BasketError::InvalidTokenOwner
+            );
+        }
+
+        // The deposit values the basket before buying, so its feeds must price
+        nav::compute_nav(basket, &balance_accounts, price_accounts)?;
+        Ok(())
+    }
+
+    pub fn emergency_withdraw(ctx: Context<EmergencyWithdraw>) -> Result<()> {
+        // Set reentrancy guard; cleared when `basket` drops
+        let basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(basket.is_halted(), BasketError::ProgramNotPaused);
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+
+        // Tokens reserved for pending split redemptions belong to their redeemers
+        let balance = ctx.accounts.basket_token.amount;
+        let amount = basket
+            .tokens
+            .iter()
+            .find(|t| t.mint == ctx.accounts.basket_token.mint)
+            .map_or(balance, |t| t.available(balance));
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::TransferChecked {
+                    from: ctx.accounts.basket_token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.authority_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sweep a token the basket holds but does not track (an airdrop or a
+    /// mistaken transfer) to the authority. Backing tokens stay put.
+    pub fn withdraw_token(ctx: Context<WithdrawToken>, amount: u64) -> Result<()> {
+        // Set reentrancy guard; cleared when `basket` drops
+        let basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        let mint = ctx.accounts.mint.key();
+        require!(
+            !basket.tokens.iter().any(|t| t.mint == mint),
+            BasketError::CannotWithdrawBackingToken
+        );
+        // The basket's own shares hold the locked MINIMUM_LIQUIDITY, and its
+        // wSOL is deposit capital in flight
+        let basket_id = basket.basket_id.to_le_bytes();
+        let (basket_mint, _) =
+            Pubkey::find_program_address(&[b"basket_mint", basket_id.as_ref()], ctx.program_id);
+        require!(
+            mint != basket_mint
+                && mint != anchor_spl::token::spl_token::native_mint::ID
+                && mint != anchor_spl::token_2022::spl_token_2022::native_mint::ID,
+            BasketError::CannotWithdrawProtectedToken
+        );
+        require!(
+            ctx.accounts.basket_token.amount >= amount,
+            BasketError::InsufficientBalance
+        );
+
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::TransferChecked {
+                    from: ctx.accounts.basket_token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.authority_token.to_account_info(),
+                    authority: basket.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn rebalance(
+        ctx: Context<Rebalance>,
+        from_mint: Pubkey,
+        to_mint: Pubkey,
+        in_amount: u64,
+        jupiter_quote: [u8; 32],
+        slippage_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts.basket.start_rebalance()?;
+        // Set reentrancy guard; cleared when `basket` drops
+        let basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        require!(from_mint != to_mint, BasketError::DuplicateToken);
+        require!(
+            basket.tokens.iter().any(|t| t.mint == from_mint)
+                && basket.tokens.iter().any(|t| t.mint == to_mint),
+            BasketError::TokenNotFound
+        );
+        require!(
+            basket
+                .tokens
+                .iter()
+                .any(|t| t.mint == from_mint && t.token_account == ctx.accounts.from_token.key())
+                && basket
+                    .tokens
+                    .iter()
+                    .any(|t| t.mint == to_mint && t.token_account == ctx.accounts.to_token.key()),
+            BasketError::InvalidTokenAccount
+        );
+        let from_reserved = basket
+            .tokens
+            .iter()
+            .find(|t| t.mint == from_mint)
+            .map_or(0, |t| t.reserved_amount);
+        require!(
+            ctx.accounts.from_token.amount.saturating_sub(from_reserved) >= in_amount,
+            BasketError::InsufficientBalance
+        );
+
+        let from_before = ctx.accounts.from_token.amount;
+        let to_before = ctx.accounts.to_token.amount;
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        jupiter::swap(
+            ctx.accounts.swap_program.key,
+            basket.swap_discriminator,
+            ctx.remaining_accounts,
+            jupiter::RouteSwapParams {
+                in_amount,
+                quote_id: jupiter_quote,
+                slippage_bps,
+            },
+            Some((&basket.key(), seeds)),
+        )?;
+
+        ctx.accounts.from_token.reload()?;
+        ctx.accounts.to_token.reload()?;
+        require!(
+            ctx.accounts.from_token.amount >= from_before.saturating_sub(in_amount),
+            BasketError::InsufficientBalance
+        );
+        require!(
+            ctx.accounts.to_token.amount > to_before,
+            BasketError::SlippageExceeded
+        );
+
+        Ok(())
+    }
+
+    /// Realign every leg to its target weight of NAV in one call: over-weight
+    /// legs are sold to SOL first, then the SOL buys under-weight legs.
+    /// Remaining accounts: every token's registered account, every token's
+    /// price feed (both ordered like `basket.tokens`), then one route per token
+    /// of `accounts_per_swap[i]` accounts, 0 leaving that leg alone. Sell routes
+    /// take the token account in the balance slot and pay SOL into the basket;
+    /// buy routes spend the basket's wSOL account in their first account and
+    /// deliver into the token account in the balance slot.
+    pub fn rebalance_to_weights(
+        ctx: Context<RebalanceToWeights>,
+        jupiter_quotes: Vec<[u8; 32]>,
+        slippage_bps: u16,
+        accounts_per_swap: Vec<u8>,
+    ) -> Result<()> {
+        token_accounts::create_associated(
+            &ctx.accounts.basket_wsol,
+            &ctx.accounts.basket.to_account_info(),
+            &ctx.accounts.native_mint.to_account_info(),
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.associated_token_program.to_account_info(),
+        )?;
+
+        let basket = &mut ctx.accounts.basket;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(!basket.is_halted(), BasketError::ProgramPaused);
+        basket.start_rebalance()?;
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        require!(
+            basket.tokens.iter().map(|t| t.weight).sum::<u8>() == 100,
+            BasketError::WeightsNotComplete
+        );
+        let token_count = basket.tokens.len();
+        require!(
+            jupiter_quotes.len() == token_count && accounts_per_swap.len() == token_count,
+            BasketError::InvalidTokenCount
+        );
+        let route_account_count = accounts_per_swap
+            .iter()
+            .map(|count| *count as usize)
+            .sum::<usize>();
+        require!(
+            accounts_per_swap.iter().all(|count| *count == 0 || *count >= 2)
+                && ctx.remaining_accounts.len() == token_count * 2 + route_account_count,
+            BasketError::InvalidAccountCount
+        );
+
+        // Set reentrancy guard; cleared when `basket` drops
+        let basket = ReentrancyGuard::acquire(basket)?;
+
+        let (token_accounts, rest) = ctx.remaining_accounts.split_at(token_count);
+        let (price_accounts, route_accounts) = rest.split_at(token_count);
+        let nav = nav::compute_nav(&basket, token_accounts, price_accounts)?;
+
+        // Each leg's holdings, price, current value and target value
+        let mut legs = Vec::with_capacity(token_count);
+        let mut offset = 0;
+        for (i, token_info) in basket.tokens.iter().enumerate() {
+            let balance =
+                token_info.available(nav::load_token_account(&token_accounts[i], i)?.amount);
+            let price = nav::read_fresh_price(&basket, token_info, &price_accounts[i])?;
+            let value = nav::token_value(balance, &price)?;
+            let target = nav::to_u64(
+                (nav as u128)
+                    .checked_mul(token_info.weight as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(100)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+            let route = jupiter::route(route_accounts, offset, accounts_per_swap[i])?;
+            offset += accounts_per_swap[i] as usize;
+            legs.push((token_info.token_account, balance, price, value, target, route));
+        }
+
+        let min_out = |expected: u64| -> u64 {
+            ((expected as u128) * (BPS_DENOMINATOR - slippage_bps as u64) as u128
+                / BPS_DENOMINATOR as u128) as u64
+        };
+
+        let basket_info = basket.to_account_info();
+        let basket_key = basket.key();
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+
+        // Sell the excess of over-weight legs first so the proceeds fund the buys
+        for (i, (token_account, balance, _, value, target, route)) in legs.iter().enumerate() {
+            if route.is_empty() || value <= target {
+                continue;
+            }
+            require!(
+                jupiter::balance_account(route)?.key() == *token_account,
+                BasketError::InvalidTokenAccount
+            );
+            let excess = value - target;
+            let in_amount = nav::to_u64(
+                (*balance as u128)
+                    .checked_mul(excess as u128)
+                    .ok_or(BasketError::MathOverflow)?
+                    .checked_div(*value as u128)
+                    .ok_or(BasketError::MathOverflow)?,
+            )?;
+
+            let lamports_before = basket_info.lamports();
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                route,
+                jupiter::RouteSwapParams {
+                    in_amount,
+                    quote_id: jupiter_quotes[i],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+            let sol_received = basket_info
+                .lamports()
+                .checked_sub(lamports_before)
+                .ok_or(BasketError::SlippageExceeded)?;
+            require!(
+                sol_received >= min_out(excess),
+                BasketError::SlippageExceeded
+            );
+        }
+
+        // Spend idle SOL on under-weight legs, as far as it stretches
+        let reserved_lamports = Rent::get()?
+            .minimum_balance(basket_info.data_len())
+            .saturating_add(basket.pending_deposit_lamports)
+            .saturating_add(basket.pending_redeem_lamports);
+        let basket_wsol = ctx.accounts.basket_wsol.to_account_info();
+        for (i, (token_account, _, price, value, target, route)) in legs.iter().enumerate() {
+            if route.is_empty() || value >= target {
+                continue;
+            }
+            require!(
+                route[0].key() == basket_wsol.key() && route[1].key() == *token_account,
+                BasketError::InvalidTokenAccount
+            );
+            let available = basket_info.lamports().saturating_sub(reserved_lamports);
+            let in_amount = (target - value).min(available);
+            if in_amount == 0 {
+                continue;
+            }
+
+            let balance_before = nav::load_token_account(&route[1], i)?.amount;
+            jupiter::wrap_lamports(
+                &basket_info,
+                &basket_wsol,
+                &ctx.accounts.token_program.to_account_info(),
+                in_amount,
+            )?;
+            jupiter::swap(
+                ctx.accounts.swap_program.key,
+                basket.swap_discriminator,
+                route,
+                jupiter::RouteSwapParams {
+                    in_amount,
+                    quote_id: jupiter_quotes[i],
+                    slippage_bps,
+                },
+                Some((&basket_key, seeds)),
+            )?;
+            let tokens_received = nav::load_token_account(&route[1], i)?
+                .amount
+                .checked_sub(balance_before)
+                .ok_or(BasketError::SlippageExceeded)?;
+            require!(
+                nav::token_value(tokens_received, price)? >= min_out(in_amount),
+                BasketError::SlippageExceeded
+            );
+        }
+
+        // Unwrap whatever the buys left back into the basket, then hand the
+        // account's rent back to the authority who paid it
+        let wsol_rent = Rent::get()?.minimum_balance(basket_wsol.data_len());
+        token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::CloseAccount {
+                account: basket_wsol,
+                destination: basket_info.clone(),
+                authority: basket_info.clone(),
+            },
+            &[seeds],
+        ))?;
+        **basket_info.try_borrow_mut_lamports()? -= wsol_rent;
+        **ctx
+            .accounts
+            .authority
+            .to_account_info()
+            .try_borrow_mut_lamports()? += wsol_rent;
+
+        Ok(())
+    }
+
+    pub fn sweep_dust(
+        ctx: Context<SweepDust>,
+        token_mint: Pubkey,
+        jupiter_quote: [u8; 32],
+        slippage_bps: u16,
+    ) -> Result<()> {
+        // Set reentrancy guard; cleared when `basket` drops
+        let basket = ReentrancyGuard::acquire(&mut ctx.accounts.basket)?;
+        require!(
+            basket.authority == ctx.accounts.authority.key(),
+            BasketError::Unauthorized
+        );
+        require!(
+            slippage_bps as u64 <= BPS_DENOMINATOR,
+            BasketError::InvalidSlippage
+        );
+        // Removed tokens are allowed: their leftover holdings are what this
+        // cleans up. Weighted tokens back shares and aren't dust.
+        require!(
+            ctx.accounts.basket_token.mint == token_mint,
+            BasketError::InvalidTokenMint
+        );
+        let token_info = basket.tokens.iter().find(|t| t.mint == token_mint);
+        require!(
+            token_info.map_or(true, |t| t.weight == 0),
+            BasketError::CannotSweepWeightedToken
+        );
+        require!(
+            jupiter::balance_account(ctx.remaining_accounts)?.key()
+                == ctx.accounts.basket_token.key(),
+            BasketError::InvalidTokenAccount
+        );
+
+        // Tokens reserved for pending split redemptions aren't dust
+        let balance = ctx.accounts.basket_token.amount;
+        let amount = token_info.map_or(balance, |t| t.available(balance));
+        require!(amount > 0, BasketError::InsufficientBalance);
+        let lamports_before = basket.to_account_info().lamports();
+
+        let basket_id = basket.basket_id.to_le_bytes();
+        let seeds = &[b"basket".as_ref(), basket_id.as_ref(), &[basket.bump]];
+        jupiter::swap(
+            ctx.accounts.swap_program.key,
+            basket.swap_discriminator,
+            ctx.remaining_accounts,
+            jupiter::RouteSwapParams {
+                in_amount: amount,
+                quote_id: jupiter_quote,
+                slippage_bps,
+            },
+            Some((&basket.key(), seeds)),
+        )?;
+
+        require!(
+            basket.to_account_info().lamports() > lamports_before,
+            BasketError::SlippageExceeded
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(
+    basket_id: u64,
+    max_tokens: u8,
+    entry_fee_bps: u16,
+    exit_fee_bps: u16,
+    fee_recipient: Pubkey,
+    max_deposit_per_user: u64,
+    max_tvl_lamports: u64,
+    min_weight: u8,
+    minimum_deposit: u64,
+    management_fee_bps: u16,
+    timelock_seconds: u64,
+    decimals: u8,
+    performance_fee_bps: u16
+)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = BasketState::required_space(max_tokens as usize), // Sized for the basket's own cap
+        seeds = [b"basket", basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        init,
+        payer = authority,
+        mint::decimals = decimals,
+        mint::authority = basket,
+        mint::token_program = token_program,
+        seeds = [b"basket_mint", basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = BasketRegistry::required_space(),
+        seeds = [b"basket_registry"],
+        bump
+    )]
+    pub registry: Account<'info, BasketRegistry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(basket_id: u64, max_tokens: u8)]
+pub struct InitializeState<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = BasketState::required_space(max_tokens as usize),
+        seeds = [b"basket", basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = BasketRegistry::required_space(),
+        seeds = [b"basket_registry"],
+        bump
+    )]
+    pub registry: Account<'info, BasketRegistry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBasket<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized,
+        close = authority
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(mut, seeds = [b"basket_registry"], bump = registry.bump)]
+    pub registry: Account<'info, BasketRegistry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetRegistry<'info> {
+    #[account(seeds = [b"basket_registry"], bump = registry.bump)]
+    pub registry: Account<'info, BasketRegistry>,
+}
+
+#[derive(Accounts)]
+#[instruction(decimals: u8)]
+pub struct InitializeMint<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    /// CHECK: created and initialized by the handler, with the TransferHook
+    /// extension when `token_program` is Token-2022
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddToken<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    /// CHECK: may be uninitialized; read by TokenAllowlist::check_mint when it exists
+    #[account(seeds = [b"token_allowlist", basket.key().as_ref()], bump)]
+    pub allowlist: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeAllowlist<'info> {
+    #[account(
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = TokenAllowlist::required_space(),
+        seeds = [b"token_allowlist", basket.key().as_ref()],
+        bump
+    )]
+    pub allowlist: Account<'info, TokenAllowlist>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAllowlist<'info> {
+    #[account(
+        mut,
+        has_one = allowlist_authority @ BasketError::Unauthorized,
+        seeds = [b"token_allowlist", allowlist.basket.as_ref()],
+        bump = allowlist.bump
+    )]
+    pub allowlist: Account<'info, TokenAllowlist>,
+
+    pub allowlist_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(token_mint: Pubkey)]
+pub struct InitializeTokenAccount<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(address = token_mint @ BasketError::InvalidTokenMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = mint,
+        associated_token::authority = basket,
+        associated_token::token_program = token_program
+    )]
+    pub basket_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeAllTokenAccounts<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveToken<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(constraint = basket_token.owner == basket.key() @ BasketError::InvalidTokenOwner)]
+    pub basket_token: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTokenWeight<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceFeed<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxSlippage<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_max_tokens: u8)]
+pub struct ResizeBasket<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized,
+        realloc = BasketState::required_space(new_max_tokens as usize),
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    /// CHECK: may predate the current layout, so `migrate` deserializes it
+    /// and checks the PDA and authority itself
+    #[account(mut, owner = crate::ID)]
+    pub basket: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoPauseThreshold<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRebalanceCooldown<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinHoldSeconds<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinSwapLamports<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetSwapDiscriminator<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxSwapsPerTx<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxPriceAge<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileSupply<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateMetadata<'info> {
+    #[account(
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: created by the Token Metadata program at its PDA for `basket_mint`
+    #[account(
+        mut,
+        seeds = [b"metadata", metaplex::TOKEN_METADATA_ID.as_ref(), basket_mint.key().as_ref()],
+        bump,
+        seeds::program = metaplex::TOKEN_METADATA_ID
+    )]
+    pub metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: pinned to the Token Metadata program
+    #[account(address = metaplex::TOKEN_METADATA_ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct EnableTransferRestriction<'info> {
+    #[account(
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    /// CHECK: checked for the TransferHook extension by the handler
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: UncheckedAccount<'info>,
+
+    /// CHECK: the hook's ExtraAccountMetaList, written by the handler
+    #[account(
+        init,
+        payer = authority,
+        space = transfer_hook::EXTRA_ACCOUNT_METAS_LEN,
+        seeds = [transfer_hook::EXTRA_ACCOUNT_METAS_SEED, basket_mint.key().as_ref()],
+        bump
+    )]
+    pub extra_account_metas: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, anchor_spl::token_2022::Token2022>,
+}
+
+#[derive(Accounts)]
+pub struct SetInputMint<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateSwapPrograms<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPauseState<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GuardianPause<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeRecipient<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the user's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &user.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub user_basket_token: UncheckedAccount<'info>,
+
+    /// CHECK: the basket's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_share_token: UncheckedAccount<'info>,
+
+    /// The native mint of whichever token program the basket uses, so the
+    /// wSOL account below is created under the program that owns its mint
+    #[account(
+        address = if token_program.key() == anchor_spl::token_2022::ID {
+            anchor_spl::token_2022::spl_token_2022::native_mint::ID
+        } else {
+            anchor_spl::token::spl_token::native_mint::ID
+        }
+    )]
+    pub native_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the basket's associated wSOL account, created by the handler
+    /// when missing. Holds the deposit while the legs swap out of it and is
+    /// closed back into the basket before the instruction ends.
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &native_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_wsol: UncheckedAccount<'info>,
+
+    /// CHECK: only receives lamports; address is pinned to the basket's fee recipient
+    #[account(mut, address = basket.fee_recipient @ BasketError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = UserDeposit::required_space(),
+        seeds = [b"user_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct BeginDeposit<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    /// CHECK: only receives lamports; address is pinned to the basket's fee recipient
+    #[account(mut, address = basket.fee_recipient @ BasketError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = UserDeposit::required_space(),
+        seeds = [b"user_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+
+    #[account(
+        init,
+        payer = user,
+        space = PendingDeposit::required_space(),
+        seeds = [b"pending_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositLeg<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump = pending_deposit.bump,
+        has_one = basket,
+        has_one = user
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    /// The native mint of whichever token program the basket uses, so the
+    /// wSOL account below is created under the program that owns its mint
+    #[account(
+        address = if token_program.key() == anchor_spl::token_2022::ID {
+            anchor_spl::token_2022::spl_token_2022::native_mint::ID
+        } else {
+            anchor_spl::token::spl_token::native_mint::ID
+        }
+    )]
+    pub native_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the basket's associated wSOL account, created by the handler
+    /// when missing. Holds the leg's SOL while the route swaps out of it and
+    /// is closed back into the basket before the instruction ends.
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &native_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_wsol: UncheckedAccount<'info>,
+
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeDeposit<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the user's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &user.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub user_basket_token: UncheckedAccount<'info>,
+
+    /// CHECK: the basket's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_share_token: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"pending_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump = pending_deposit.bump,
+        has_one = basket,
+        has_one = user
+    )]
+    pub pending_deposit: Account<'info, PendingDeposit>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Redeem<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_basket_token.mint == basket_mint.key(),
+        constraint = user_basket_token.owner == user.key()
+    )]
+    pub user_basket_token: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: only receives lamports; address is pinned to the basket's fee recipient
+    #[account(mut, address = basket.fee_recipient @ BasketError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    /// CHECK: may be uninitialized; read by UserDeposit::check_hold_period when it exists
+    #[account(seeds = [b"user_deposit", basket.key().as_ref(), user.key().as_ref()], bump)]
+    pub user_deposit: UncheckedAccount<'info>,
+
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct BeginRedeem<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_basket_token.mint == basket_mint.key(),
+        constraint = user_basket_token.owner == user.key()
+    )]
+    pub user_basket_token: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: may be uninitialized; read by UserDeposit::check_hold_period when it exists
+    #[account(seeds = [b"user_deposit", basket.key().as_ref(), user.key().as_ref()], bump)]
+    pub user_deposit: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = user,
+        space = PendingRedeem::required_space(basket.tokens.len()),
+        seeds = [b"pending_redeem", basket.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub pending_redeem: Account<'info, PendingRedeem>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemLeg<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_redeem", basket.key().as_ref(), user.key().as_ref()],
+        bump = pending_redeem.bump,
+        has_one = basket,
+        has_one = user
+    )]
+    pub pending_redeem: Account<'info, PendingRedeem>,
+
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeRedeem<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"pending_redeem", basket.key().as_ref(), user.key().as_ref()],
+        bump = pending_redeem.bump,
+        has_one = basket,
+        has_one = user
+    )]
+    pub pending_redeem: Account<'info, PendingRedeem>,
+
+    /// CHECK: only receives lamports; address is pinned to the basket's fee recipient
+    #[account(mut, address = basket.fee_recipient @ BasketError::InvalidFeeRecipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToken<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the user's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &user.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub user_basket_token: UncheckedAccount<'info>,
+
+    /// CHECK: the basket's associated share account, created by the handler
+    /// when missing
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &basket_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_share_token: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = user_input_token.owner == user.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub user_input_token: InterfaceAccount<'info, TokenAccount>,
 
-        // Update state before external calls
-        basket.total_supply = basket
-            .total_supply
-            .checked_sub(amount)
-            .ok_or(BasketError::MathOverflow)?;
+    #[account(
+        mut,
+        constraint = basket_input_token.owner == basket.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub basket_input_token: InterfaceAccount<'info, TokenAccount>,
 
-        // Burn basket tokens
-        let seeds = &[b"basket".as_ref(), &[basket.bump]];
-        token::burn(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::Burn {
-                    mint: ctx.accounts.basket_mint.to_account_info(),
-                    from: ctx.accounts.user_basket_token.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-                &[seeds],
-            ),
-            amount,
-        )?;
+    pub input_mint: InterfaceAccount<'info, Mint>,
+    pub input_token_program: Interface<'info, TokenInterface>,
 
-        // Sell tokens back to SOL
-        let initial_basket_lamports = basket.to_account_info().lamports();
-        let mut total_sol_received = 0;
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = UserDeposit::required_space(),
+        seeds = [b"user_deposit", basket.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
 
-        for (i, token_info) in basket.tokens.iter().enumerate() {
-            // Verify token account mint and owner
-            let token_account = Account::<TokenAccount>::try_from(
-                &ctx.accounts.user_basket_token.to_account_info(),
-            )?;
-            let token_acc_data = Account::<TokenAccount>::try_from(
-                &ctx.accounts.user_basket_token.to_account_info(),
-            )?;
-            require!(
-                token_acc_data.mint == token_info.mint,
-                BasketError::InvalidTokenMint
-            );
-            require!(
-                token_acc_data.owner == basket.key(),
-                BasketError::InvalidTokenOwner
-            );
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
 
-            let token_amount = token_acc_data.amount;
-            let redeem_amount = (token_amount as u128)
-                .checked_mul(redemption_ratio)
-                .ok_or(BasketError::MathOverflow)?
-                .checked_div(MAGNIFIER)
-                .ok_or(BasketError::MathOverflow)? as u64;
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
 
-            // Execute Jupiter swap
-            let swap_ix = Instruction {
-                program_id: jupiter::JUPITER_V6_ID,
-                accounts: ctx.remaining_accounts[i * 12..(i + 1) * 12]
-                    .iter()
-                    .map(|acc| AccountMeta {
-                        pubkey: *acc.key,
-                        is_signer: acc.is_signer,
-                        is_writable: acc.is_writable,
-                    })
-                    .collect(),
-                data: AnchorSerialize::try_to_vec(&(
-                    4u8,
-                    jupiter::RouteSwapParams {
-                        in_amount: redeem_amount,
-                        quote_id: jupiter_quote,
-                        slippage_bps,
-                    },
-                ))
-                .unwrap(),
-            };
+#[derive(Accounts)]
+pub struct WithdrawAuthoritySol<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
+    pub basket: Account<'info, BasketState>,
 
-            anchor_lang::solana_program::program::invoke(
-                &swap_ix,
-                &ctx.remaining_accounts[i * 12..(i + 1) * 12],
-            )?;
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
 
-            // Track SOL received
-            let current_lamports = basket.to_account_info().lamports();
-            let sol_received = current_lamports
-                .checked_sub(initial_basket_lamports + total_sol_received)
-                .ok_or(BasketError::MathOverflow)?;
-            total_sol_received += sol_received;
-        }
+#[derive(Accounts)]
+pub struct CollectManagementFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump
+    )]
+    pub basket: Account<'info, BasketState>,
 
-        require!(
-            total_sol_received >= minimum_sol_amount,
-            BasketError::SlippageExceeded
-        );
+    #[account(
+        mut,
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
 
-        // Transfer SOL to user
-        **basket.to_account_info().try_borrow_mut_lamports()? -= total_sol_received;
-        **ctx
-            .accounts
-            .user
-            .to_account_info()
-            .try_borrow_mut_lamports()? += total_sol_received;
+    #[account(
+        mut,
+        constraint = fee_recipient_token.owner == basket.fee_recipient @ BasketError::InvalidFeeRecipient,
+        constraint = fee_recipient_token.mint == basket_mint.key() @ BasketError::InvalidTokenMint
+    )]
+    pub fee_recipient_token: InterfaceAccount<'info, TokenAccount>,
 
-        // Clear reentrancy guard
-        basket.reentrancy_guard = false;
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
-        Ok(())
-    }
+#[derive(Accounts)]
+pub struct GetComposition<'info> {
+    pub basket: Account<'info, BasketState>,
+}
 
-    pub fn withdraw_authority_sol(ctx: Context<WithdrawAuthoritySol>, amount: u64) -> Result<()> {
-        let basket = &ctx.accounts.basket;
-        require!(
-            basket.authority == ctx.accounts.authority.key(),
-            BasketError::Unauthorized
-        );
+#[derive(Accounts)]
+pub struct DescribeSwapAccounts<'info> {
+    pub basket: Account<'info, BasketState>,
+}
 
-        let basket_lamports = basket.to_account_info().lamports();
-        require!(basket_lamports >= amount, BasketError::InsufficientBalance);
+#[derive(Accounts)]
+pub struct VerifyHoldings<'info> {
+    pub basket: Account<'info, BasketState>,
+}
 
-        **basket.to_account_info().try_borrow_mut_lamports()? -= amount;
-        **ctx
-            .accounts
-            .authority
-            .to_account_info()
-            .try_borrow_mut_lamports()? += amount;
+#[derive(Accounts)]
+pub struct GetPricePerShare<'info> {
+    pub basket: Account<'info, BasketState>,
+}
 
-        Ok(())
-    }
+#[derive(Accounts)]
+pub struct GetBasketValue<'info> {
+    pub basket: Account<'info, BasketState>,
 }
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct GetWeightDrift<'info> {
+    pub basket: Account<'info, BasketState>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewRedeem<'info> {
+    pub basket: Account<'info, BasketState>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct GetUserPosition<'info> {
+    pub basket: Account<'info, BasketState>,
+
     #[account(
-        init,
-        payer = authority,
-        space = BasketState::required_space(MAX_TOKENS),  // Use this instead of manual calculation
-        seeds = [b"basket"],
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
         bump
     )]
+    pub basket_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the user's basket-token ATA, which may not exist yet
+    #[account(
+        address = anchor_spl::associated_token::get_associated_token_address_with_program_id(
+            &user,
+            &basket_mint.key(),
+            &token_program.key(),
+        )
+    )]
+    pub user_basket_token: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct GetDepositCapacity<'info> {
+    pub basket: Account<'info, BasketState>,
+
+    /// CHECK: the user's deposit record, which may not exist yet
+    #[account(seeds = [b"user_deposit", basket.key().as_ref(), user.as_ref()], bump)]
+    pub user_deposit: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CheckHealth<'info> {
     pub basket: Account<'info, BasketState>,
 
     #[account(
-        init,
-        payer = authority,
-        mint::decimals = 9,
-        mint::authority = basket,
-        seeds = [b"basket_mint"],
+        seeds = [b"basket_mint", basket.basket_id.to_le_bytes().as_ref()],
         bump
     )]
-    pub basket_mint: Account<'info, Mint>,
-
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
+    pub basket_mint: InterfaceAccount<'info, Mint>,
 }
 
 #[derive(Accounts)]
-pub struct AddToken<'info> {
-    #[account(mut, has_one = authority @ BasketError::Unauthorized)]
+pub struct GetTokenInfo<'info> {
     pub basket: Account<'info, BasketState>,
-    pub authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveToken<'info> {
-    #[account(mut, has_one = authority @ BasketError::Unauthorized)]
+pub struct ValidateDeposit<'info> {
     pub basket: Account<'info, BasketState>,
-    pub authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct SetPaused<'info> {
-    #[account(mut, has_one = authority @ BasketError::Unauthorized)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
     pub basket: Account<'info, BasketState>,
+
+    #[account(
+        mut,
+        constraint = basket_token.owner == basket.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub basket_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = authority_token.mint == basket_token.mint @ BasketError::InvalidTokenMint
+    )]
+    pub authority_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = basket_token.mint @ BasketError::InvalidTokenMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
     pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut)]
+pub struct WithdrawToken<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
     pub basket: Account<'info, BasketState>,
 
-    #[account(mut)]
-    pub basket_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = basket_token.owner == basket.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub basket_token: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = basket_mint,
-        associated_token::authority = user
+        mut,
+        constraint = authority_token.mint == basket_token.mint @ BasketError::InvalidTokenMint
     )]
-    pub user_basket_token: Account<'info, TokenAccount>,
+    pub authority_token: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    #[account(address = basket_token.mint @ BasketError::InvalidTokenMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Redeem<'info> {
-    #[account(mut)]
+pub struct Rebalance<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
     pub basket: Account<'info, BasketState>,
 
-    #[account(mut)]
-    pub basket_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = from_token.owner == basket.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub from_token: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = user_basket_token.mint == basket_mint.key(),
-        constraint = user_basket_token.owner == user.key()
+        constraint = to_token.owner == basket.key() @ BasketError::InvalidTokenOwner
     )]
-    pub user_basket_token: Account<'info, TokenAccount>,
+    pub to_token: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawAuthoritySol<'info> {
-    #[account(mut, has_one = authority @ BasketError::Unauthorized)]
+pub struct RebalanceToWeights<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
     pub basket: Account<'info, BasketState>,
 
+    /// The native mint of whichever token program the basket uses, so the
+    /// wSOL account below is created under the program that owns its mint
+    #[account(
+        address = if token_program.key() == anchor_spl::token_2022::ID {
+            anchor_spl::token_2022::spl_token_2022::native_mint::ID
+        } else {
+            anchor_spl::token::spl_token::native_mint::ID
+        }
+    )]
+    pub native_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the basket's associated wSOL account, created by the handler
+    /// when missing. Holds SOL while buy routes swap out of it and is closed
+    /// back into the basket before the instruction ends.
+    #[account(
+        mut,
+        address = associated_token::get_associated_token_address_with_program_id(
+            &basket.key(),
+            &native_mint.key(),
+            &token_program.key()
+        ) @ anchor_lang::error::ErrorCode::AccountNotAssociatedTokenAccount
+    )]
+    pub basket_wsol: UncheckedAccount<'info>,
+
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
-pub struct EmergencyWithdraw<'info> {
-    #[account(mut, has_one = authority @ BasketError::Unauthorized)]
+pub struct SweepDust<'info> {
+    #[account(
+        mut,
+        seeds = [b"basket", basket.basket_id.to_le_bytes().as_ref()],
+        bump = basket.bump,
+        has_one = authority @ BasketError::Unauthorized
+    )]
     pub basket: Account<'info, BasketState>,
 
-    #[account(mut)]
-    pub basket_token: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = basket_token.owner == basket.key() @ BasketError::InvalidTokenOwner
+    )]
+    pub basket_token: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub authority_token: Account<'info, TokenAccount>,
+    /// CHECK: router every route is sent to; must be on the basket's allowlist
+    #[account(
+        executable,
+        constraint = basket.allowed_swap_programs.contains(swap_program.key)
+            @ BasketError::UnauthorizedSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
 
-    #[account(mut)]
     pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event]
+pub struct DepositEvent {
+    pub user: Pubkey,
+    pub sol_in: u64,
+    pub shares_minted: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RedeemEvent {
+    pub user: Pubkey,
+    pub shares_burned: u64,
+    pub sol_out: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenAddedEvent {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub weight: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenRemovedEvent {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PauseStateChangedEvent {
+    pub authority: Pubkey, // The authority or guardian that signed
+    pub paused: bool, // Whether deposits or redemptions are left paused
+    pub deposits_paused: bool,
+    pub redemptions_paused: bool,
+    pub reason: String, // Empty when the change left nothing paused
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthoritySolWithdrawnEvent {
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeRecipientUpdated {
+    pub authority: Pubkey,
+    pub previous_recipient: Pubkey,
+    pub new_recipient: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SupplyReconciled {
+    pub basket: Pubkey,
+    pub previous_supply: u64,
+    pub reconciled_supply: u64,
+    pub timestamp: i64,
 }
 
 #[error_code]
@@ -584,10 +6338,18 @@ pub enum BasketError {
     Unauthorized,
     #[msg("Insufficient deposit")]
     InsufficientDeposit,
+    #[msg("Deposit too small to mint any shares")]
+    DepositTooSmallForShares,
+    #[msg("Stale nonce")]
+    StaleNonce,
+    #[msg("Transfer failed")]
+    TransferFailed,
     #[msg("Math overflow")]
     MathOverflow,
     #[msg("Weight overflow")]
     WeightOverflow,
+    #[msg("Weight below minimum")]
+    WeightBelowMinimum,
     #[msg("Invalid token count")]
     InvalidTokenCount,
     #[msg("Invalid account count")]
@@ -596,18 +6358,118 @@ pub enum BasketError {
     InvalidTokenMint,
     #[msg("Invalid token owner")]
     InvalidTokenOwner,
+    #[msg("Invalid token account")]
+    InvalidTokenAccount,
+    #[msg("Invalid token account data")]
+    InvalidTokenAccountData,
+    #[msg("Unexpected holding")]
+    UnexpectedHolding,
     #[msg("Slippage exceeded")]
     SlippageExceeded,
+    #[msg("Slippage floor too low")]
+    SlippageFloorTooLow,
+    #[msg("Circuit breaker tripped")]
+    CircuitBreakerTripped,
     #[msg("Too many tokens")]
     TooManyTokens,
     #[msg("Token not found")]
     TokenNotFound,
+    #[msg("Token already active")]
+    TokenAlreadyActive,
+    #[msg("Token balance non-zero")]
+    TokenBalanceNonZero,
+    #[msg("Basket still has shares or tokens")]
+    BasketNotEmpty,
+    #[msg("Basket registry full")]
+    RegistryFull,
     #[msg("Duplicate token")]
     DuplicateToken,
+    #[msg("Mint not allowed")]
+    MintNotAllowed,
     #[msg("Program paused")]
     ProgramPaused,
+    #[msg("Program not paused")]
+    ProgramNotPaused,
+    #[msg("Deposits paused")]
+    DepositsPaused,
+    #[msg("Redemptions paused")]
+    RedemptionsPaused,
+    #[msg("Deposit cap exceeded")]
+    DepositCapExceeded,
+    #[msg("TVL cap exceeded")]
+    TvlCapExceeded,
+    #[msg("Leg already completed")]
+    LegAlreadyCompleted,
+    #[msg("Legs incomplete")]
+    LegsIncomplete,
     #[msg("Reentrancy detected")]
     ReentrancyDetected,
     #[msg("Insufficient balance")]
     InsufficientBalance,
+    #[msg("Would undercollateralize")]
+    WouldUndercollateralize,
+    #[msg("Would break rent exemption")]
+    WouldBreakRentExemption,
+    #[msg("Cannot withdraw a backing token")]
+    CannotWithdrawBackingToken,
+    #[msg("No shares outstanding")]
+    NoSharesOutstanding,
+    #[msg("Invalid NAV")]
+    InvalidNav,
+    #[msg("Invalid price account")]
+    InvalidPriceAccount,
+    #[msg("Stale price")]
+    StalePrice,
+    #[msg("Invalid price age")]
+    InvalidPriceAge,
+    #[msg("Invalid fee")]
+    InvalidFee,
+    #[msg("Invalid decimals")]
+    InvalidDecimals,
+    #[msg("Not initialized")]
+    NotInitialized,
+    #[msg("Already initialized")]
+    AlreadyInitialized,
+    #[msg("Already at current version")]
+    AlreadyMigrated,
+    #[msg("Metadata field too long")]
+    MetadataTooLong,
+    #[msg("Pause reason too long")]
+    PauseReasonTooLong,
+    #[msg("Invalid fee recipient")]
+    InvalidFeeRecipient,
+    #[msg("Invalid slippage")]
+    InvalidSlippage,
+    #[msg("Slippage too high")]
+    SlippageTooHigh,
+    #[msg("Weights not complete")]
+    WeightsNotComplete,
+    #[msg("Rebalance cooldown")]
+    RebalanceCooldown,
+    #[msg("Hold period not met")]
+    HoldPeriodNotMet,
+    #[msg("Token account not initialized")]
+    TokenAccountNotInitialized,
+    #[msg("Timelock not elapsed")]
+    TimelockNotElapsed,
+    #[msg("No pending action")]
+    NoPendingAction,
+    #[msg("Input mint not allowed")]
+    InputMintNotAllowed,
+    #[msg("Swap program not allowed")]
+    UnauthorizedSwapProgram,
+    #[msg("Too many swap programs")]
+    TooManySwapPrograms,
+    #[msg("Basket shares can only move into or out of the basket")]
+    TransferRestricted,
+    #[msg("Basket mint has no transfer hook extension")]
+    TransferHookUnavailable,
+    #[msg("Too many swaps for one transaction")]
+    TooManySwapsForTx,
+    #[msg("Cannot withdraw basket shares or wSOL")]
+    CannotWithdrawProtectedToken,
+    #[msg("Leg has no weight")]
+    ZeroWeightLeg,
+    #[msg("Cannot sweep a weighted token")]
+    CannotSweepWeightedToken,
 }
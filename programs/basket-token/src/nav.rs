@@ -0,0 +1,219 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::TokenAccount;
+
+use crate::{BasketError, BasketState, PriceSource, TokenInfo};
+
+// Pyth v2 price account layout
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const PYTH_MAGIC_OFFSET: usize = 0;
+const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+const PYTH_EXPO_OFFSET: usize = 20;
+const PYTH_PUBLISH_TIME_OFFSET: usize = 96;
+const PYTH_AGG_PRICE_OFFSET: usize = 208;
+const PYTH_AGG_STATUS_OFFSET: usize = 224;
+pub const PYTH_PRICE_ACCOUNT_MIN_SIZE: usize = 240;
+
+// Switchboard v2 AggregatorAccountData layout
+const SWITCHBOARD_AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+const SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET: usize = 358;
+const SWITCHBOARD_RESULT_MANTISSA_OFFSET: usize = 366;
+const SWITCHBOARD_RESULT_SCALE_OFFSET: usize = 382;
+pub const SWITCHBOARD_AGGREGATOR_MIN_SIZE: usize = 386;
+
+/// Price of one token base unit in lamports, scaled by `10^expo`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_i128(data: &[u8], offset: usize) -> i128 {
+    i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+/// Read `price_account` as the feed type named by `source`
+pub fn read_price(price_account: &AccountInfo, source: PriceSource) -> Result<OraclePrice> {
+    match source {
+        PriceSource::Pyth => read_pyth_price(price_account),
+        PriceSource::Switchboard => read_switchboard_price(price_account),
+    }
+}
+
+pub fn read_pyth_price(price_account: &AccountInfo) -> Result<OraclePrice> {
+    let data = price_account.try_borrow_data()?;
+    require!(
+        data.len() >= PYTH_PRICE_ACCOUNT_MIN_SIZE
+            && read_u32(&data, PYTH_MAGIC_OFFSET) == PYTH_MAGIC
+            && read_u32(&data, PYTH_ACCOUNT_TYPE_OFFSET) == PYTH_PRICE_ACCOUNT_TYPE,
+        BasketError::InvalidPriceAccount
+    );
+    require!(
+        read_u32(&data, PYTH_AGG_STATUS_OFFSET) == PYTH_STATUS_TRADING,
+        BasketError::InvalidPriceAccount
+    );
+
+    let price = OraclePrice {
+        price: read_i64(&data, PYTH_AGG_PRICE_OFFSET),
+        expo: read_i32(&data, PYTH_EXPO_OFFSET),
+        publish_time: read_i64(&data, PYTH_PUBLISH_TIME_OFFSET),
+    };
+    require!(price.price > 0, BasketError::InvalidPriceAccount);
+    Ok(price)
+}
+
+/// Latest confirmed round of a Switchboard aggregator, normalized so that
+/// `mantissa * 10^-scale` becomes `price * 10^expo`
+pub fn read_switchboard_price(aggregator: &AccountInfo) -> Result<OraclePrice> {
+    let data = aggregator.try_borrow_data()?;
+    require!(
+        data.len() >= SWITCHBOARD_AGGREGATOR_MIN_SIZE
+            && data[..8] == SWITCHBOARD_AGGREGATOR_DISCRIMINATOR,
+        BasketError::InvalidPriceAccount
+    );
+
+    let mantissa = read_i128(&data, SWITCHBOARD_RESULT_MANTISSA_OFFSET);
+    let scale = read_u32(&data, SWITCHBOARD_RESULT_SCALE_OFFSET);
+    let price = OraclePrice {
+        price: i64::try_from(mantissa).map_err(|_| error!(BasketError::InvalidPriceAccount))?,
+        expo: -i32::try_from(scale).map_err(|_| error!(BasketError::InvalidPriceAccount))?,
+        publish_time: read_i64(&data, SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET),
+    };
+    require!(price.price > 0, BasketError::InvalidPriceAccount);
+    Ok(price)
+}
+
+/// Read the price of `token_info` and reject it if it was published more than
+/// `max_price_age_seconds` ago
+pub fn read_fresh_price(
+    basket: &BasketState,
+    token_info: &TokenInfo,
+    price_account: &AccountInfo,
+) -> Result<OraclePrice> {
+    let price = read_price(price_account, token_info.price_source)?;
+    let oldest_allowed = Clock::get()?
+        .unix_timestamp
+        .saturating_sub(basket.max_price_age_seconds.min(i64::MAX as u64) as i64);
+    if price.publish_time < oldest_allowed {
+        msg!("Stale price for {}: published at {}", token_info.mint, price.publish_time);
+        return err!(BasketError::StalePrice);
+    }
+    Ok(price)
+}
+
+/// Lamport value of `amount` base units at `price`
+pub fn token_value(amount: u64, price: &OraclePrice) -> Result<u64> {
+    let scale = 10u128
+        .checked_pow(price.expo.unsigned_abs())
+        .ok_or(BasketError::MathOverflow)?;
+    let value = (amount as u128)
+        .checked_mul(price.price as u128)
+        .ok_or(BasketError::MathOverflow)?;
+    let value = if price.expo < 0 {
+        value.checked_div(scale)
+    } else {
+        value.checked_mul(scale)
+    }
+    .ok_or(BasketError::MathOverflow)?;
+
+    to_u64(value)
+}
+
+/// Narrow a widened intermediate back to `u64`, failing rather than truncating
+pub fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| error!(BasketError::MathOverflow))
+}
+
+/// Deserialize the token account supplied for leg `index`, naming the leg
+/// when the caller passed something else
+pub fn load_token_account<'info>(
+    account: &AccountInfo<'info>,
+    index: usize,
+) -> Result<InterfaceAccount<'info, TokenAccount>> {
+    InterfaceAccount::<TokenAccount>::try_from(account).map_err(|_| {
+        msg!("Leg {}: {} is not a token account", index, account.key());
+        error!(BasketError::InvalidTokenAccountData)
+    })
+}
+
+/// Total lamport value held by the basket: idle SOL above the rent-exempt
+/// reserve plus every token holding priced by its pinned oracle feed.
+/// `token_accounts` and `price_accounts` are ordered like `basket.tokens` and
+/// must be the registered `token_account` and `price_feed` of each entry.
+pub fn compute_nav(
+    basket: &Account<BasketState>,
+    token_accounts: &[AccountInfo],
+    price_accounts: &[AccountInfo],
+) -> Result<u64> {
+    compute_leg_values(basket, token_accounts, price_accounts)?
+        .into_iter()
+        .try_fold(idle_lamports(basket)?, |nav, value| nav.checked_add(value))
+        .ok_or_else(|| error!(BasketError::MathOverflow))
+}
+
+/// Lamports the basket holds above its rent-exempt reserve and the SOL still
+/// earmarked for in-flight split deposits and redemptions
+pub fn idle_lamports(basket: &Account<BasketState>) -> Result<u64> {
+    let basket_info = basket.to_account_info();
+    let rent_exempt_lamports = Rent::get()?.minimum_balance(basket_info.data_len());
+    Ok(basket_info
+        .lamports()
+        .saturating_sub(rent_exempt_lamports)
+        .saturating_sub(basket.pending_deposit_lamports)
+        .saturating_sub(basket.pending_redeem_lamports))
+}
+
+/// Lamport value of each token holding, ordered like `basket.tokens`
+pub fn compute_leg_values(
+    basket: &Account<BasketState>,
+    token_accounts: &[AccountInfo],
+    price_accounts: &[AccountInfo],
+) -> Result<Vec<u64>> {
+    require!(
+        token_accounts.len() == basket.tokens.len()
+            && price_accounts.len() == basket.tokens.len(),
+        BasketError::InvalidAccountCount
+    );
+
+    let mut values = Vec::with_capacity(basket.tokens.len());
+    for (i, token_info) in basket.tokens.iter().enumerate() {
+        require!(
+            price_accounts[i].key() == token_info.price_feed,
+            BasketError::InvalidPriceAccount
+        );
+
+        require!(
+            token_accounts[i].key() == token_info.token_account,
+            BasketError::InvalidTokenAccount
+        );
+
+        let token_acc_data = load_token_account(&token_accounts[i], i)?;
+        require!(
+            token_acc_data.mint == token_info.mint,
+            BasketError::InvalidTokenMint
+        );
+        require!(
+            token_acc_data.owner == basket.key(),
+            BasketError::InvalidTokenOwner
+        );
+
+        let price = read_fresh_price(basket, token_info, &price_accounts[i])?;
+        values.push(token_value(token_info.available(token_acc_data.amount), &price)?);
+    }
+
+    Ok(values)
+}
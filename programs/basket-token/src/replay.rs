@@ -0,0 +1,83 @@
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+
+use crate::basket_token::MINIMUM_LIQUIDITY;
+use crate::{
+    BasketError, DepositEvent, RedeemEvent, SupplyReconciled, TokenAddedEvent, TokenRemovedEvent,
+};
+
+/// An emitted event that moves the state `BasketReplay` tracks
+pub enum BasketEvent {
+    Deposit(DepositEvent),
+    Redeem(RedeemEvent),
+    TokenAdded(TokenAddedEvent),
+    TokenRemoved(TokenRemovedEvent),
+    SupplyReconciled(SupplyReconciled),
+}
+
+impl BasketEvent {
+    /// Decode one base64-decoded `Program data:` payload; `None` for events
+    /// that don't affect replayed state
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let discriminator = data.get(..8)?;
+        let mut body = data.get(8..)?;
+        if discriminator == DepositEvent::discriminator() {
+            DepositEvent::deserialize(&mut body).ok().map(Self::Deposit)
+        } else if discriminator == RedeemEvent::discriminator() {
+            RedeemEvent::deserialize(&mut body).ok().map(Self::Redeem)
+        } else if discriminator == TokenAddedEvent::discriminator() {
+            TokenAddedEvent::deserialize(&mut body).ok().map(Self::TokenAdded)
+        } else if discriminator == TokenRemovedEvent::discriminator() {
+            TokenRemovedEvent::deserialize(&mut body).ok().map(Self::TokenRemoved)
+        } else if discriminator == SupplyReconciled::discriminator() {
+            SupplyReconciled::deserialize(&mut body).ok().map(Self::SupplyReconciled)
+        } else {
+            None
+        }
+    }
+}
+
+/// One basket's share supply and token list rebuilt from its events, applied
+/// in the order they were emitted. Fee collection mints shares without an
+/// event, so replayed supply lags until the next `SupplyReconciled`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BasketReplay {
+    pub total_supply: u64,
+    pub tokens: Vec<(Pubkey, u8)>, // Mint and weight, in the order they were added
+}
+
+impl BasketReplay {
+    pub fn apply_event(&mut self, event: &BasketEvent) -> Result<()> {
+        match event {
+            BasketEvent::Deposit(deposit) => {
+                // The first deposit also locks shares the event doesn't report
+                let locked = if self.total_supply == 0 {
+                    MINIMUM_LIQUIDITY
+                } else {
+                    0
+                };
+                self.total_supply = self
+                    .total_supply
+                    .checked_add(deposit.shares_minted)
+                    .and_then(|supply| supply.checked_add(locked))
+                    .ok_or(BasketError::MathOverflow)?;
+            }
+            BasketEvent::Redeem(redeem) => {
+                self.total_supply = self
+                    .total_supply
+                    .checked_sub(redeem.shares_burned)
+                    .ok_or(BasketError::MathOverflow)?;
+            }
+            BasketEvent::TokenAdded(added) => {
+                self.tokens.push((added.mint, added.weight));
+            }
+            BasketEvent::TokenRemoved(removed) => {
+                self.tokens.retain(|(mint, _)| *mint != removed.mint);
+            }
+            BasketEvent::SupplyReconciled(reconciled) => {
+                self.total_supply = reconciled.reconciled_supply;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,42 +1,287 @@
-#[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::*;
-    use anchor_lang::solana_program::{system_program, system_instruction};
+    use crate::accounts::*;
+    use crate::{
+        basket_token, id, jupiter, metaplex, nav, transfer_hook, BasketComposition, BasketError,
+        BasketState, BasketValue, DepositCapacity, DepositEvent, PauseStateChangedEvent,
+        PendingRedeem, PricePerShare, PriceSource, RedeemEvent, RedeemPreview, SwapAccountLayout,
+        SwapLeg, TimelockedAction, TokenAddedEvent, TokenLookup, UserDeposit, UserPosition,
+    };
+    use anchor_lang::prelude::*;
+    use anchor_lang::solana_program::{system_program, system_instruction, sysvar};
     use anchor_spl::{token, associated_token};
     use solana_program_test::*;
-    use solana_sdk::{signature::Keypair, signer::Signer};
-    use anchor_spl::token::{Mint, Token};
-    use spl_associated_token_account::get_associated_token_address;
+    use solana_sdk::{signature::Keypair, signer::Signer, transaction::Transaction};
+    use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+    use solana_sdk::account::AccountSharedData;
+    use anchor_spl::token::{Mint, TokenAccount};
+    use anchor_spl::token_2022::spl_token_2022;
+    use spl_associated_token_account::{
+        get_associated_token_address, get_associated_token_address_with_program_id,
+    };
+    use spl_token_2022::extension::{transfer_fee, ExtensionType, StateWithExtensions};
+    use solana_program::entrypoint::ProgramResult;
+    use solana_program::program_stubs;
+    use solana_program::instruction::Instruction;
+    use solana_program::program::{invoke, invoke_signed};
+
+    const PYTH_PROGRAM_ID: Pubkey =
+        solana_program::pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+    const SWITCHBOARD_PROGRAM_ID: Pubkey =
+        solana_program::pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+    // Second deployment of the mock router, for swap program allowlist tests
+    const ALT_SWAP_PROGRAM_ID: Pubkey =
+        solana_program::pubkey!("JUP4Fb2cqiRUcaTHdrPC8h2gNsA2ETXiPDD33WcGuJB");
 
     // Mock Jupiter Program
     pub fn process_jupiter_instruction(
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         instruction_data: &[u8],
-    ) -> Result<()> {
-        let source_info = accounts[0];
-        let destination_info = accounts[1];
+    ) -> ProgramResult {
+        let source_info = &accounts[0];
+        let destination_info = &accounts[1];
         
-        let (_disc, params) = jupiter::RouteSwapParams::try_from_slice(&instruction_data[1..]).unwrap();
+        let params = jupiter::RouteSwapParams::try_from_slice(&instruction_data[1..]).unwrap();
+        msg!("Jupiter discriminator: {}", instruction_data[0]);
+        msg!("Jupiter quote: {:?}", params.quote_id);
+        msg!("Jupiter in_amount: {}", params.in_amount);
+
+        // Quotes of all 0xff bytes simulate a failed route
+        if params.quote_id == [u8::MAX; 32] {
+            return Err(ProgramError::Custom(0));
+        }
+
+        // Token-to-token route: [source, destination, source authority, vault,
+        // source mint, source token program, destination mint, mint authority,
+        // destination token program, ...]. Source tokens go to the vault and the
+        // same amount of destination tokens is minted.
+        if accounts.len() >= 9 && *accounts[8].key == token::ID {
+            let decimals = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+                &accounts[4].try_borrow_data()?,
+            )?
+            .base
+            .decimals;
+            invoke(
+                &spl_token_2022::instruction::transfer_checked(
+                    accounts[5].key,
+                    accounts[0].key,
+                    accounts[4].key,
+                    accounts[3].key,
+                    accounts[2].key,
+                    &[],
+                    params.in_amount,
+                    decimals,
+                )?,
+                &[
+                    accounts[0].clone(),
+                    accounts[4].clone(),
+                    accounts[3].clone(),
+                    accounts[2].clone(),
+                ],
+            )?;
+            let (_, bump) = Pubkey::find_program_address(&[b"mint_authority"], program_id);
+            invoke_signed(
+                &token::spl_token::instruction::mint_to(
+                    &token::ID,
+                    accounts[6].key,
+                    accounts[1].key,
+                    accounts[7].key,
+                    &[],
+                    params.in_amount,
+                )?,
+                &[accounts[6].clone(), accounts[1].clone(), accounts[7].clone()],
+                &[&[b"mint_authority", &[bump]]],
+            )?;
+            return Ok(());
+        }
+
+        // Token-to-SOL route: [lamport destination, source, source authority,
+        // vault, source mint, source token program, lamport reserve, ...].
+        // Source tokens go to the vault and the Jupiter-owned reserve pays
+        // out the same number of lamports.
+        if accounts.len() >= 7
+            && (*accounts[5].key == token::ID || *accounts[5].key == spl_token_2022::ID)
+            && accounts[6].owner == program_id
+        {
+            let (destination, source) = (&accounts[0], &accounts[1]);
+            let decimals = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+                &accounts[4].try_borrow_data()?,
+            )?
+            .base
+            .decimals;
+            invoke(
+                &spl_token_2022::instruction::transfer_checked(
+                    accounts[5].key,
+                    source.key,
+                    accounts[4].key,
+                    accounts[3].key,
+                    accounts[2].key,
+                    &[],
+                    params.in_amount,
+                    decimals,
+                )?,
+                &[
+                    source.clone(),
+                    accounts[4].clone(),
+                    accounts[3].clone(),
+                    accounts[2].clone(),
+                ],
+            )?;
+            **accounts[6].try_borrow_mut_lamports()? -= params.in_amount;
+            **destination.try_borrow_mut_lamports()? += params.in_amount;
+            return Ok(());
+        }
+
+        // SOL-to-token route: [lamport input, destination, source authority,
+        // destination mint, mint authority, destination token program, ...].
+        // The Jupiter-owned input is drained into the mint authority and the
+        // same amount of destination tokens is minted.
+        if accounts.len() >= 6 && *accounts[5].key == token::ID {
+            let (_, bump) = Pubkey::find_program_address(&[b"mint_authority"], program_id);
+            invoke_signed(
+                &token::spl_token::instruction::mint_to(
+                    &token::ID,
+                    accounts[3].key,
+                    accounts[1].key,
+                    accounts[4].key,
+                    &[],
+                    params.in_amount,
+                )?,
+                &[accounts[3].clone(), accounts[1].clone(), accounts[4].clone()],
+                &[&[b"mint_authority", &[bump]]],
+            )?;
+            **accounts[0].try_borrow_mut_lamports()? -= params.in_amount;
+            **accounts[4].try_borrow_mut_lamports()? += params.in_amount;
+            return Ok(());
+        }
         
-        if source_info.is_writable() && destination_info.is_writable() {
+        // Only a Jupiter-owned source can pay out, so routes padded with
+        // wallet accounts swap nothing
+        if source_info.is_writable
+            && destination_info.is_writable
+            && source_info.owner == program_id
+        {
             let amount = params.in_amount;
             **destination_info.try_borrow_mut_lamports()? += amount;
             **source_info.try_borrow_mut_lamports()? = source_info
                 .lamports()
                 .checked_sub(amount)
-                .ok_or(BasketError::MathOverflow)?;
+                .ok_or(ProgramError::InsufficientFunds)?;
         }
         
         Ok(())
     }
 
+    // Builtin programs log events through the syscall stubs, whose default
+    // `sol_log_data` prints to stdout instead of the transaction logs. Route it
+    // through `sol_log` so events show up in the logs as `Program log: Program
+    // data: ...`, and forward everything else to the program-test stubs.
+    struct EventLogStubs;
+
+    // Filled under the write lock while the wrapper is swapped in, so calls made
+    // in between wait for it instead of seeing no stubs
+    static PROGRAM_TEST_STUBS: std::sync::RwLock<Option<Box<dyn program_stubs::SyscallStubs>>> =
+        std::sync::RwLock::new(None);
+
+    impl EventLogStubs {
+        fn inner(&self) -> std::sync::RwLockReadGuard<'static, Option<Box<dyn program_stubs::SyscallStubs>>> {
+            PROGRAM_TEST_STUBS.read().unwrap()
+        }
+    }
+
+    impl program_stubs::SyscallStubs for EventLogStubs {
+        fn sol_log(&self, message: &str) {
+            self.inner().as_ref().unwrap().sol_log(message)
+        }
+        fn sol_log_compute_units(&self) {
+            self.inner().as_ref().unwrap().sol_log_compute_units()
+        }
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            self.inner().as_ref().unwrap().sol_invoke_signed(instruction, account_infos, signers_seeds)
+        }
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            self.inner().as_ref().unwrap().sol_get_clock_sysvar(var_addr)
+        }
+        fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+            self.inner().as_ref().unwrap().sol_get_epoch_schedule_sysvar(var_addr)
+        }
+        fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+            self.inner().as_ref().unwrap().sol_get_fees_sysvar(var_addr)
+        }
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            self.inner().as_ref().unwrap().sol_get_rent_sysvar(var_addr)
+        }
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            self.inner().as_ref().unwrap().sol_get_return_data()
+        }
+        fn sol_set_return_data(&self, data: &[u8]) {
+            self.inner().as_ref().unwrap().sol_set_return_data(data)
+        }
+        fn sol_log_data(&self, fields: &[&[u8]]) {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(anchor_lang::__private::base64::encode)
+                .collect();
+            self.inner().as_ref().unwrap().sol_log(&format!("Program data: {}", fields.join(" ")))
+        }
+        fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+            self.inner().as_ref().unwrap().sol_get_processed_sibling_instruction(index)
+        }
+        fn sol_get_stack_height(&self) -> u64 {
+            self.inner().as_ref().unwrap().sol_get_stack_height()
+        }
+    }
+
+    // The base64 event payload of a `Program data:` log line
+    fn event_data(log: &str) -> Option<&str> {
+        log.strip_prefix("Program log: ")
+            .unwrap_or(log)
+            .strip_prefix("Program data: ")
+    }
+
+    // Mock Token Metadata program: CreateMetadataAccountV3 stores the raw
+    // instruction arguments in the metadata PDA
+    pub fn process_metadata_instruction(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        instruction_data: &[u8],
+    ) -> ProgramResult {
+        assert_eq!(instruction_data[0], 33);
+        assert!(accounts[2].is_signer && accounts[4].is_signer);
+        let (metadata, bump) = Pubkey::find_program_address(
+            &[b"metadata", program_id.as_ref(), accounts[1].key.as_ref()],
+            program_id,
+        );
+        assert_eq!(metadata, *accounts[0].key);
+
+        let args = &instruction_data[1..];
+        invoke_signed(
+            &system_instruction::create_account(
+                accounts[3].key,
+                accounts[0].key,
+                Rent::get()?.minimum_balance(args.len()),
+                args.len() as u64,
+                program_id,
+            ),
+            &[accounts[3].clone(), accounts[0].clone(), accounts[5].clone()],
+            &[&[b"metadata", program_id.as_ref(), accounts[1].key.as_ref(), &[bump]]],
+        )?;
+        accounts[0].try_borrow_mut_data()?.copy_from_slice(args);
+        Ok(())
+    }
+
     async fn setup() -> (ProgramTestContext, Keypair, Pubkey, Pubkey) {
         let program_id = id();
         let mut program_test = ProgramTest::new(
             "basket_token",
             program_id,
-            processor!(basket_token::entry),
+            processor!(crate::entry),
         );
 
         // Add mock Jupiter program
@@ -45,24 +290,49 @@ mod tests {
             jupiter::JUPITER_V6_ID,
             processor!(process_jupiter_instruction),
         );
+        program_test.add_program(
+            "jupiter_alt",
+            ALT_SWAP_PROGRAM_ID,
+            processor!(process_jupiter_instruction),
+        );
+
+        // Add mock Token Metadata program
+        program_test.add_program(
+            "mpl_token_metadata",
+            metaplex::TOKEN_METADATA_ID,
+            processor!(process_metadata_instruction),
+        );
 
         let mut context = program_test.start_with_context().await;
+
+        // program-test installs its stubs on the first start; wrap them once
+        static EVENT_LOGS: std::sync::Once = std::sync::Once::new();
+        EVENT_LOGS.call_once(|| {
+            let mut inner = PROGRAM_TEST_STUBS.write().unwrap();
+            *inner = Some(program_stubs::set_syscall_stubs(Box::new(EventLogStubs)));
+        });
         let payer = Keypair::new();
 
         // Airdrop SOL to payer
         airdrop_sol(&mut context, &payer.pubkey(), 10_000_000_000).await;
 
+        let (basket_pda, mint_pda) = basket_pdas(0);
+
+        (context, payer, basket_pda, mint_pda)
+    }
+
+    fn basket_pdas(basket_id: u64) -> (Pubkey, Pubkey) {
         let (basket_pda, _) = Pubkey::find_program_address(
-            &[b"basket"],
-            &program_id,
+            &[b"basket", &basket_id.to_le_bytes()],
+            &id(),
         );
 
         let (mint_pda, _) = Pubkey::find_program_address(
-            &[b"basket_mint"],
-            &program_id,
+            &[b"basket_mint", &basket_id.to_le_bytes()],
+            &id(),
         );
 
-        (context, payer, basket_pda, mint_pda)
+        (basket_pda, mint_pda)
     }
 
     async fn create_mint(
@@ -80,7 +350,7 @@ mod tests {
             &token::ID,
         );
         
-        let initialize_ix = token::instruction::initialize_mint(
+        let initialize_ix = token::spl_token::instruction::initialize_mint(
             &token::ID,
             &mint.pubkey(),
             &payer.pubkey(),
@@ -104,11 +374,21 @@ mod tests {
         mint: &Pubkey,
         owner: &Pubkey,
     ) -> Pubkey {
-        let account = get_associated_token_address(owner, mint);
-        let create_ix = associated_token::instruction::create_associated_token_account(
+        create_token_account_with_program(context, mint, owner, &token::ID).await
+    }
+
+    async fn create_token_account_with_program(
+        context: &mut ProgramTestContext,
+        mint: &Pubkey,
+        owner: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Pubkey {
+        let account = get_associated_token_address_with_program_id(owner, mint, token_program);
+        let create_ix = spl_associated_token_account::instruction::create_associated_token_account(
             &context.payer.pubkey(),
             owner,
             mint,
+            token_program,
         );
 
         let transaction = Transaction::new_signed_with_payer(
@@ -122,6 +402,193 @@ mod tests {
         account
     }
 
+    async fn mint_tokens(
+        context: &mut ProgramTestContext,
+        mint: &Pubkey,
+        mint_authority: &Keypair,
+        to: &Pubkey,
+        amount: u64,
+    ) {
+        let mint_ix = token::spl_token::instruction::mint_to(
+            &token::ID,
+            mint,
+            to,
+            &mint_authority.pubkey(),
+            &[],
+            amount,
+        ).unwrap();
+
+        let transaction = Transaction::new_signed_with_payer(
+            &[mint_ix],
+            Some(&mint_authority.pubkey()),
+            &[mint_authority],
+            context.last_blockhash,
+        );
+
+        context.banks_client.process_transaction(transaction).await.unwrap();
+    }
+
+    async fn token_balance(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+        let account = context.banks_client
+            .get_account(*account)
+            .await
+            .unwrap()
+            .unwrap();
+
+        TokenAccount::try_deserialize(&mut &account.data[..]).unwrap().amount
+    }
+
+    async fn get_basket_state(context: &mut ProgramTestContext, basket_pda: &Pubkey) -> BasketState {
+        let basket_account = context.banks_client
+            .get_account(*basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+
+        BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap()
+    }
+
+    // Basket SOL not held for a split deposit in flight
+    async fn unreserved_lamports(context: &mut ProgramTestContext, basket_pda: &Pubkey) -> u64 {
+        let lamports = context.banks_client.get_balance(*basket_pda).await.unwrap();
+        lamports - get_basket_state(context, basket_pda).await.pending_deposit_lamports
+    }
+
+    async fn process_ix(
+        context: &mut ProgramTestContext,
+        ix: Instruction,
+        signer: &Keypair,
+    ) -> std::result::Result<(), BanksClientError> {
+        // Fresh blockhash so retrying an identical instruction isn't deduplicated
+        let blockhash = context.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&signer.pubkey()),
+            &[signer],
+            blockhash,
+        );
+
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    // Runs `transaction` straight on the working bank to capture its logs.
+    // The bank can still hold the previous transaction's account locks just
+    // after its status lands, so retry while they are in use.
+    async fn process_transaction_with_metadata(
+        context: &mut ProgramTestContext,
+        transaction: Transaction,
+    ) -> BanksTransactionResultWithMetadata {
+        loop {
+            let result = context.banks_client
+                .process_transaction_with_metadata(transaction.clone())
+                .await
+                .unwrap();
+            if result.result != Err(TransactionError::AccountInUse) {
+                return result;
+            }
+        }
+    }
+
+    async fn set_pause_state(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        deposits: bool,
+        redemptions: bool,
+    ) {
+        // Test baskets have no timelock, so the queued change executes immediately
+        let accounts = SetPauseState {
+            basket: *basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueSetPauseState {
+                deposits,
+                redemptions,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteSetPauseState { reason: None }),
+            accounts,
+        );
+
+        process_ix(context, queue_ix, payer).await.unwrap();
+        process_ix(context, execute_ix, payer).await.unwrap();
+    }
+
+    async fn add_token(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        token_mint: Pubkey,
+        weight: u8,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::AddToken {
+                token_mint,
+                weight,
+            }),
+            AddToken {
+                basket: *basket_pda,
+                allowlist: allowlist_pda(basket_pda),
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        process_ix(context, ix, payer).await
+    }
+
+    fn remove_token_ix(
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        token_mint: Pubkey,
+        basket_token: Option<Pubkey>,
+    ) -> Instruction {
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::RemoveToken { token_mint }),
+            RemoveToken {
+                basket: *basket_pda,
+                basket_token,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        )
+    }
+
+    fn update_token_weight_ix(
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        token_mint: Pubkey,
+        new_weight: u8,
+    ) -> Instruction {
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::UpdateTokenWeight {
+                token_mint,
+                new_weight,
+            }),
+            UpdateTokenWeight {
+                basket: *basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        )
+    }
+
+    fn assert_basket_error(
+        result: std::result::Result<(), BanksClientError>,
+        expected: BasketError,
+    ) {
+        let code = match result.unwrap_err().unwrap() {
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => code,
+            err => panic!("unexpected error: {:?}", err),
+        };
+        assert_eq!(code, u32::from(expected));
+    }
+
     async fn airdrop_sol(
         context: &mut ProgramTestContext,
         to: &Pubkey,
@@ -148,10 +615,53 @@ mod tests {
         payer: &Keypair,
         basket_pda: &Pubkey,
         mint_pda: &Pubkey,
-    ) -> Result<()> {
+    ) -> std::result::Result<(), BanksClientError> {
+        initialize_basket_with_id(context, payer, 0, basket_pda, mint_pda).await
+    }
+
+    async fn initialize_basket_with_id(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_id: u64,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        initialize_basket_with_args(context, payer, initialize_args(basket_id), basket_pda, mint_pda).await
+    }
+
+    fn fee_recipient() -> Pubkey {
+        Pubkey::new_from_array([7u8; 32])
+    }
+
+    fn initialize_args(basket_id: u64) -> crate::instruction::Initialize {
+        crate::instruction::Initialize {
+            basket_id,
+            max_tokens: 10,
+            entry_fee_bps: 0,
+            exit_fee_bps: 0,
+            fee_recipient: fee_recipient(),
+            max_deposit_per_user: 0,
+            max_tvl_lamports: 0,
+            min_weight: 0,
+            minimum_deposit: 0,
+            management_fee_bps: 0,
+            timelock_seconds: 0,
+            decimals: 9,
+            performance_fee_bps: 0,
+        }
+    }
+
+    async fn initialize_basket_with_args(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        args: crate::instruction::Initialize,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
         let accounts = Initialize {
             basket: *basket_pda,
             basket_mint: *mint_pda,
+            registry: registry_pda(),
             authority: payer.pubkey(),
             system_program: system_program::ID,
             token_program: token::ID,
@@ -160,7 +670,7 @@ mod tests {
 
         let ix = Instruction::new_with_bytes(
             id(),
-            &[0], // Initialize instruction
+            &anchor_lang::InstructionData::data(&args),
             accounts.to_account_metas(None),
         );
 
@@ -175,137 +685,617 @@ mod tests {
         Ok(())
     }
 
-    // Helper function to create mock Jupiter accounts
-    async fn create_mock_jupiter_accounts(
+    async fn setup_basket_with_tokens(
         context: &mut ProgramTestContext,
-        num_pairs: usize,
-    ) -> Vec<AccountMeta> {
-        let mut accounts = Vec::new();
-        for _ in 0..num_pairs {
-            let token_account = Keypair::new();
-            let destination = Keypair::new();
-
-            // Fund accounts
-            airdrop_sol(context, &token_account.pubkey(), 1_000_000_000).await;
-            airdrop_sol(context, &destination.pubkey(), 1_000_000_000).await;
-
-            // Add main accounts
-            accounts.push(AccountMeta::new(token_account.pubkey(), false));
-            accounts.push(AccountMeta::new(destination.pubkey(), false));
-            
-            // Add remaining required accounts (mocked)
-            for _ in 0..10 {
-                let mock_account = Keypair::new();
-                accounts.push(AccountMeta::new(mock_account.pubkey(), false));
-            }
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        weights: &[u8],
+    ) -> Vec<Pubkey> {
+        let mut mints = Vec::new();
+        for weight in weights {
+            let token_mint = create_mint(context, payer).await;
+            add_token(context, payer, basket_pda, token_mint, *weight).await.unwrap();
+            set_price_feed(context, payer, basket_pda, token_mint).await;
+            set_pyth_price(context, &price_feed_for(&token_mint), 1, 0).await;
+            initialize_token_account(context, payer, basket_pda, token_mint).await.unwrap();
+            mints.push(token_mint);
         }
-        accounts
+        mints
     }
 
-    #[tokio::test]
-    async fn test_initialize() {
-        let (mut context, payer, basket_pda, mint_pda) = setup().await;
-        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
-            .await
-            .unwrap();
+    async fn initialize_token_account(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        token_mint: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(
+                &crate::instruction::InitializeTokenAccount { token_mint },
+            ),
+            InitializeTokenAccount {
+                basket: *basket_pda,
+                mint: token_mint,
+                basket_token_account: get_associated_token_address(basket_pda, &token_mint),
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+                associated_token_program: associated_token::ID,
+            }.to_account_metas(None),
+        );
 
-        // Verify basket state
-        let basket_account = context.banks_client
-            .get_account(basket_pda)
-            .await
-            .unwrap()
-            .unwrap();
+        process_ix(context, ix, payer).await
+    }
 
-        let basket_state = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
-        assert_eq!(basket_state.authority, payer.pubkey());
-        assert_eq!(basket_state.tokens.len(), 0);
-        assert_eq!(basket_state.total_supply, 0);
+    fn registry_pda() -> Pubkey {
+        Pubkey::find_program_address(&[b"basket_registry"], &id()).0
     }
 
-    #[tokio::test]
-    async fn test_add_token() {
-        let (mut context, payer, basket_pda, mint_pda) = setup().await;
-        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
-            .await
-            .unwrap();
+    fn allowlist_pda(basket_pda: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"token_allowlist", basket_pda.as_ref()], &id()).0
+    }
 
-        let token_mint = create_mint(&mut context, &payer).await;
-        
-        // Add token with 50% weight
-        let accounts = AddToken {
-            basket: basket_pda,
-            authority: payer.pubkey(),
-        };
+    fn price_feed_for(mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"mock_price", mint.as_ref()], &PYTH_PROGRAM_ID).0
+    }
 
+    async fn set_price_feed(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        token_mint: Pubkey,
+    ) {
         let ix = Instruction::new_with_bytes(
             id(),
-            &anchor_lang::InstructionData::data(&basket_token::instruction::AddToken {
+            &anchor_lang::InstructionData::data(&crate::instruction::SetPriceFeed {
                 token_mint,
-                weight: 50,
+                price_feed: price_feed_for(&token_mint),
+                price_source: PriceSource::Pyth,
             }),
-            accounts.to_account_metas(None),
+            SetPriceFeed {
+                basket: *basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
         );
 
-        let transaction = Transaction::new_signed_with_payer(
-            &[ix],
-            Some(&payer.pubkey()),
-            &[&payer],
-            context.last_blockhash,
-        );
+        process_ix(context, ix, payer).await.unwrap();
+    }
 
-        context.banks_client.process_transaction(transaction).await.unwrap();
+    fn pyth_price_data(price: i64, expo: i32, publish_time: i64) -> Vec<u8> {
+        let mut data = vec![0u8; nav::PYTH_PRICE_ACCOUNT_MIN_SIZE];
+        data[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes()); // magic
+        data[8..12].copy_from_slice(&3u32.to_le_bytes()); // price account
+        data[20..24].copy_from_slice(&expo.to_le_bytes());
+        data[96..104].copy_from_slice(&publish_time.to_le_bytes());
+        data[208..216].copy_from_slice(&price.to_le_bytes()); // aggregate price
+        data[224..228].copy_from_slice(&1u32.to_le_bytes()); // trading
+        data
+    }
 
-        // Verify token was added
-        let basket_account = context.banks_client
-            .get_account(basket_pda)
-            .await
-            .unwrap()
-            .unwrap();
+    // Mock a Pyth price account quoting lamports per token base unit
+    async fn set_pyth_price(
+        context: &mut ProgramTestContext,
+        price_feed: &Pubkey,
+        price: i64,
+        expo: i32,
+    ) {
+        let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        let data = pyth_price_data(price, expo, clock.unix_timestamp);
+        let rent = context.banks_client.get_rent().await.unwrap();
 
-        let basket_state = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
-        assert_eq!(basket_state.tokens.len(), 1);
-        assert_eq!(basket_state.tokens[0].mint, token_mint);
-        assert_eq!(basket_state.tokens[0].weight, 50);
+        let mut account = AccountSharedData::new(
+            rent.minimum_balance(data.len()),
+            data.len(),
+            &PYTH_PROGRAM_ID,
+        );
+        account.set_data(data);
+        context.set_account(price_feed, &account);
     }
 
-    #[tokio::test]
-    async fn test_deposit() {
-        let (mut context, payer, basket_pda, mint_pda) = setup().await;
-        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
-            .await
-            .unwrap();
+    // Mock a Switchboard aggregator whose latest round is `mantissa * 10^-scale`
+    async fn set_switchboard_price(
+        context: &mut ProgramTestContext,
+        aggregator: &Pubkey,
+        mantissa: i128,
+        scale: u32,
+    ) {
+        let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        let mut data = vec![0u8; nav::SWITCHBOARD_AGGREGATOR_MIN_SIZE];
+        data[0..8].copy_from_slice(&[217, 230, 65, 101, 201, 162, 27, 125]);
+        data[358..366].copy_from_slice(&clock.unix_timestamp.to_le_bytes()); // round open
+        data[366..382].copy_from_slice(&mantissa.to_le_bytes());
+        data[382..386].copy_from_slice(&scale.to_le_bytes());
+        let rent = context.banks_client.get_rent().await.unwrap();
 
-        // Create and add two tokens
-        let token1 = create_mint(&mut context, &payer).await;
-        let token2 = create_mint(&mut context, &payer).await;
+        let mut account = AccountSharedData::new(
+            rent.minimum_balance(data.len()),
+            data.len(),
+            &SWITCHBOARD_PROGRAM_ID,
+        );
+        account.set_data(data);
+        context.set_account(aggregator, &account);
+    }
 
-        // Add tokens with equal weights
-        let add_token_ix1 = Instruction::new_with_bytes(
+    fn user_deposit_pda(basket_pda: &Pubkey, user: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"user_deposit", basket_pda.as_ref(), user.as_ref()],
+            &id(),
+        ).0
+    }
+
+    fn deposit_args(amount: u64, token_count: usize) -> crate::instruction::Deposit {
+        crate::instruction::Deposit {
+            amount,
+            jupiter_quotes: vec![[0u8; 32]; token_count],
+            slippage_bps: 100,
+            minimum_shares_out: 0,
+            accounts_per_swap: vec![12; token_count],
+            nonce: None,
+            continue_on_leg_failure: false,
+            max_allocation_lamports: None,
+        }
+    }
+
+    fn deposit_ix(
+        user: &Keypair,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+        args: crate::instruction::Deposit,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut accounts = Deposit {
+            basket: *basket_pda,
+            basket_mint: *mint_pda,
+            user_basket_token: get_associated_token_address(&user.pubkey(), mint_pda),
+            basket_share_token: get_associated_token_address(basket_pda, mint_pda),
+            native_mint: token::spl_token::native_mint::ID,
+            basket_wsol: get_associated_token_address(basket_pda, &token::spl_token::native_mint::ID),
+            fee_recipient: fee_recipient(),
+            user_deposit: user_deposit_pda(basket_pda, &user.pubkey()),
+            swap_program: jupiter::JUPITER_V6_ID,
+            user: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+        }.to_account_metas(None);
+        accounts.extend(remaining_accounts);
+
+        Instruction::new_with_bytes(
             id(),
-            &anchor_lang::InstructionData::data(&basket_token::instruction::AddToken {
-                token_mint: token1,
-                weight: 50,
+            &anchor_lang::InstructionData::data(&args),
+            accounts,
+        )
+    }
+
+    // Helper function to build swap accounts whose balance slot is the basket's
+    // real token account for each mint
+    async fn create_basket_swap_accounts(
+        context: &mut ProgramTestContext,
+        basket_pda: &Pubkey,
+        mints: &[Pubkey],
+    ) -> Vec<AccountMeta> {
+        let strides = vec![12; mints.len()];
+        create_basket_swap_accounts_with_strides(context, basket_pda, mints, &strides).await
+    }
+
+    async fn create_basket_swap_accounts_with_strides(
+        context: &mut ProgramTestContext,
+        basket_pda: &Pubkey,
+        mints: &[Pubkey],
+        strides: &[u8],
+    ) -> Vec<AccountMeta> {
+        let mut accounts = Vec::new();
+        for (mint, stride) in mints.iter().zip(strides) {
+            let source = Keypair::new();
+            airdrop_sol(context, &source.pubkey(), 1_000_000_000).await;
+            let basket_token = get_associated_token_address(basket_pda, mint);
+
+            accounts.push(AccountMeta::new(source.pubkey(), false));
+            accounts.push(AccountMeta::new(basket_token, false));
+            for _ in 3..*stride {
+                accounts.push(AccountMeta::new(Keypair::new().pubkey(), false));
+            }
+            // The swap CPI needs the router's program account in the transaction
+            accounts.push(AccountMeta::new_readonly(jupiter::JUPITER_V6_ID, false));
+        }
+
+        // Price accounts trail the swap accounts
+        for mint in mints {
+            accounts.push(AccountMeta::new_readonly(price_feed_for(mint), false));
+        }
+        accounts
+    }
+
+    fn jupiter_mint_authority() -> Pubkey {
+        Pubkey::find_program_address(&[b"mint_authority"], &jupiter::JUPITER_V6_ID).0
+    }
+
+    // Lets the mock Jupiter program mint `mint` as the output of token routes
+    async fn give_mint_to_jupiter(context: &mut ProgramTestContext, payer: &Keypair, mint: &Pubkey) {
+        let set_authority_ix = token::spl_token::instruction::set_authority(
+            &token::ID,
+            mint,
+            Some(&jupiter_mint_authority()),
+            token::spl_token::instruction::AuthorityType::MintTokens,
+            &payer.pubkey(),
+            &[],
+        ).unwrap();
+        process_ix(context, set_authority_ix, payer).await.unwrap();
+    }
+
+    // One 10-account mock token route selling `source` into `destination`;
+    // the mock mints `destination_mint`, so it must be given to Jupiter first
+    fn token_route(
+        basket_pda: &Pubkey,
+        source: &Pubkey,
+        source_mint: &Pubkey,
+        source_token_program: &Pubkey,
+        destination: &Pubkey,
+        destination_mint: &Pubkey,
+    ) -> Vec<AccountMeta> {
+        let vault = get_associated_token_address_with_program_id(
+            &jupiter_mint_authority(),
+            source_mint,
+            source_token_program,
+        );
+        vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*basket_pda, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*source_mint, false),
+            AccountMeta::new_readonly(*source_token_program, false),
+            AccountMeta::new(*destination_mint, false),
+            AccountMeta::new_readonly(jupiter_mint_authority(), false),
+            AccountMeta::new_readonly(token::ID, false),
+            // The swap CPI needs the router's program account in the transaction
+            AccountMeta::new_readonly(jupiter::JUPITER_V6_ID, false),
+        ]
+    }
+
+    // Mock route selling a basket-owned `source` token account, carried in the
+    // balance slot, for SOL paid into the basket from a Jupiter-owned reserve
+    async fn create_sol_route_accounts(
+        context: &mut ProgramTestContext,
+        basket_pda: &Pubkey,
+        source: &Pubkey,
+        source_mint: &Pubkey,
+    ) -> Vec<AccountMeta> {
+        let vault = create_token_account(context, source_mint, &jupiter_mint_authority()).await;
+        let reserve = Pubkey::new_unique();
+        context.set_account(
+            &reserve,
+            &AccountSharedData::new(100_000_000_000, 0, &jupiter::JUPITER_V6_ID),
+        );
+
+        vec![
+            AccountMeta::new(*basket_pda, false),
+            AccountMeta::new(*source, false),
+            AccountMeta::new_readonly(*basket_pda, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*source_mint, false),
+            AccountMeta::new_readonly(token::ID, false),
+            AccountMeta::new(reserve, false),
+            // The swap CPI needs the router's program account in the transaction
+            AccountMeta::new_readonly(jupiter::JUPITER_V6_ID, false),
+        ]
+    }
+
+    // Helper function to build token routes from a basket-owned `source`
+    // token account into the basket's account for each mint
+    async fn create_token_route_accounts(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        source: &Pubkey,
+        source_mint: &Pubkey,
+        source_token_program: &Pubkey,
+        mints: &[Pubkey],
+    ) -> Vec<AccountMeta> {
+        create_token_account_with_program(
+            context,
+            source_mint,
+            &jupiter_mint_authority(),
+            source_token_program,
+        ).await;
+        let mut accounts = Vec::new();
+        for mint in mints {
+            give_mint_to_jupiter(context, payer, mint).await;
+            accounts.extend(token_route(
+                basket_pda,
+                source,
+                source_mint,
+                source_token_program,
+                &get_associated_token_address(basket_pda, mint),
+                mint,
+            ));
+        }
+
+        // Price accounts trail the swap accounts
+        for mint in mints {
+            accounts.push(AccountMeta::new_readonly(price_feed_for(mint), false));
+        }
+        accounts
+    }
+
+    fn deposit_token_args(
+        input_mint: &Pubkey,
+        amount: u64,
+        token_count: usize,
+    ) -> crate::instruction::DepositToken {
+        crate::instruction::DepositToken {
+            token_mint: *input_mint,
+            amount,
+            jupiter_quotes: vec![[0u8; 32]; token_count],
+            slippage_bps: 100,
+            minimum_token_amounts: vec![0; token_count],
+            expected_token_amounts: vec![0; token_count],
+            accounts_per_swap: vec![10; token_count],
+        }
+    }
+
+    // Deposits `amount` of `input_mint` from the user's associated account,
+    // routed through the 10-account token routes in `remaining_accounts`
+    fn deposit_token_ix(
+        user: &Keypair,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+        input_mint: &Pubkey,
+        input_token_program: &Pubkey,
+        amount: u64,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let token_count = remaining_accounts.len() / 10;
+        deposit_token_ix_with_args(
+            user,
+            basket_pda,
+            mint_pda,
+            input_token_program,
+            deposit_token_args(input_mint, amount, token_count),
+            remaining_accounts,
+        )
+    }
+
+    fn deposit_token_ix_with_args(
+        user: &Keypair,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+        input_token_program: &Pubkey,
+        args: crate::instruction::DepositToken,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let input_mint = &args.token_mint;
+        let mut accounts = DepositToken {
+            basket: *basket_pda,
+            basket_mint: *mint_pda,
+            user_basket_token: get_associated_token_address(&user.pubkey(), mint_pda),
+            basket_share_token: get_associated_token_address(basket_pda, mint_pda),
+            user_input_token: get_associated_token_address_with_program_id(
+                &user.pubkey(),
+                input_mint,
+                input_token_program,
+            ),
+            basket_input_token: get_associated_token_address_with_program_id(
+                basket_pda,
+                input_mint,
+                input_token_program,
+            ),
+            input_mint: *input_mint,
+            input_token_program: *input_token_program,
+            user_deposit: user_deposit_pda(basket_pda, &user.pubkey()),
+            swap_program: jupiter::JUPITER_V6_ID,
+            user: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+        }.to_account_metas(None);
+        accounts.extend(remaining_accounts);
+
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&args),
+            accounts,
+        )
+    }
+
+    async fn allow_input_mint(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        input_mint: Pubkey,
+    ) {
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetInputMint {
+                input_mint,
+                allowed: true,
             }),
-            AddToken {
-                basket: basket_pda,
+            SetInputMint {
+                basket: *basket_pda,
                 authority: payer.pubkey(),
             }.to_account_metas(None),
         );
+        process_ix(context, ix, payer).await.unwrap();
+    }
+
+    // Token-2022 mint charging `fee_bps` on every transfer
+    async fn create_transfer_fee_mint(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        fee_bps: u16,
+    ) -> Pubkey {
+        let mint = Keypair::new();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let space = ExtensionType::get_account_len::<spl_token_2022::state::Mint>(&[
+            ExtensionType::TransferFeeConfig,
+        ]);
+
+        let create_ix = system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::ID,
+        );
+        let fee_config_ix = transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::ID,
+            &mint.pubkey(),
+            Some(&payer.pubkey()),
+            Some(&payer.pubkey()),
+            fee_bps,
+            u64::MAX,
+        ).unwrap();
+        let initialize_ix = spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::ID,
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            9,
+        ).unwrap();
+
+        let transaction = Transaction::new_signed_with_payer(
+            &[create_ix, fee_config_ix, initialize_ix],
+            Some(&payer.pubkey()),
+            &[payer, &mint],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+        mint.pubkey()
+    }
+
+    fn redeem_args(amount: u64, token_count: usize) -> crate::instruction::Redeem {
+        crate::instruction::Redeem {
+            amount,
+            jupiter_quotes: vec![[0u8; 32]; token_count],
+            slippage_bps: 100,
+            minimum_sol_amounts: vec![0; token_count],
+            minimum_sol_amount: 0,
+            accounts_per_swap: vec![12; token_count],
+        }
+    }
+
+    fn redeem_ix(
+        user: &Keypair,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+        args: impl anchor_lang::InstructionData,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut accounts = Redeem {
+            basket: *basket_pda,
+            basket_mint: *mint_pda,
+            user_basket_token: get_associated_token_address(&user.pubkey(), mint_pda),
+            fee_recipient: fee_recipient(),
+            user_deposit: user_deposit_pda(basket_pda, &user.pubkey()),
+            swap_program: jupiter::JUPITER_V6_ID,
+            user: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: token::ID,
+        }.to_account_metas(None);
+        accounts.extend(remaining_accounts);
+
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&args),
+            accounts,
+        )
+    }
+
+    fn decode_events<T: AnchorDeserialize + anchor_lang::Discriminator>(logs: &[String]) -> Vec<T> {
+        logs.iter()
+            .filter_map(|log| event_data(log))
+            .filter_map(|data| anchor_lang::__private::base64::decode(data).ok())
+            .filter(|data| data.starts_with(&T::discriminator()))
+            .map(|data| T::deserialize(&mut &data[8..]).unwrap())
+            .collect()
+    }
+
+    async fn simulate_return_data<T: AnchorDeserialize>(
+        context: &mut ProgramTestContext,
+        ix: Instruction,
+        signer: &Keypair,
+    ) -> T {
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&signer.pubkey()),
+            &[signer],
+            context.last_blockhash,
+        );
+        let simulation = context.banks_client
+            .simulate_transaction(transaction)
+            .await
+            .unwrap();
+        // The runtime trims trailing zero bytes from return data (dropping it
+        // entirely when nothing is left), so pad it back out before decoding
+        let mut data = simulation
+            .simulation_details
+            .unwrap()
+            .return_data
+            .map(|return_data| {
+                assert_eq!(return_data.program_id, id());
+                return_data.data
+            })
+            .unwrap_or_default();
+        data.resize(data.len() + 1024, 0);
+
+        T::deserialize(&mut &data[..]).unwrap()
+    }
+
+    fn logged_in_amounts(logs: &[String]) -> Vec<u64> {
+        logs.iter()
+            .filter_map(|log| log.split("Jupiter in_amount: ").nth(1))
+            .map(|amount| amount.trim().parse().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_initialize() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Verify basket state
+        let basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let basket_state = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        assert_eq!(basket_state.authority, payer.pubkey());
+        assert_eq!(basket_state.tokens.len(), 0);
+        assert_eq!(basket_state.total_supply, 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_token() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let token_mint = create_mint(&mut context, &payer).await;
+        
+        // Add token with 50% weight
+        let accounts = AddToken {
+            basket: basket_pda,
+            allowlist: allowlist_pda(&basket_pda),
+            authority: payer.pubkey(),
+        };
 
-        let add_token_ix2 = Instruction::new_with_bytes(
+        let ix = Instruction::new_with_bytes(
             id(),
-            &anchor_lang::InstructionData::data(&basket_token::instruction::AddToken {
-                token_mint: token2,
+            &anchor_lang::InstructionData::data(&crate::instruction::AddToken {
+                token_mint,
                 weight: 50,
             }),
-            AddToken {
-                basket: basket_pda,
-                authority: payer.pubkey(),
-            }.to_account_metas(None),
+            accounts.to_account_metas(None),
         );
 
         let transaction = Transaction::new_signed_with_payer(
-            &[add_token_ix1, add_token_ix2],
+            &[ix],
             Some(&payer.pubkey()),
             &[&payer],
             context.last_blockhash,
@@ -313,6 +1303,29 @@ mod tests {
 
         context.banks_client.process_transaction(transaction).await.unwrap();
 
+        // Verify token was added
+        let basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let basket_state = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        assert_eq!(basket_state.tokens.len(), 1);
+        assert_eq!(basket_state.tokens[0].mint, token_mint);
+        assert_eq!(basket_state.tokens[0].weight, 50);
+    }
+
+    #[tokio::test]
+    async fn test_deposit() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Create and add two tokens with equal weights
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+
         // Set up deposit
         let user = Keypair::new();
         airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
@@ -323,6 +1336,12 @@ mod tests {
             basket: basket_pda,
             basket_mint: mint_pda,
             user_basket_token,
+            basket_share_token: get_associated_token_address(&basket_pda, &mint_pda),
+            native_mint: token::spl_token::native_mint::ID,
+            basket_wsol: get_associated_token_address(&basket_pda, &token::spl_token::native_mint::ID),
+            fee_recipient: fee_recipient(),
+            user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+            swap_program: jupiter::JUPITER_V6_ID,
             user: user.pubkey(),
             system_program: system_program::ID,
             token_program: token::ID,
@@ -330,22 +1349,26 @@ mod tests {
         };
 
         let deposit_amount = 1_000_000_000; // 1 SOL
-        let jupiter_quote = [0u8; 32];
+        let jupiter_quotes = vec![[0u8; 32]; 2];
         let slippage_bps = 100;
-        let minimum_token_amounts = vec![100_000_000, 100_000_000];
+        let minimum_shares_out = deposit_amount - basket_token::MINIMUM_LIQUIDITY;
 
         // Create mock Jupiter accounts
-        let mock_accounts = create_mock_jupiter_accounts(&mut context, 2).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
         let mut all_accounts = accounts.to_account_metas(None);
-        all_accounts.extend(mock_accounts);
+        all_accounts.extend(swap_accounts);
 
         let ix = Instruction::new_with_bytes(
             id(),
-            &anchor_lang::InstructionData::data(&basket_token::instruction::Deposit {
+            &anchor_lang::InstructionData::data(&crate::instruction::Deposit {
                 amount: deposit_amount,
-                jupiter_quote,
+                jupiter_quotes,
                 slippage_bps,
-                minimum_token_amounts,
+                minimum_shares_out,
+                accounts_per_swap: vec![12, 12],
+                nonce: None,
+                continue_on_leg_failure: false,
+                max_allocation_lamports: None,
             }),
             all_accounts,
         );
@@ -366,7 +1389,5487 @@ mod tests {
             .unwrap()
             .unwrap();
 
-        let token_balance = TokenAccount::unpack(&user_token_account.data[..]).unwrap();
-        assert_eq!(token_balance.amount, deposit_amount);
+        let token_balance = TokenAccount::try_deserialize(&mut &user_token_account.data[..]).unwrap();
+        assert_eq!(token_balance.amount, deposit_amount - basket_token::MINIMUM_LIQUIDITY);
+    }
+
+    #[tokio::test]
+    async fn test_emergency_withdraw() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Seed a token account owned by the basket PDA
+        let token_mint = create_mint(&mut context, &payer).await;
+        let basket_token = create_token_account(&mut context, &token_mint, &basket_pda).await;
+        let authority_token = create_token_account(&mut context, &token_mint, &payer.pubkey()).await;
+        mint_tokens(&mut context, &token_mint, &payer, &basket_token, 500_000_000).await;
+
+        let withdraw_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::EmergencyWithdraw {}),
+            EmergencyWithdraw {
+                basket: basket_pda,
+                basket_token,
+                authority_token,
+                mint: token_mint,
+                authority: payer.pubkey(),
+                token_program: token::ID,
+            }.to_account_metas(None),
+        );
+
+        // Rejected while the basket is live
+        assert!(process_ix(&mut context, withdraw_ix(), &payer).await.is_err());
+
+        set_pause_state(&mut context, &payer, &basket_pda, true, true).await;
+        process_ix(&mut context, withdraw_ix(), &payer).await.unwrap();
+
+        assert_eq!(token_balance(&mut context, &basket_token).await, 0);
+        assert_eq!(token_balance(&mut context, &authority_token).await, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_emergency_withdraw_leaves_reserved_tokens() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        // A split redemption of half the supply reserves half the holding
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::BeginRedeem {
+                shares: 500_000_000,
+            }),
+            [
+                BeginRedeem {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    user_basket_token: get_associated_token_address(&user.pubkey(), &mint_pda),
+                    user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+                    pending_redeem: Pubkey::find_program_address(
+                        &[b"pending_redeem", basket_pda.as_ref(), user.pubkey().as_ref()],
+                        &id(),
+                    ).0,
+                    user: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                vec![AccountMeta::new_readonly(basket_token, false)],
+            ].concat(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let authority_token = create_token_account(&mut context, &mints[0], &payer.pubkey()).await;
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::EmergencyWithdraw {}),
+            EmergencyWithdraw {
+                basket: basket_pda,
+                basket_token,
+                authority_token,
+                mint: mints[0],
+                authority: payer.pubkey(),
+                token_program: token::ID,
+            }.to_account_metas(None),
+        );
+        set_pause_state(&mut context, &payer, &basket_pda, true, true).await;
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // Only the unreserved half left the basket
+        assert_eq!(token_balance(&mut context, &basket_token).await, 500_000_000);
+        assert_eq!(token_balance(&mut context, &authority_token).await, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_update_token_weight() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let token1 = create_mint(&mut context, &payer).await;
+        let token2 = create_mint(&mut context, &payer).await;
+        add_token(&mut context, &payer, &basket_pda, token1, 50).await.unwrap();
+        add_token(&mut context, &payer, &basket_pda, token2, 30).await.unwrap();
+
+        let ix = update_token_weight_ix(&payer, &basket_pda, token2, 50);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.tokens[1].mint, token2);
+        assert_eq!(basket_state.tokens[1].weight, 50);
+    }
+
+    #[tokio::test]
+    async fn test_update_token_weight_overflow() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let token1 = create_mint(&mut context, &payer).await;
+        let token2 = create_mint(&mut context, &payer).await;
+        add_token(&mut context, &payer, &basket_pda, token1, 50).await.unwrap();
+        add_token(&mut context, &payer, &basket_pda, token2, 30).await.unwrap();
+
+        let ix = update_token_weight_ix(&payer, &basket_pda, token2, 51);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::WeightOverflow);
+    }
+
+    #[tokio::test]
+    async fn test_update_token_weight_unknown_mint() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let ix = update_token_weight_ix(&payer, &basket_pda, Pubkey::new_unique(), 10);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::TokenNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_quote_count_mismatch() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                jupiter_quotes: vec![[1u8; 32]],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidTokenCount);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_uses_quote_per_token() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                jupiter_quotes: vec![[1u8; 32], [2u8; 32]],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[&user],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        let logs = result.metadata.unwrap().log_messages;
+        let quotes: Vec<&String> = logs
+            .iter()
+            .filter(|log| log.contains("Jupiter quote"))
+            .collect();
+        assert_eq!(quotes.len(), 2);
+        assert!(quotes[0].contains(&format!("{:?}", [1u8; 32])));
+        assert!(quotes[1].contains(&format!("{:?}", [2u8; 32])));
+    }
+
+    #[tokio::test]
+    async fn test_redeem_sells_proportional_to_basket_holdings() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // Give the PDA uneven underlying holdings
+        mint_tokens(&mut context, &mints[0], &payer, &swap_accounts[1].pubkey, 400_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &swap_accounts[13].pubkey, 100_000).await;
+
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(500_000_000, 2),
+            swap_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[&user],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        let logs = result.metadata.unwrap().log_messages;
+        assert_eq!(logged_in_amounts(&logs), vec![200_000, 50_000]);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_shares_track_nav() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // First deposit bootstraps shares 1:1
+        let first_user = Keypair::new();
+        airdrop_sol(&mut context, &first_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &first_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &first_user).await.unwrap();
+        let first_shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&first_user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(first_shares, 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+
+        // The basket has doubled in value, so the same deposit buys half the shares
+        mint_tokens(&mut context, &mints[0], &payer, &swap_accounts[1].pubkey, 1_000_000_000).await;
+        let second_user = Keypair::new();
+        airdrop_sol(&mut context, &second_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &second_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &second_user).await.unwrap();
+        let second_shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&second_user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(second_shares, 500_000_000);
+
+        // Existing holders keep two thirds of the basket rather than half
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        let existing_shares = first_shares + basket_token::MINIMUM_LIQUIDITY;
+        assert_eq!(basket_state.total_supply, existing_shares + second_shares);
+        assert_eq!(existing_shares * 3, basket_state.total_supply * 2);
+    }
+
+    fn accept_authority_ix(basket_pda: &Pubkey, new_authority: &Keypair) -> Instruction {
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::AcceptAuthority {}),
+            AcceptAuthority {
+                basket: *basket_pda,
+                new_authority: new_authority.pubkey(),
+            }.to_account_metas(None),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_authority_transfer() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let new_authority = Keypair::new();
+        let impostor = Keypair::new();
+        airdrop_sol(&mut context, &new_authority.pubkey(), 100_000_000).await;
+        airdrop_sol(&mut context, &impostor.pubkey(), 100_000_000).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ProposeAuthority {
+                new_authority: new_authority.pubkey(),
+            }),
+            ProposeAuthority {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.authority, payer.pubkey());
+        assert_eq!(basket_state.pending_authority, Some(new_authority.pubkey()));
+
+        // Only the proposed authority can accept
+        let result = process_ix(&mut context, accept_authority_ix(&basket_pda, &impostor), &impostor).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+
+        let ix = accept_authority_ix(&basket_pda, &new_authority);
+        process_ix(&mut context, ix, &new_authority).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.authority, new_authority.pubkey());
+        assert_eq!(basket_state.pending_authority, None);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_and_redeem_emit_events() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        let deposit = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        let redeem = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(400_000_000, 2),
+            swap_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[deposit, redeem],
+            Some(&user.pubkey()),
+            &[&user],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+        let logs = result.metadata.unwrap().log_messages;
+
+        let deposits = decode_events::<DepositEvent>(&logs);
+        assert_eq!(deposits.len(), 1);
+        assert_eq!(deposits[0].user, user.pubkey());
+        assert_eq!(deposits[0].sol_in, 1_000_000_000);
+        assert_eq!(deposits[0].shares_minted, 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+
+        let redeems = decode_events::<RedeemEvent>(&logs);
+        assert_eq!(redeems.len(), 1);
+        assert_eq!(redeems[0].user, user.pubkey());
+        assert_eq!(redeems[0].shares_burned, 400_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_independent_baskets() {
+        let (mut context, payer, first_basket, first_mint) = setup().await;
+        let (second_basket, second_mint) = basket_pdas(1);
+        assert_ne!(first_basket, second_basket);
+        assert_ne!(first_mint, second_mint);
+
+        initialize_basket_with_id(&mut context, &payer, 0, &first_basket, &first_mint)
+            .await
+            .unwrap();
+        initialize_basket_with_id(&mut context, &payer, 1, &second_basket, &second_mint)
+            .await
+            .unwrap();
+
+        setup_basket_with_tokens(&mut context, &payer, &first_basket, &[60, 40]).await;
+        let second_mints = setup_basket_with_tokens(&mut context, &payer, &second_basket, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &second_basket, &second_mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &second_basket,
+            &second_mint,
+            deposit_args(500_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let first_state = get_basket_state(&mut context, &first_basket).await;
+        let second_state = get_basket_state(&mut context, &second_basket).await;
+        assert_eq!(first_state.basket_id, 0);
+        assert_eq!(second_state.basket_id, 1);
+        assert_eq!(first_state.tokens.len(), 2);
+        assert_eq!(second_state.tokens.len(), 1);
+        assert_eq!(first_state.total_supply, 0);
+        assert_eq!(second_state.total_supply, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_entry_fee() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                entry_fee_bps: 100,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let recipient_balance = context.banks_client
+            .get_balance(fee_recipient())
+            .await
+            .unwrap();
+        assert_eq!(recipient_balance, 10_000_000);
+
+        let shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(shares, 990_000_000 - basket_token::MINIMUM_LIQUIDITY);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_rejects_fee_above_100_percent() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        let result = initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                entry_fee_bps: 10_001,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redeem_exit_fee() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                exit_fee_bps: 100,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        let basket_token = swap_accounts[1].pubkey;
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        // Half the supply: 0.5 SOL idle plus 0.5 SOL from selling the token leg
+        let mut sell_accounts = create_sol_route_accounts(&mut context, &basket_pda, &basket_token, &mints[0]).await;
+        sell_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+
+        // A floor above the post-fee proceeds reverts
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                minimum_sol_amount: 1_000_000_000,
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_000, 1)
+            },
+            sell_accounts.clone(),
+        );
+        // The payer covers fees so the user's balance only moves by proceeds
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        let result = context.banks_client.process_transaction(transaction).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                minimum_sol_amount: 990_000_000,
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_000, 1)
+            },
+            sell_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let recipient_balance = context.banks_client.get_balance(fee_recipient()).await.unwrap();
+        assert_eq!(user_after - user_before, 990_000_000);
+        assert_eq!(recipient_balance, 10_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_slippage_bps_out_of_bounds() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                slippage_bps: 10_001,
+                ..deposit_args(1_000_000_000, 1)
+            },
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidSlippage);
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                slippage_bps: 10_001,
+                ..redeem_args(1_000_000_000, 1)
+            },
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidSlippage);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_requires_complete_weights() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 30]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        // 80% allocated
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::WeightsNotComplete);
+
+        // 100% allocated
+        let ix = update_token_weight_ix(&payer, &basket_pda, mints[1], 50);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mixed_accounts_per_swap() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts =
+            create_basket_swap_accounts_with_strides(&mut context, &basket_pda, &mints, &[8, 14]).await;
+        assert_eq!(swap_accounts.len(), 8 + 14 + 2);
+
+        // Seed the balance slots at offsets 1 and 9
+        mint_tokens(&mut context, &mints[0], &payer, &swap_accounts[1].pubkey, 300).await;
+        mint_tokens(&mut context, &mints[1], &payer, &swap_accounts[9].pubkey, 700).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+
+        // A fixed stride no longer lines up with the supplied accounts
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidAccountCount);
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                accounts_per_swap: vec![8, 14],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts,
+        );
+        let blockhash = context.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[&user],
+            blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        // Both legs found their balance slot at their own offset and swapped
+        // their half of the deposit
+        assert!(result.result.is_ok());
+        let logs = result.metadata.unwrap().log_messages;
+        assert_eq!(logged_in_amounts(&logs), vec![500_000_000, 500_000_000]);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_while_deposits_paused() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        set_pause_state(&mut context, &payer, &basket_pda, true, false).await;
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(500_000_000, 1),
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::DepositsPaused);
+
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(1_000_000_000 - basket_token::MINIMUM_LIQUIDITY, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(basket_state.deposits_paused);
+        assert!(!basket_state.redemptions_paused);
+        assert_eq!(basket_state.total_supply, basket_token::MINIMUM_LIQUIDITY);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_per_user_cap() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                max_deposit_per_user: 1_500_000_000,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // The second deposit pushes the running total past the cap
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(600_000_000, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::DepositCapExceeded);
+
+        let record = context.banks_client
+            .get_account(user_deposit_pda(&basket_pda, &user.pubkey()))
+            .await
+            .unwrap()
+            .unwrap();
+        let record = UserDeposit::try_deserialize(&mut &record.data[..]).unwrap();
+        assert_eq!(record.total_deposited, 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_tvl_cap() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                max_tvl_lamports: 2_000_000_000,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 5_000_000_000).await;
+
+        // Fill the basket exactly to the cap; rent-exempt lamports don't count
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(2_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(10_000_000, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::TvlCapExceeded);
+    }
+
+    #[tokio::test]
+    async fn test_get_composition() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[70, 30]).await;
+        set_pause_state(&mut context, &payer, &basket_pda, true, false).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetComposition {}),
+            GetComposition { basket: basket_pda }.to_account_metas(None),
+        );
+        let composition: BasketComposition = simulate_return_data(&mut context, ix, &payer).await;
+
+        assert_eq!(composition.tokens.len(), 2);
+        assert_eq!(composition.tokens[0].mint, mints[0]);
+        assert_eq!(composition.tokens[0].weight, 70);
+        assert_eq!(composition.tokens[1].mint, mints[1]);
+        assert_eq!(composition.tokens[1].weight, 30);
+        assert_eq!(composition.total_supply, 0);
+        assert!(composition.deposits_paused);
+        assert!(!composition.redemptions_paused);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_per_leg_minimum() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        mint_tokens(&mut context, &mints[0], &payer, &swap_accounts[1].pubkey, 500_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &swap_accounts[13].pubkey, 100_000_000).await;
+
+        // The second leg underperforms its own floor even though the total clears
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                minimum_sol_amounts: vec![100_000_000, 400_000_000],
+                minimum_sol_amount: 500_000_000,
+                ..redeem_args(1_000_000_000 - basket_token::MINIMUM_LIQUIDITY, 2)
+            },
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                minimum_sol_amounts: vec![1_000_000_000],
+                ..redeem_args(1_000_000_000 - basket_token::MINIMUM_LIQUIDITY, 2)
+            },
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidTokenCount);
+    }
+
+    #[tokio::test]
+    async fn test_failed_swap_does_not_lock_basket() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+
+        // Second leg's route fails mid-deposit
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                jupiter_quotes: vec![[0u8; 32], [u8::MAX; 32]],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts.clone(),
+        );
+        assert!(process_ix(&mut context, ix, &user).await.is_err());
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(!basket_state.reentrancy_guard);
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(!basket_state.reentrancy_guard);
+        assert_eq!(basket_state.total_supply, 1_000_000_000);
+    }
+
+    #[test]
+    fn test_read_pyth_price_and_value() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = pyth_price_data(250_000_000, -9, 42);
+        let price_account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &PYTH_PROGRAM_ID,
+            false,
+            0,
+        );
+
+        let price = nav::read_pyth_price(&price_account).unwrap();
+        assert_eq!(price.price, 250_000_000);
+        assert_eq!(price.expo, -9);
+        assert_eq!(price.publish_time, 42);
+
+        // 4 base units at 0.25 lamports each
+        assert_eq!(nav::token_value(4, &price).unwrap(), 1);
+        assert_eq!(nav::token_value(4_000_000_000, &price).unwrap(), 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_prices_shares_from_oracle_nav() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let first_user = Keypair::new();
+        airdrop_sol(&mut context, &first_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &first_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &first_user).await.unwrap();
+
+        // Holdings worth 2 * 500M + 0.5 * 2B = 2 SOL on top of 1 SOL idle
+        mint_tokens(&mut context, &mints[0], &payer, &swap_accounts[1].pubkey, 500_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &swap_accounts[13].pubkey, 2_000_000_000).await;
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 2, 0).await;
+        set_pyth_price(&mut context, &price_feed_for(&mints[1]), 5, -1).await;
+
+        let second_user = Keypair::new();
+        airdrop_sol(&mut context, &second_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &second_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_500_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &second_user).await.unwrap();
+
+        let second_shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&second_user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(second_shares, 500_000_000);
+
+        // A price account that isn't the token's pinned feed is rejected
+        let mut wrong_accounts = swap_accounts;
+        let last = wrong_accounts.len() - 1;
+        wrong_accounts[last] = AccountMeta::new_readonly(price_feed_for(&mints[0]), false);
+        let ix = deposit_ix(
+            &second_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(100_000_000, 2),
+            wrong_accounts,
+        );
+        let result = process_ix(&mut context, ix, &second_user).await;
+        assert_basket_error(result, BasketError::InvalidPriceAccount);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_moves_value_between_tokens() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let (token_a, token_b) = (mints[0], mints[1]);
+
+        // Token A is over-weight: the basket holds only A
+        let from_token = get_associated_token_address(&basket_pda, &token_a);
+        let to_token = get_associated_token_address(&basket_pda, &token_b);
+        mint_tokens(&mut context, &token_a, &payer, &from_token, 1_000_000_000).await;
+
+        give_mint_to_jupiter(&mut context, &payer, &token_b).await;
+        create_token_account(&mut context, &token_a, &jupiter_mint_authority()).await;
+        let route = token_route(&basket_pda, &from_token, &token_a, &token::ID, &to_token, &token_b);
+
+        let rebalance_ix = |in_amount: u64| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::Rebalance {
+                from_mint: token_a,
+                to_mint: token_b,
+                in_amount,
+                jupiter_quote: [0; 32],
+                slippage_bps: 100,
+            }),
+            [
+                Rebalance {
+                    basket: basket_pda,
+                    from_token,
+                    to_token,
+                    swap_program: jupiter::JUPITER_V6_ID,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                route.clone(),
+            ].concat(),
+        );
+
+        process_ix(&mut context, rebalance_ix(400_000_000), &payer).await.unwrap();
+
+        assert_eq!(token_balance(&mut context, &from_token).await, 600_000_000);
+        assert_eq!(token_balance(&mut context, &to_token).await, 400_000_000);
+
+        // Spending more than the basket holds is rejected
+        let result = process_ix(&mut context, rebalance_ix(700_000_000), &payer).await;
+        assert_basket_error(result, BasketError::InsufficientBalance);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_token_mints_shares() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+
+        // Mock USDC: the user holds some, the basket has an input account for it
+        let usdc = create_mint(&mut context, &payer).await;
+        let user_input_token = create_token_account(&mut context, &usdc, &payer.pubkey()).await;
+        let basket_input_token = create_token_account(&mut context, &usdc, &basket_pda).await;
+        mint_tokens(&mut context, &usdc, &payer, &user_input_token, 1_000_000_000).await;
+
+        let remaining_accounts = create_token_route_accounts(
+            &mut context,
+            &payer,
+            &basket_pda,
+            &basket_input_token,
+            &usdc,
+            &token::ID,
+            &mints,
+        ).await;
+
+        let deposit_token_ix = || deposit_token_ix(
+            &payer,
+            &basket_pda,
+            &mint_pda,
+            &usdc,
+            &token::ID,
+            500_000_000,
+            remaining_accounts.clone(),
+        );
+
+        // USDC isn't accepted until the authority allows it
+        let result = process_ix(&mut context, deposit_token_ix(), &payer).await;
+        assert_basket_error(result, BasketError::InputMintNotAllowed);
+
+        allow_input_mint(&mut context, &payer, &basket_pda, usdc).await;
+
+        process_ix(&mut context, deposit_token_ix(), &payer).await.unwrap();
+
+        // Both legs were bought at a price of one lamport per unit
+        let user_basket_token = get_associated_token_address(&payer.pubkey(), &mint_pda);
+        assert_eq!(token_balance(&mut context, &user_basket_token).await, 500_000_000 - basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(token_balance(&mut context, &user_input_token).await, 500_000_000);
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[0])).await,
+            300_000_000
+        );
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[1])).await,
+            200_000_000
+        );
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_add_token_below_min_weight() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        let args = crate::instruction::Initialize {
+            min_weight: 5,
+            ..initialize_args(0)
+        };
+        initialize_basket_with_args(&mut context, &payer, args, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let token1 = create_mint(&mut context, &payer).await;
+        let result = add_token(&mut context, &payer, &basket_pda, token1, 1).await;
+        assert_basket_error(result, BasketError::WeightBelowMinimum);
+
+        add_token(&mut context, &payer, &basket_pda, token1, 5).await.unwrap();
+        let ix = update_token_weight_ix(&payer, &basket_pda, token1, 4);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::WeightBelowMinimum);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_below_custom_minimum() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                minimum_deposit: 100_000_000,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.minimum_deposit, 100_000_000);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 1_000_000_000).await;
+
+        // Above the global default but below this basket's floor
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(50_000_000, 1),
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InsufficientDeposit);
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(100_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_minimum_deposit_defaults_when_zero() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.minimum_deposit, basket_token::MINIMUM_DEPOSIT);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_respects_max_slippage() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let set_max_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetMaxSlippage {
+                max_slippage_bps: 50,
+            }),
+            SetMaxSlippage {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, set_max_ix, &payer).await.unwrap();
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 1_000_000_000).await;
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                slippage_bps: 51,
+                ..deposit_args(100_000_000, 1)
+            },
+            swap_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::SlippageTooHigh);
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                slippage_bps: 50,
+                ..deposit_args(100_000_000, 1)
+            },
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+    }
+
+    fn add_tokens_ix(payer: &Keypair, basket_pda: &Pubkey, tokens: Vec<(Pubkey, u8)>) -> Instruction {
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::AddTokens { tokens }),
+            AddToken {
+                basket: *basket_pda,
+                allowlist: allowlist_pda(basket_pda),
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_add_tokens_batch() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let tokens: Vec<(Pubkey, u8)> = (0..4).map(|_| (Pubkey::new_unique(), 25)).collect();
+        let ix = add_tokens_ix(&payer, &basket_pda, tokens.clone());
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.tokens.len(), 4);
+        for (token_info, (mint, weight)) in basket_state.tokens.iter().zip(tokens) {
+            assert_eq!(token_info.mint, mint);
+            assert_eq!(token_info.weight, weight);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_tokens_exceeds_max_tokens() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let tokens = (0..11).map(|_| (Pubkey::new_unique(), 1)).collect();
+        let ix = add_tokens_ix(&payer, &basket_pda, tokens);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::TooManyTokens);
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(basket_state.tokens.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_tokens_internal_duplicate() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let duplicate = Pubkey::new_unique();
+        let tokens = vec![(duplicate, 30), (Pubkey::new_unique(), 30), (duplicate, 30)];
+        let ix = add_tokens_ix(&payer, &basket_pda, tokens);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::DuplicateToken);
+
+        // Nothing from the failed batch was kept
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(basket_state.tokens.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_token_account_records_address() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(
+            basket_state.tokens[0].token_account,
+            get_associated_token_address(&basket_pda, &mints[0])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_unregistered_token_account() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let mut swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // A second basket-owned account for the same mint passes the mint and
+        // owner checks but isn't the registered holding
+        let decoy = Keypair::new();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let create_ix = system_instruction::create_account(
+            &payer.pubkey(),
+            &decoy.pubkey(),
+            rent.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            &token::ID,
+        );
+        let init_ix = token::spl_token::instruction::initialize_account3(
+            &token::ID,
+            &decoy.pubkey(),
+            &mints[0],
+            &basket_pda,
+        ).unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[create_ix, init_ix],
+            Some(&payer.pubkey()),
+            &[&payer, &decoy],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+        swap_accounts[1] = AccountMeta::new(decoy.pubkey(), false);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 1_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(100_000_000, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidTokenAccount);
+    }
+
+    #[tokio::test]
+    async fn test_collect_management_fee() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                management_fee_bps: 200,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let fee_recipient_token = create_token_account(&mut context, &mint_pda, &fee_recipient()).await;
+
+        // Half a year after initialization
+        let last_fee_collection = get_basket_state(&mut context, &basket_pda).await.last_fee_collection;
+        let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp = last_fee_collection + (basket_token::SECONDS_PER_YEAR / 2) as i64;
+        context.set_sysvar(&clock);
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::CollectManagementFee {}),
+            CollectManagementFee {
+                basket: basket_pda,
+                basket_mint: mint_pda,
+                fee_recipient_token,
+                token_program: token::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // 2% a year on 1 SOL of shares for half a year
+        assert_eq!(token_balance(&mut context, &fee_recipient_token).await, 10_000_000);
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_010_000_000);
+        assert_eq!(basket_state.last_fee_collection, clock.unix_timestamp);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_with_no_shares_outstanding() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        create_token_account(&mut context, &mint_pda, &payer.pubkey()).await;
+
+        let ix = redeem_ix(
+            &payer,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(1, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::NoSharesOutstanding);
+    }
+
+    #[tokio::test]
+    async fn test_timelocked_withdraw_authority_sol() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                timelock_seconds: 86_400,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        airdrop_sol(&mut context, &basket_pda, 1_000_000_000).await;
+
+        let accounts = WithdrawAuthoritySol {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueWithdrawAuthoritySol {
+                amount: 500_000_000,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteWithdrawAuthoritySol {}),
+            accounts.clone(),
+        );
+
+        // Nothing queued yet
+        let result = process_ix(&mut context, execute_ix(), &payer).await;
+        assert_basket_error(result, BasketError::NoPendingAction);
+
+        process_ix(&mut context, queue_ix, &payer).await.unwrap();
+        let result = process_ix(&mut context, execute_ix(), &payer).await;
+        assert_basket_error(result, BasketError::TimelockNotElapsed);
+
+        let pending = get_basket_state(&mut context, &basket_pda).await.pending_action.unwrap();
+        assert_eq!(pending.action, TimelockedAction::WithdrawAuthoritySol { amount: 500_000_000 });
+
+        let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp = pending.execute_after;
+        context.set_sysvar(&clock);
+
+        let balance_before = context.banks_client.get_balance(payer.pubkey()).await.unwrap();
+        process_ix(&mut context, execute_ix(), &payer).await.unwrap();
+        let balance_after = context.banks_client.get_balance(payer.pubkey()).await.unwrap();
+        assert_eq!(balance_after + 5_000, balance_before + 500_000_000);
+
+        // The action is consumed
+        assert!(get_basket_state(&mut context, &basket_pda).await.pending_action.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_timelocked_pause_before_delay() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                timelock_seconds: 3_600,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+
+        let accounts = SetPauseState {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueSetPauseState {
+                deposits: true,
+                redemptions: true,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteSetPauseState { reason: None }),
+            accounts,
+        );
+
+        process_ix(&mut context, queue_ix, &payer).await.unwrap();
+        let result = process_ix(&mut context, execute_ix, &payer).await;
+        assert_basket_error(result, BasketError::TimelockNotElapsed);
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(!basket_state.deposits_paused);
+        assert!(!basket_state.redemptions_paused);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_token_with_transfer_fee() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+
+        // Token-2022 input charging 1% per transfer
+        let fee_mint = create_transfer_fee_mint(&mut context, &payer, 100).await;
+        let user_input_token = create_token_account_with_program(
+            &mut context,
+            &fee_mint,
+            &payer.pubkey(),
+            &spl_token_2022::ID,
+        ).await;
+        let basket_input_token = create_token_account_with_program(
+            &mut context,
+            &fee_mint,
+            &basket_pda,
+            &spl_token_2022::ID,
+        ).await;
+        let mint_ix = spl_token_2022::instruction::mint_to(
+            &spl_token_2022::ID,
+            &fee_mint,
+            &user_input_token,
+            &payer.pubkey(),
+            &[],
+            1_000_000_000,
+        ).unwrap();
+        process_ix(&mut context, mint_ix, &payer).await.unwrap();
+        allow_input_mint(&mut context, &payer, &basket_pda, fee_mint).await;
+
+        let remaining_accounts = create_token_route_accounts(
+            &mut context,
+            &payer,
+            &basket_pda,
+            &basket_input_token,
+            &fee_mint,
+            &spl_token_2022::ID,
+            &mints,
+        ).await;
+        let ix = deposit_token_ix(
+            &payer,
+            &basket_pda,
+            &mint_pda,
+            &fee_mint,
+            &spl_token_2022::ID,
+            1_000_000_000,
+            remaining_accounts,
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // Only the 990_000_000 that arrived after the fee was swapped and credited
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[0])).await,
+            594_000_000
+        );
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[1])).await,
+            396_000_000
+        );
+        let user_basket_token = get_associated_token_address(&payer.pubkey(), &mint_pda);
+        assert_eq!(token_balance(&mut context, &user_basket_token).await, 990_000_000 - basket_token::MINIMUM_LIQUIDITY);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_dust_then_remove_token() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50]).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_234).await;
+
+        let sweep_ix = |route: Vec<AccountMeta>| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SweepDust {
+                token_mint: mints[0],
+                jupiter_quote: [0; 32],
+                slippage_bps: 100,
+            }),
+            [
+                SweepDust {
+                    basket: basket_pda,
+                    basket_token,
+                    swap_program: jupiter::JUPITER_V6_ID,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                route,
+            ].concat(),
+        );
+        let route = create_sol_route_accounts(&mut context, &basket_pda, &basket_token, &mints[0]).await;
+
+        // A token that still carries weight backs shares and isn't dust
+        let result = process_ix(&mut context, sweep_ix(route.clone()), &payer).await;
+        assert_basket_error(result, BasketError::CannotSweepWeightedToken);
+        let ix = update_token_weight_ix(&payer, &basket_pda, mints[0], 0);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // The route has to sell from the account being swept
+        let other = create_token_account(&mut context, &mints[0], &payer.pubkey()).await;
+        let mut other_route = route.clone();
+        other_route[jupiter::BALANCE_SLOT as usize] = AccountMeta::new(other, false);
+        let result = process_ix(&mut context, sweep_ix(other_route), &payer).await;
+        assert_basket_error(result, BasketError::InvalidTokenAccount);
+
+        let lamports_before = context.banks_client.get_balance(basket_pda).await.unwrap();
+        process_ix(&mut context, sweep_ix(route), &payer).await.unwrap();
+
+        assert_eq!(token_balance(&mut context, &basket_token).await, 0);
+        let lamports_after = context.banks_client.get_balance(basket_pda).await.unwrap();
+        assert_eq!(lamports_after, lamports_before + 1_234);
+
+        // With the dust gone the token can be removed
+        let ix = remove_token_ix(&payer, &basket_pda, mints[0], Some(basket_token));
+        process_ix(&mut context, ix, &payer).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_token_with_balance() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let funded = get_associated_token_address(&basket_pda, &mints[0]);
+        let empty = get_associated_token_address(&basket_pda, &mints[1]);
+        mint_tokens(&mut context, &mints[0], &payer, &funded, 1_000).await;
+
+        let ix = remove_token_ix(&payer, &basket_pda, mints[0], Some(funded));
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::TokenBalanceNonZero);
+
+        // An initialized holding can't be skipped by omitting its account
+        let ix = remove_token_ix(&payer, &basket_pda, mints[0], None);
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::InvalidTokenAccount);
+
+        let ix = remove_token_ix(&payer, &basket_pda, mints[1], Some(empty));
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.tokens.len(), 1);
+        assert_eq!(basket_state.tokens[0].mint, mints[0]);
+    }
+
+    #[tokio::test]
+    async fn test_get_price_per_share() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+
+        let price_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetPricePerShare {}),
+            [
+                GetPricePerShare { basket: basket_pda }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+                ],
+            ].concat(),
+        );
+
+        // An empty basket quotes shares at par
+        let price: PricePerShare = simulate_return_data(&mut context, price_ix(), &payer).await;
+        assert_eq!(price.price_per_share, basket_token::MAGNIFIER);
+        assert_eq!(price.total_supply, 0);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // Token holdings worth another 1 SOL double the share price
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        let price: PricePerShare = simulate_return_data(&mut context, price_ix(), &payer).await;
+        assert_eq!(price.nav, 2_000_000_000);
+        assert_eq!(price.total_supply, 1_000_000_000);
+        assert_eq!(price.token_balances, vec![1_000_000_000]);
+        assert_eq!(price.price_per_share, 2 * basket_token::MAGNIFIER);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_with_six_decimals() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                decimals: 6,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+
+        let mint_account = context.banks_client.get_account(mint_pda).await.unwrap().unwrap();
+        let mint = Mint::try_deserialize(&mut &mint_account.data[..]).unwrap();
+        assert_eq!(mint.decimals, 6);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_rejects_too_many_decimals() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        let result = initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                decimals: 10,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_token_respects_allowlist() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let allowlist_authority = Keypair::new();
+        airdrop_sol(&mut context, &allowlist_authority.pubkey(), 1_000_000_000).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::InitializeAllowlist {
+                allowlist_authority: allowlist_authority.pubkey(),
+            }),
+            InitializeAllowlist {
+                basket: basket_pda,
+                allowlist: allowlist_pda(&basket_pda),
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let approved = Pubkey::new_unique();
+        let unapproved = Pubkey::new_unique();
+        let add_allowed_ix = |mint: Pubkey, signer: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::AddAllowedMint { mint }),
+            UpdateAllowlist {
+                allowlist: allowlist_pda(&basket_pda),
+                allowlist_authority: signer,
+            }.to_account_metas(None),
+        );
+
+        // Only the allowlist authority can approve mints
+        let result = process_ix(&mut context, add_allowed_ix(approved, payer.pubkey()), &payer).await;
+        assert!(result.is_err());
+        let ix = add_allowed_ix(approved, allowlist_authority.pubkey());
+        process_ix(&mut context, ix, &allowlist_authority).await.unwrap();
+
+        let result = add_token(&mut context, &payer, &basket_pda, unapproved, 50).await;
+        assert_basket_error(result, BasketError::MintNotAllowed);
+
+        add_token(&mut context, &payer, &basket_pda, approved, 50).await.unwrap();
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.tokens.len(), 1);
+        assert_eq!(basket_state.tokens[0].mint, approved);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_credits_basket_exact_lamports() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let before = context.banks_client.get_balance(basket_pda).await.unwrap();
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let after = context.banks_client.get_balance(basket_pda).await.unwrap();
+        assert_eq!(after - before, 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_position() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+
+        let user = Keypair::new();
+        let position_ix = |user: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetUserPosition { user }),
+            [
+                GetUserPosition {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    user_basket_token: get_associated_token_address(&user, &mint_pda),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+                ],
+            ].concat(),
+        );
+
+        // No ATA yet: an empty position
+        let position: UserPosition =
+            simulate_return_data(&mut context, position_ix(user.pubkey()), &payer).await;
+        assert_eq!(position.shares, 0);
+        assert_eq!(position.token_amounts, vec![0]);
+        assert_eq!(position.value_lamports, 0);
+
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        // Only holder besides the locked minimum liquidity
+        let position: UserPosition =
+            simulate_return_data(&mut context, position_ix(user.pubkey()), &payer).await;
+        assert_eq!(position.user, user.pubkey());
+        assert_eq!(position.shares, 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(position.total_supply, 1_000_000_000);
+        assert_eq!(position.token_amounts, vec![999_999_000]);
+        assert_eq!(position.value_lamports, 1_999_998_000);
+
+        // A split redemption of half the supply burns its shares and reserves
+        // half the holding, which no longer counts toward the position
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::BeginRedeem {
+                shares: 500_000_000,
+            }),
+            [
+                BeginRedeem {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    user_basket_token: get_associated_token_address(&user.pubkey(), &mint_pda),
+                    user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+                    pending_redeem: Pubkey::find_program_address(
+                        &[b"pending_redeem", basket_pda.as_ref(), user.pubkey().as_ref()],
+                        &id(),
+                    ).0,
+                    user: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                vec![AccountMeta::new_readonly(basket_token, false)],
+            ].concat(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let position: UserPosition =
+            simulate_return_data(&mut context, position_ix(user.pubkey()), &payer).await;
+        assert_eq!(position.shares, 500_000_000 - basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(position.total_supply, 500_000_000);
+        assert_eq!(position.token_amounts, vec![499_999_000]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_performance_fee_above_high_water_mark() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                performance_fee_bps: 2_000,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let fee_recipient_token = create_token_account(&mut context, &mint_pda, &fee_recipient()).await;
+        let fee_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::CollectPerformanceFee {}),
+            [
+                CollectManagementFee {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    fee_recipient_token,
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                vec![
+                    AccountMeta::new(basket_token, false),
+                    AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+                ],
+            ].concat(),
+        );
+
+        // Token holdings worth another 1 SOL double the share price
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+        process_ix(&mut context, fee_ix(), &payer).await.unwrap();
+
+        // 20% of the 1 SOL gain, paid in shares at the 1.8 SOL post-fee NAV
+        assert_eq!(token_balance(&mut context, &fee_recipient_token).await, 111_111_111);
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_111_111_111);
+        assert_eq!(basket_state.high_water_mark, 1_800_000_000_180_000_000);
+
+        // Tokens drop to a tenth of their value: below the mark, nothing accrues
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 1, -1).await;
+        process_ix(&mut context, fee_ix(), &payer).await.unwrap();
+
+        assert_eq!(token_balance(&mut context, &fee_recipient_token).await, 111_111_111);
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_111_111_111);
+        assert_eq!(basket_state.high_water_mark, 1_800_000_000_180_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_reverts_below_minimum_shares_out() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let token_a = get_associated_token_address(&basket_pda, &mints[0]);
+        let token_b = get_associated_token_address(&basket_pda, &mints[1]);
+        mint_tokens(&mut context, &mints[0], &payer, &token_a, 1_000_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &token_b, 1_000_000_000).await;
+        give_mint_to_jupiter(&mut context, &payer, &mints[1]).await;
+        create_token_account(&mut context, &mints[0], &jupiter_mint_authority()).await;
+
+        // The second leg sells token A for the same count of token B, which is
+        // worth a tenth as much: the basket loses value on the fill
+        set_pyth_price(&mut context, &price_feed_for(&mints[1]), 1, -1).await;
+        let leg_a = create_basket_swap_accounts(&mut context, &basket_pda, &mints[..1]).await;
+        let mut swap_accounts = leg_a[..12].to_vec();
+        swap_accounts.extend(token_route(
+            &basket_pda,
+            &token_a,
+            &mints[0],
+            &token::ID,
+            &token_b,
+            &mints[1],
+        ));
+        swap_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+        swap_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[1]), false));
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let deposit_with_floor = |minimum_shares_out: u64| deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                minimum_shares_out,
+                accounts_per_swap: vec![12, 10],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts.clone(),
+        );
+
+        // 1 SOL in, but selling 0.5 SOL of token A for 0.05 SOL of token B
+        // loses 0.45 SOL: only 0.55 SOL of shares, less the minimum liquidity
+        // the first deposit locks
+        let result = process_ix(&mut context, deposit_with_floor(550_000_000), &user).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        process_ix(&mut context, deposit_with_floor(550_000_000 - basket_token::MINIMUM_LIQUIDITY), &user).await.unwrap();
+        let shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(shares, 550_000_000 - basket_token::MINIMUM_LIQUIDITY);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_partial_sells_one_leg() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let token_a = get_associated_token_address(&basket_pda, &mints[0]);
+        let token_b = get_associated_token_address(&basket_pda, &mints[1]);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // NAV: 1 SOL idle + 1 SOL of token A + 1 SOL of token B
+        mint_tokens(&mut context, &mints[0], &payer, &token_a, 1_000_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &token_b, 1_000_000_000).await;
+
+        let mut remaining = create_sol_route_accounts(&mut context, &basket_pda, &token_a, &mints[0]).await;
+        remaining.extend([
+            AccountMeta::new(token_a, false),
+            AccountMeta::new(token_b, false),
+            AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+            AccountMeta::new_readonly(price_feed_for(&mints[1]), false),
+        ]);
+
+        // Half the user's shares, but only their slice of token A
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::RedeemPartial {
+                shares: 500_000_000,
+                token_mints: vec![mints[0]],
+                jupiter_quotes: vec![[0u8; 32]],
+                slippage_bps: 100,
+                minimum_sol_amount: 500_000_000,
+                accounts_per_swap: vec![8],
+            },
+            remaining,
+        );
+
+        // At twice the price the route's 0.5 SOL falls short of the leg's
+        // oracle value, even though it clears minimum_sol_amount
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 2, 0).await;
+        let result = process_ix(&mut context, ix.clone(), &user).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 1, 0).await;
+        let user_lamports = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // 0.5 SOL of a 3 SOL NAV backs a sixth of the supply
+        let shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&user.pubkey(), &mint_pda),
+        ).await;
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(shares, 833_333_334 - basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(basket_state.total_supply, shares + basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(token_balance(&mut context, &token_a).await, 500_000_000);
+        assert_eq!(token_balance(&mut context, &token_b).await, 1_000_000_000);
+
+        let received = context.banks_client.get_balance(user.pubkey()).await.unwrap() - user_lamports;
+        assert_eq!(received, 500_000_000 - 5_000);
+    }
+
+    #[tokio::test]
+    async fn test_resize_basket_beyond_initial_max_tokens() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                max_tokens: 5,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        for _ in 0..5 {
+            add_token(&mut context, &payer, &basket_pda, Pubkey::new_unique(), 5).await.unwrap();
+        }
+        let result = add_token(&mut context, &payer, &basket_pda, Pubkey::new_unique(), 5).await;
+        assert_basket_error(result, BasketError::TooManyTokens);
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ResizeBasket {
+                new_max_tokens: 15,
+            }),
+            ResizeBasket {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let account = context.banks_client.get_account(basket_pda).await.unwrap().unwrap();
+        assert_eq!(account.data.len(), BasketState::required_space(15));
+
+        for _ in 0..6 {
+            add_token(&mut context, &payer, &basket_pda, Pubkey::new_unique(), 5).await.unwrap();
+        }
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.max_tokens, 15);
+        assert_eq!(basket_state.tokens.len(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_basket_space_follows_max_tokens() {
+        let (mut context, payer, small_basket, small_mint) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                max_tokens: 2,
+                ..initialize_args(0)
+            },
+            &small_basket,
+            &small_mint,
+        )
+        .await
+        .unwrap();
+        let (large_basket, large_mint) = basket_pdas(1);
+        initialize_basket_with_id(&mut context, &payer, 1, &large_basket, &large_mint)
+            .await
+            .unwrap();
+
+        let small = context.banks_client.get_account(small_basket).await.unwrap().unwrap();
+        let large = context.banks_client.get_account(large_basket).await.unwrap().unwrap();
+        assert_eq!(small.data.len(), BasketState::required_space(2));
+        assert_eq!(large.data.len(), BasketState::required_space(10));
+        assert!(small.lamports < large.lamports);
+
+        // Capacity ends where the allocation does
+        add_token(&mut context, &payer, &small_basket, Pubkey::new_unique(), 50).await.unwrap();
+        add_token(&mut context, &payer, &small_basket, Pubkey::new_unique(), 50).await.unwrap();
+        let result = add_token(&mut context, &payer, &small_basket, Pubkey::new_unique(), 0).await;
+        assert_basket_error(result, BasketError::TooManyTokens);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_reused_nonce() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 5_000_000_000).await;
+        let deposit_with_nonce = |amount: u64, nonce: u64| deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                nonce: Some(nonce),
+                ..deposit_args(amount, 1)
+            },
+            swap_accounts.clone(),
+        );
+
+        process_ix(&mut context, deposit_with_nonce(1_000_000_000, 1), &user).await.unwrap();
+
+        // Same nonce again (a different amount keeps the transaction distinct)
+        let result = process_ix(&mut context, deposit_with_nonce(1_000_000_001, 1), &user).await;
+        assert_basket_error(result, BasketError::StaleNonce);
+
+        process_ix(&mut context, deposit_with_nonce(1_000_000_000, 2), &user).await.unwrap();
+        let record = context.banks_client
+            .get_account(user_deposit_pda(&basket_pda, &user.pubkey()))
+            .await
+            .unwrap()
+            .unwrap();
+        let record = UserDeposit::try_deserialize(&mut &record.data[..]).unwrap();
+        assert_eq!(record.last_nonce, 2);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_circuit_breaker() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let token_a = get_associated_token_address(&basket_pda, &mints[0]);
+        let token_b = get_associated_token_address(&basket_pda, &mints[1]);
+        mint_tokens(&mut context, &mints[0], &payer, &token_a, 1_000_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &token_b, 1_000_000_000).await;
+        give_mint_to_jupiter(&mut context, &payer, &mints[1]).await;
+        create_token_account(&mut context, &mints[0], &jupiter_mint_authority()).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetAutoPauseThreshold {
+                auto_pause_threshold_bps: 4_000,
+            }),
+            SetAutoPauseThreshold {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // The second leg sells token A for token B at a tenth of its value
+        set_pyth_price(&mut context, &price_feed_for(&mints[1]), 1, -1).await;
+        let leg_a = create_basket_swap_accounts(&mut context, &basket_pda, &mints[..1]).await;
+        let mut swap_accounts = leg_a[..12].to_vec();
+        swap_accounts.extend(token_route(
+            &basket_pda,
+            &token_a,
+            &mints[0],
+            &token::ID,
+            &token_b,
+            &mints[1],
+        ));
+        swap_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+        swap_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[1]), false));
+
+        // Losing 0.45 of the 1 SOL deposited is 45% realized slippage against a
+        // 40% breaker, even with no share floor
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                accounts_per_swap: vec![12, 10],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::CircuitBreakerTripped);
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 0);
+        assert_eq!(token_balance(&mut context, &token_a).await, 1_000_000_000);
+    }
+
+    fn create_metadata_ix(
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        mint_pda: &Pubkey,
+        name: &str,
+        symbol: &str,
+    ) -> Instruction {
+        let (metadata, _) = Pubkey::find_program_address(
+            &[b"metadata", metaplex::TOKEN_METADATA_ID.as_ref(), mint_pda.as_ref()],
+            &metaplex::TOKEN_METADATA_ID,
+        );
+        Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::CreateMetadata {
+                name: name.to_string(),
+                symbol: symbol.to_string(),
+                uri: "https://example.com/basket.json".to_string(),
+            }),
+            CreateMetadata {
+                basket: *basket_pda,
+                basket_mint: *mint_pda,
+                metadata,
+                authority: payer.pubkey(),
+                token_metadata_program: metaplex::TOKEN_METADATA_ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }.to_account_metas(None),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_create_metadata() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let ix = create_metadata_ix(&payer, &basket_pda, &mint_pda, "x".repeat(33).as_str(), "BSKT");
+        let result = process_ix(&mut context, ix, &payer).await;
+        assert_basket_error(result, BasketError::MetadataTooLong);
+
+        let ix = create_metadata_ix(&payer, &basket_pda, &mint_pda, "Blue Chip Basket", "BSKT");
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let (metadata, _) = Pubkey::find_program_address(
+            &[b"metadata", metaplex::TOKEN_METADATA_ID.as_ref(), mint_pda.as_ref()],
+            &metaplex::TOKEN_METADATA_ID,
+        );
+        let account = context.banks_client.get_account(metadata).await.unwrap().unwrap();
+        let (name, symbol) = <(String, String)>::deserialize(&mut &account.data[..]).unwrap();
+        assert_eq!(name, "Blue Chip Basket");
+        assert_eq!(symbol, "BSKT");
+    }
+
+    #[tokio::test]
+    async fn test_describe_swap_accounts() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::DescribeSwapAccounts {}),
+            DescribeSwapAccounts { basket: basket_pda }.to_account_metas(None),
+        );
+        let layout: SwapAccountLayout = simulate_return_data(&mut context, ix, &payer).await;
+
+        assert_eq!(layout.balance_slot, 1);
+        let expected: Vec<SwapLeg> = mints
+            .iter()
+            .map(|mint| SwapLeg {
+                mint: *mint,
+                token_account: get_associated_token_address(&basket_pda, mint),
+                price_feed: price_feed_for(mint),
+                stride: 12,
+            })
+            .collect();
+        assert_eq!(layout.legs, expected);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_non_token_balance_account() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // The registered token account was closed, so the balance slot holds
+        // no token data
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        context.set_account(
+            &swap_accounts[1].pubkey,
+            &AccountSharedData::new(1_000_000, 0, &system_program::ID),
+        );
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::InvalidTokenAccountData);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_proceeds_track_nav() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let basket_token = swap_accounts[1].pubkey;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        // Lamports pushed straight into the PDA are NAV shared by every holder
+        airdrop_sol(&mut context, &basket_pda, 1_000_000_000).await;
+
+        let mut sell_accounts = create_sol_route_accounts(&mut context, &basket_pda, &basket_token, &mints[0]).await;
+        sell_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+
+        // The token is quoted at 10 lamports but the route pays 1: the fill
+        // misses the NAV-implied proceeds and reverts
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 10, 0).await;
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_002, 1)
+            },
+            sell_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        // The token is quoted at a tenth of a lamport but the route pays 1:
+        // overpayment reverts too
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 1, -1).await;
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_001, 1)
+            },
+            sell_accounts.clone(),
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::SlippageExceeded);
+
+        // At par, half the shares draw half the 3 SOL NAV, injection included pro rata
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 1, 0).await;
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_000, 1)
+            },
+            sell_accounts,
+        );
+        // The payer covers the fee so the user's balance moves by the proceeds alone
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        assert_eq!(user_after - user_before, 1_500_000_000);
+    }
+
+    // Mock route buying `destination_mint` with SOL from a Jupiter-owned input
+    // account, which callers fund with the lamports the route should spend
+    async fn create_buy_route_accounts(
+        context: &mut ProgramTestContext,
+        payer: &Keypair,
+        basket_pda: &Pubkey,
+        destination_mint: &Pubkey,
+    ) -> Vec<AccountMeta> {
+        give_mint_to_jupiter(context, payer, destination_mint).await;
+        let input = Pubkey::new_unique();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        context.set_account(
+            &input,
+            &AccountSharedData::new(rent.minimum_balance(0), 0, &jupiter::JUPITER_V6_ID),
+        );
+
+        vec![
+            AccountMeta::new(input, false),
+            AccountMeta::new(get_associated_token_address(basket_pda, destination_mint), false),
+            AccountMeta::new_readonly(*basket_pda, false),
+            AccountMeta::new(*destination_mint, false),
+            AccountMeta::new(jupiter_mint_authority(), false),
+            AccountMeta::new_readonly(token::ID, false),
+            // The swap CPI needs the router's program account in the transaction
+            AccountMeta::new_readonly(jupiter::JUPITER_V6_ID, false),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_to_weights() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let token_a = get_associated_token_address(&basket_pda, &mints[0]);
+        let token_b = get_associated_token_address(&basket_pda, &mints[1]);
+
+        // 75/25 against a 50/50 target
+        mint_tokens(&mut context, &mints[0], &payer, &token_a, 3_000_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &token_b, 1_000_000_000).await;
+
+        let mut remaining = vec![
+            AccountMeta::new(token_a, false),
+            AccountMeta::new(token_b, false),
+            AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+            AccountMeta::new_readonly(price_feed_for(&mints[1]), false),
+        ];
+        remaining.extend(create_sol_route_accounts(&mut context, &basket_pda, &token_a, &mints[0]).await);
+
+        // The buy spends the basket's wSOL, wrapped from the sale's proceeds
+        let native_mint = token::spl_token::native_mint::ID;
+        let basket_wsol = get_associated_token_address(&basket_pda, &native_mint);
+        create_token_account(&mut context, &native_mint, &jupiter_mint_authority()).await;
+        give_mint_to_jupiter(&mut context, &payer, &mints[1]).await;
+        remaining.extend(token_route(&basket_pda, &basket_wsol, &native_mint, &token::ID, &token_b, &mints[1]));
+
+        let rebalance_ix = |authority: &Keypair| {
+            let mut accounts = RebalanceToWeights {
+                basket: basket_pda,
+                native_mint,
+                basket_wsol,
+                swap_program: jupiter::JUPITER_V6_ID,
+                authority: authority.pubkey(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+                associated_token_program: associated_token::ID,
+            }.to_account_metas(None);
+            accounts.extend(remaining.clone());
+            Instruction::new_with_bytes(
+                id(),
+                &anchor_lang::InstructionData::data(&crate::instruction::RebalanceToWeights {
+                    jupiter_quotes: vec![[0u8; 32]; 2],
+                    slippage_bps: 100,
+                    accounts_per_swap: vec![8, 10],
+                }),
+                accounts,
+            )
+        };
+
+        let stranger = Keypair::new();
+        airdrop_sol(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+        let result = process_ix(&mut context, rebalance_ix(&stranger), &stranger).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+
+        let basket_lamports = context.banks_client.get_balance(basket_pda).await.unwrap();
+        process_ix(&mut context, rebalance_ix(&payer), &payer).await.unwrap();
+
+        // 1 SOL of A sold and spent on B, with the wSOL account closed again
+        assert_eq!(token_balance(&mut context, &token_a).await, 2_000_000_000);
+        assert_eq!(token_balance(&mut context, &token_b).await, 2_000_000_000);
+        assert_eq!(context.banks_client.get_balance(basket_pda).await.unwrap(), basket_lamports);
+        assert!(context.banks_client.get_account(basket_wsol).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deposit_token_rejects_floor_below_quote() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+
+        let usdc = create_mint(&mut context, &payer).await;
+        let user_input_token = create_token_account(&mut context, &usdc, &payer.pubkey()).await;
+        let basket_input_token = create_token_account(&mut context, &usdc, &basket_pda).await;
+        mint_tokens(&mut context, &usdc, &payer, &user_input_token, 1_000_000_000).await;
+        allow_input_mint(&mut context, &payer, &basket_pda, usdc).await;
+
+        let remaining_accounts = create_token_route_accounts(
+            &mut context,
+            &payer,
+            &basket_pda,
+            &basket_input_token,
+            &usdc,
+            &token::ID,
+            &mints,
+        ).await;
+        let deposit_with_floors = |minimum_token_amounts: Vec<u64>| deposit_token_ix_with_args(
+            &payer,
+            &basket_pda,
+            &mint_pda,
+            &token::ID,
+            crate::instruction::DepositToken {
+                minimum_token_amounts,
+                expected_token_amounts: vec![300_000_000, 200_000_000],
+                ..deposit_token_args(&usdc, 500_000_000, 2)
+            },
+            remaining_accounts.clone(),
+        );
+
+        // A zero floor against a quoted 200M output
+        let result = process_ix(&mut context, deposit_with_floors(vec![297_000_000, 0]), &payer).await;
+        assert_basket_error(result, BasketError::SlippageFloorTooLow);
+
+        // Floors at exactly 1% under the quotes are accepted
+        process_ix(&mut context, deposit_with_floors(vec![297_000_000, 198_000_000]), &payer)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_supply_matches_mint() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Desync the tracked supply from the (empty) mint
+        let mut basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut basket = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        basket.total_supply = 1_000_000;
+        let mut data = Vec::new();
+        basket.try_serialize(&mut data).unwrap();
+        basket_account.data[..data.len()].copy_from_slice(&data);
+        context.set_account(&basket_pda, &AccountSharedData::from(basket_account));
+
+        let reconcile_ix = |authority: &Keypair| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ReconcileSupply {}),
+            vec![
+                AccountMeta::new(basket_pda, false),
+                AccountMeta::new_readonly(mint_pda, false),
+                AccountMeta::new_readonly(authority.pubkey(), true),
+            ],
+        );
+
+        let stranger = Keypair::new();
+        airdrop_sol(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+        let result = process_ix(&mut context, reconcile_ix(&stranger), &stranger).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+
+        process_ix(&mut context, reconcile_ix(&payer), &payer).await.unwrap();
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.total_supply, 0);
+    }
+
+    #[tokio::test]
+    async fn test_switchboard_nav_matches_pyth() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        let nav_ix = |price_feed: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetPricePerShare {}),
+            [
+                GetPricePerShare { basket: basket_pda }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(price_feed, false),
+                ],
+            ].concat(),
+        );
+
+        // Pyth quotes 2.5 lamports per base unit
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 25, -1).await;
+        let pyth: PricePerShare =
+            simulate_return_data(&mut context, nav_ix(price_feed_for(&mints[0])), &payer).await;
+        assert_eq!(pyth.nav, 2_500_000_000);
+
+        // Switch the leg to a Switchboard aggregator quoting the same price
+        let aggregator = Pubkey::new_unique();
+        set_switchboard_price(&mut context, &aggregator, 2_500, 3).await;
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetPriceFeed {
+                token_mint: mints[0],
+                price_feed: aggregator,
+                price_source: PriceSource::Switchboard,
+            }),
+            SetPriceFeed {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let switchboard: PricePerShare =
+            simulate_return_data(&mut context, nav_ix(aggregator), &payer).await;
+        assert_eq!(switchboard.nav, pyth.nav);
+
+        // A Pyth account isn't a valid Switchboard aggregator
+        set_pyth_price(&mut context, &aggregator, 25, -1).await;
+        let result = process_ix(&mut context, nav_ix(aggregator), &payer).await;
+        assert_basket_error(result, BasketError::InvalidPriceAccount);
+    }
+
+    #[tokio::test]
+    async fn test_nav_rejects_stale_price() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        let price_feed = price_feed_for(&mints[0]);
+
+        let price_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetPricePerShare {}),
+            [
+                GetPricePerShare { basket: basket_pda }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(price_feed, false),
+                ],
+            ].concat(),
+        );
+
+        // A price published just now is accepted
+        let price: PricePerShare = simulate_return_data(&mut context, price_ix(), &payer).await;
+        assert_eq!(price.price_per_share, basket_token::MAGNIFIER);
+
+        // Age the price past the default window
+        let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        let mut account = context.banks_client.get_account(price_feed).await.unwrap().unwrap();
+        account.data = pyth_price_data(
+            1,
+            0,
+            clock.unix_timestamp - basket_token::DEFAULT_MAX_PRICE_AGE_SECONDS as i64 - 1,
+        );
+        context.set_account(&price_feed, &AccountSharedData::from(account));
+
+        let result = process_ix(&mut context, price_ix(), &payer).await;
+        assert_basket_error(result, BasketError::StalePrice);
+    }
+
+    #[tokio::test]
+    async fn test_get_basket_value_breakdown() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+
+        // Only the first leg holds anything, priced at 3 lamports per unit
+        mint_tokens(&mut context, &mints[0], &payer, &basket_tokens[0], 1_000).await;
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 3, 0).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetBasketValue {}),
+            [
+                GetBasketValue { basket: basket_pda }.to_account_metas(None),
+                basket_tokens
+                    .iter()
+                    .map(|account| AccountMeta::new_readonly(*account, false))
+                    .collect(),
+                mints
+                    .iter()
+                    .map(|mint| AccountMeta::new_readonly(price_feed_for(mint), false))
+                    .collect(),
+            ].concat(),
+        );
+        let value: BasketValue = simulate_return_data(&mut context, ix, &payer).await;
+
+        assert_eq!(value.idle_lamports, 0);
+        assert_eq!(value.nav, 3_000);
+        assert_eq!(value.token_values, vec![(mints[0], 3_000), (mints[1], 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_guardian_can_pause_but_not_withdraw() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        airdrop_sol(&mut context, &basket_pda, 1_000_000_000).await;
+
+        let guardian = Keypair::new();
+        airdrop_sol(&mut context, &guardian.pubkey(), 1_000_000_000).await;
+
+        let pause_ix = |signer: &Keypair| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GuardianPause {
+                deposits: true,
+                redemptions: false,
+                reason: None,
+            }),
+            GuardianPause {
+                basket: basket_pda,
+                guardian: signer.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        // Not yet appointed
+        let result = process_ix(&mut context, pause_ix(&guardian), &guardian).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetGuardian {
+                guardian: guardian.pubkey(),
+            }),
+            SetGuardian {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        process_ix(&mut context, pause_ix(&guardian), &guardian).await.unwrap();
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(basket_state.deposits_paused);
+        assert!(!basket_state.redemptions_paused);
+
+        // The guardian holds no authority powers
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueWithdrawAuthoritySol {
+                amount: 500_000_000,
+            }),
+            WithdrawAuthoritySol {
+                basket: basket_pda,
+                authority: guardian.pubkey(),
+            }.to_account_metas(None),
+        );
+        let result = process_ix(&mut context, ix, &guardian).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_skips_failed_leg_when_permissive() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+
+        // Leg 0 routes through a mock that delivers tokens; leg 1's route
+        // returns without delivering anything
+        mint_tokens(&mut context, &mints[0], &payer, &basket_tokens[0], 1_000).await;
+        let buy_route = create_buy_route_accounts(&mut context, &payer, &basket_pda, &mints[0]).await;
+        let rent = context.banks_client.get_rent().await.unwrap();
+        context.set_account(
+            &buy_route[0].pubkey,
+            &AccountSharedData::new(rent.minimum_balance(0) + 500_000_000, 0, &jupiter::JUPITER_V6_ID),
+        );
+        let empty_route = create_basket_swap_accounts(&mut context, &basket_pda, &mints[1..]).await;
+        let remaining_accounts = [
+            buy_route,
+            empty_route[..12].to_vec(),
+            vec![
+                AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+                AccountMeta::new_readonly(price_feed_for(&mints[1]), false),
+            ],
+        ].concat();
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let basket_lamports_before = context.banks_client.get_balance(basket_pda).await.unwrap();
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                accounts_per_swap: vec![7, 12],
+                continue_on_leg_failure: true,
+                ..deposit_args(1_000_000_000, 2)
+            },
+            remaining_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // Leg 1's half of the deposit went back to the user
+        let basket_lamports_after = context.banks_client.get_balance(basket_pda).await.unwrap();
+        assert_eq!(basket_lamports_after - basket_lamports_before, 500_000_000);
+        assert_eq!(token_balance(&mut context, &basket_tokens[0]).await, 500_001_000);
+
+        // Shares cover only the allocated SOL plus what leg 0 delivered
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_split_deposit_legs_and_finalize() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        // The zero-weight third token has no leg to run
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50, 0]).await;
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+
+        // Each leg's route sells the basket's wSOL for its token
+        let native_mint = token::spl_token::native_mint::ID;
+        let basket_wsol = get_associated_token_address(&basket_pda, &native_mint);
+        let vault = create_token_account(&mut context, &native_mint, &jupiter_mint_authority()).await;
+        let mut routes = Vec::new();
+        for (mint, basket_token) in mints.iter().zip(&basket_tokens) {
+            give_mint_to_jupiter(&mut context, &payer, mint).await;
+            routes.push(token_route(&basket_pda, &basket_wsol, &native_mint, &token::ID, basket_token, mint));
+        }
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let pending_pda = Pubkey::find_program_address(
+            &[b"pending_deposit", basket_pda.as_ref(), user.pubkey().as_ref()],
+            &id(),
+        ).0;
+        let unreserved_before = unreserved_lamports(&mut context, &basket_pda).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::BeginDeposit {
+                amount: 1_000_000_000,
+            }),
+            BeginDeposit {
+                basket: basket_pda,
+                fee_recipient: fee_recipient(),
+                user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+                pending_deposit: pending_pda,
+                user: user.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // The SOL is parked outside NAV until the deposit is finalized
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.pending_deposit_lamports, 1_000_000_000);
+        assert_eq!(unreserved_lamports(&mut context, &basket_pda).await, unreserved_before);
+
+        let leg_ix = |leg_index: u8| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::DepositLeg {
+                leg_index,
+                jupiter_quote: [leg_index; 32],
+                slippage_bps: 100,
+            }),
+            [
+                DepositLeg {
+                    basket: basket_pda,
+                    pending_deposit: pending_pda,
+                    native_mint,
+                    basket_wsol,
+                    swap_program: jupiter::JUPITER_V6_ID,
+                    user: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                    associated_token_program: associated_token::ID,
+                }.to_account_metas(None),
+                routes[leg_index as usize].clone(),
+                vec![AccountMeta::new_readonly(price_feed_for(&mints[leg_index as usize]), false)],
+            ].concat(),
+        );
+        let finalize_ix = |minimum_shares_out: u64| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::FinalizeDeposit {
+                minimum_shares_out,
+            }),
+            [
+                FinalizeDeposit {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    user_basket_token: get_associated_token_address(&user.pubkey(), &mint_pda),
+                    basket_share_token: get_associated_token_address(&basket_pda, &mint_pda),
+                    pending_deposit: pending_pda,
+                    user: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                    associated_token_program: associated_token::ID,
+                }.to_account_metas(None),
+                basket_tokens
+                    .iter()
+                    .map(|account| AccountMeta::new_readonly(*account, false))
+                    .collect(),
+                mints
+                    .iter()
+                    .map(|mint| AccountMeta::new_readonly(price_feed_for(mint), false))
+                    .collect(),
+            ].concat(),
+        );
+
+        process_ix(&mut context, leg_ix(0), &user).await.unwrap();
+        assert_eq!(token_balance(&mut context, &basket_tokens[0]).await, 500_000_000);
+        assert_eq!(token_balance(&mut context, &vault).await, 500_000_000);
+
+        // The leg's wSOL account is closed again once the route has spent it
+        assert!(context.banks_client.get_account(basket_wsol).await.unwrap().is_none());
+
+        // The leg spends only its reserved SOL, and the user gets the wSOL rent back
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.pending_deposit_lamports, 500_000_000);
+        assert_eq!(unreserved_lamports(&mut context, &basket_pda).await, unreserved_before);
+
+        // Leg 0 can't run twice and leg 1 is still outstanding
+        let result = process_ix(&mut context, leg_ix(0), &user).await;
+        assert_basket_error(result, BasketError::LegAlreadyCompleted);
+        let result = process_ix(&mut context, finalize_ix(0), &user).await;
+        assert_basket_error(result, BasketError::LegsIncomplete);
+
+        process_ix(&mut context, leg_ix(1), &user).await.unwrap();
+        assert_eq!(token_balance(&mut context, &basket_tokens[1]).await, 500_000_000);
+
+        let result = process_ix(&mut context, leg_ix(2), &user).await;
+        assert_basket_error(result, BasketError::ZeroWeightLeg);
+
+        process_ix(&mut context, finalize_ix(1_000_000_000 - basket_token::MINIMUM_LIQUIDITY), &user).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_000_000_000);
+        assert_eq!(basket_state.pending_deposit_lamports, 0);
+        assert_eq!(unreserved_lamports(&mut context, &basket_pda).await, unreserved_before);
+        let user_basket_token = get_associated_token_address(&user.pubkey(), &mint_pda);
+        assert_eq!(token_balance(&mut context, &user_basket_token).await, 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+
+        // The pending record is closed back to the user
+        assert!(context.banks_client.get_account(pending_pda).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_holdings_flags_orphans() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+
+        // A basket-owned account for a mint the basket doesn't track
+        let stray_mint = create_mint(&mut context, &payer).await;
+        let orphan = create_token_account(&mut context, &stray_mint, &basket_pda).await;
+
+        let verify_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::VerifyHoldings {}),
+            [
+                VerifyHoldings { basket: basket_pda }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(orphan, false),
+                ],
+            ].concat(),
+        );
+
+        // An empty orphan is tolerated
+        process_ix(&mut context, verify_ix(), &payer).await.unwrap();
+
+        mint_tokens(&mut context, &stray_mint, &payer, &orphan, 1_000).await;
+        let result = process_ix(&mut context, verify_ix(), &payer).await;
+        assert_basket_error(result, BasketError::UnexpectedHolding);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_skips_leg_below_min_swap() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[98, 2]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetMinSwapLamports {
+                min_swap_lamports: 50_000_000,
+                roll_small_legs: false,
+            }),
+            SetMinSwapLamports {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // Leg 1 gets 20M lamports of a 1 SOL deposit; its route would fail if invoked
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                jupiter_quotes: vec![[0u8; 32], [u8::MAX; 32]],
+                ..deposit_args(1_000_000_000, 2)
+            },
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // The skipped leg's SOL stays in the basket and backs the shares
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_add_token_emits_event() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let token_mint = create_mint(&mut context, &payer).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::AddToken {
+                token_mint,
+                weight: 40,
+            }),
+            AddToken {
+                basket: basket_pda,
+                allowlist: allowlist_pda(&basket_pda),
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        let logs = result.metadata.unwrap().log_messages;
+        let events: Vec<TokenAddedEvent> = decode_events(&logs);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].authority, payer.pubkey());
+        assert_eq!(events[0].mint, token_mint);
+        assert_eq!(events[0].weight, 40);
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_authority_sol_keeps_user_backing() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let accounts = WithdrawAuthoritySol {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = |amount: u64| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueWithdrawAuthoritySol {
+                amount,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteWithdrawAuthoritySol {}),
+            accounts.clone(),
+        );
+
+        // SOL sent to a basket with no shares outstanding is surplus
+        airdrop_sol(&mut context, &basket_pda, 200_000_000).await;
+        process_ix(&mut context, queue_ix(200_000_000), &payer).await.unwrap();
+        process_ix(&mut context, execute_ix(), &payer).await.unwrap();
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // The deposit backs the user's shares and can't be withdrawn
+        process_ix(&mut context, queue_ix(100_000_000), &payer).await.unwrap();
+        let result = process_ix(&mut context, execute_ix(), &payer).await;
+        assert_basket_error(result, BasketError::WouldUndercollateralize);
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_authority_sol_keeps_rent_exemption() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let accounts = WithdrawAuthoritySol {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = |amount: u64| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueWithdrawAuthoritySol {
+                amount,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteWithdrawAuthoritySol {}),
+            accounts.clone(),
+        );
+
+        airdrop_sol(&mut context, &basket_pda, 200_000_000).await;
+        let basket_account = context.banks_client.get_account(basket_pda).await.unwrap().unwrap();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let rent_exempt_minimum = rent.minimum_balance(basket_account.data.len());
+
+        // Draining the basket to zero would leave it below the rent floor
+        process_ix(&mut context, queue_ix(basket_account.lamports), &payer).await.unwrap();
+        let result = process_ix(&mut context, execute_ix(), &payer).await;
+        assert_basket_error(result, BasketError::WouldBreakRentExemption);
+
+        process_ix(&mut context, queue_ix(basket_account.lamports - rent_exempt_minimum), &payer)
+            .await
+            .unwrap();
+        process_ix(&mut context, execute_ix(), &payer).await.unwrap();
+
+        let basket_account = context.banks_client.get_account(basket_pda).await.unwrap().unwrap();
+        assert_eq!(basket_account.lamports, rent_exempt_minimum);
+    }
+
+    #[tokio::test]
+    async fn test_two_phase_initialize() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::InitializeState {
+                basket_id: 0,
+                max_tokens: 5,
+            }),
+            InitializeState {
+                basket: basket_pda,
+                registry: registry_pda(),
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert!(!basket_state.initialized);
+        assert_eq!(basket_state.max_tokens, 5);
+
+        // No deposits before the mint exists
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(&user, &basket_pda, &mint_pda, deposit_args(1_000_000_000, 0), vec![]);
+        let result = process_ix(&mut context, ix, &user).await;
+        let code = match result.unwrap_err().unwrap() {
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => code,
+            err => panic!("unexpected error: {:?}", err),
+        };
+        assert_eq!(code, anchor_lang::error::ErrorCode::AccountNotInitialized as u32);
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::InitializeMint {
+                decimals: 6,
+            }),
+            InitializeMint {
+                basket: basket_pda,
+                basket_mint: mint_pda,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: token::ID,
+                rent: sysvar::rent::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        assert!(get_basket_state(&mut context, &basket_pda).await.initialized);
+        let mint = context.banks_client.get_account(mint_pda).await.unwrap().unwrap();
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint.data).unwrap();
+        assert_eq!(mint.base.decimals, 6);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_restriction_blocks_user_transfers() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+
+        // Two-step setup on Token-2022, which gives the mint a TransferHook slot
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::InitializeState {
+                basket_id: 0,
+                max_tokens: 5,
+            }),
+            InitializeState {
+                basket: basket_pda,
+                registry: registry_pda(),
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::InitializeMint {
+                decimals: 6,
+            }),
+            InitializeMint {
+                basket: basket_pda,
+                basket_mint: mint_pda,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token_2022::ID,
+                rent: sysvar::rent::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let extra_account_metas = Pubkey::find_program_address(
+            &[transfer_hook::EXTRA_ACCOUNT_METAS_SEED, mint_pda.as_ref()],
+            &id(),
+        ).0;
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::EnableTransferRestriction {}),
+            EnableTransferRestriction {
+                basket: basket_pda,
+                basket_mint: mint_pda,
+                extra_account_metas,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token_2022::ID,
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // Deposits wrap SOL under the basket's token program, whose native
+        // mint program-test doesn't create
+        let ix = spl_token_2022::instruction::create_native_mint(&spl_token_2022::ID, &payer.pubkey())
+            .unwrap();
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // Minting shares on deposit doesn't go through the hook
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let user_shares =
+            get_associated_token_address_with_program_id(&user.pubkey(), &mint_pda, &spl_token_2022::ID);
+        let mut accounts = Deposit {
+            basket: basket_pda,
+            basket_mint: mint_pda,
+            user_basket_token: user_shares,
+            basket_share_token: get_associated_token_address_with_program_id(
+                &basket_pda,
+                &mint_pda,
+                &spl_token_2022::ID,
+            ),
+            native_mint: spl_token_2022::native_mint::ID,
+            basket_wsol: get_associated_token_address_with_program_id(
+                &basket_pda,
+                &spl_token_2022::native_mint::ID,
+                &spl_token_2022::ID,
+            ),
+            fee_recipient: payer.pubkey(),
+            user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+            swap_program: jupiter::JUPITER_V6_ID,
+            user: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token_2022::ID,
+            associated_token_program: associated_token::ID,
+        }.to_account_metas(None);
+        accounts.extend(swap_accounts);
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&deposit_args(1_000_000_000, 1)),
+            accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let shares = |data: &[u8]| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(data).unwrap().base.amount
+        };
+        let account = context.banks_client.get_account(user_shares).await.unwrap().unwrap();
+        assert_eq!(shares(&account.data), 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+
+        // Handing shares to another user is rejected by the hook
+        let recipient = Keypair::new();
+        let recipient_shares = get_associated_token_address_with_program_id(
+            &recipient.pubkey(),
+            &mint_pda,
+            &spl_token_2022::ID,
+        );
+        let ix = spl_associated_token_account::instruction::create_associated_token_account(
+            &user.pubkey(),
+            &recipient.pubkey(),
+            &mint_pda,
+            &spl_token_2022::ID,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let mut ix = spl_token_2022::instruction::transfer_checked(
+            &spl_token_2022::ID,
+            &user_shares,
+            &mint_pda,
+            &recipient_shares,
+            &user.pubkey(),
+            &[],
+            1_000_000,
+            6,
+        ).unwrap();
+        // Token-2022 finds the hook's accounts among the transfer's extras
+        ix.accounts.push(AccountMeta::new_readonly(extra_account_metas, false));
+        ix.accounts.push(AccountMeta::new_readonly(id(), false));
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::TransferRestricted);
+
+        let account = context.banks_client.get_account(recipient_shares).await.unwrap().unwrap();
+        assert_eq!(shares(&account.data), 0);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_requires_token_accounts() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Registered and priced, but its basket token account was never created
+        let token_mint = create_mint(&mut context, &payer).await;
+        add_token(&mut context, &payer, &basket_pda, token_mint, 100).await.unwrap();
+        set_price_feed(&mut context, &payer, &basket_pda, token_mint).await;
+        set_pyth_price(&mut context, &price_feed_for(&token_mint), 1, 0).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &[token_mint]).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::TokenAccountNotInitialized);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_cooldown() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let (token_a, token_b) = (mints[0], mints[1]);
+
+        let from_token = get_associated_token_address(&basket_pda, &token_a);
+        let to_token = get_associated_token_address(&basket_pda, &token_b);
+        mint_tokens(&mut context, &token_a, &payer, &from_token, 1_000_000_000).await;
+        give_mint_to_jupiter(&mut context, &payer, &token_b).await;
+        create_token_account(&mut context, &token_a, &jupiter_mint_authority()).await;
+        let route = token_route(&basket_pda, &from_token, &token_a, &token::ID, &to_token, &token_b);
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetRebalanceCooldown {
+                rebalance_cooldown_seconds: 3_600,
+            }),
+            SetRebalanceCooldown {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let rebalance_ix = |in_amount: u64| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::Rebalance {
+                from_mint: token_a,
+                to_mint: token_b,
+                in_amount,
+                jupiter_quote: [0; 32],
+                slippage_bps: 100,
+            }),
+            [
+                Rebalance {
+                    basket: basket_pda,
+                    from_token,
+                    to_token,
+                    swap_program: jupiter::JUPITER_V6_ID,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                route.clone(),
+            ].concat(),
+        );
+
+        process_ix(&mut context, rebalance_ix(100_000_000), &payer).await.unwrap();
+        let result = process_ix(&mut context, rebalance_ix(100_000_000), &payer).await;
+        assert_basket_error(result, BasketError::RebalanceCooldown);
+    }
+
+    #[tokio::test]
+    async fn test_preview_redeem_matches_redeem() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                exit_fee_bps: 100,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        let basket_token = swap_accounts[1].pubkey;
+        mint_tokens(&mut context, &mints[0], &payer, &basket_token, 1_000_000_000).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::PreviewRedeem {
+                shares: 500_000_000,
+            }),
+            [
+                PreviewRedeem { basket: basket_pda }.to_account_metas(None),
+                vec![
+                    AccountMeta::new_readonly(basket_token, false),
+                    AccountMeta::new_readonly(price_feed_for(&mints[0]), false),
+                ],
+            ].concat(),
+        );
+        let preview: RedeemPreview = simulate_return_data(&mut context, ix, &payer).await;
+        assert_eq!(preview.gross_sol, 1_000_000_000);
+        assert_eq!(preview.fee, 10_000_000);
+        assert_eq!(preview.net_sol, 990_000_000);
+
+        let mut sell_accounts = create_sol_route_accounts(&mut context, &basket_pda, &basket_token, &mints[0]).await;
+        sell_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                accounts_per_swap: vec![8],
+                ..redeem_args(500_000_000, 1)
+            },
+            sell_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        // Realized proceeds land within the 100 bps slippage of the preview
+        let received = context.banks_client.get_balance(user.pubkey()).await.unwrap() - user_before;
+        let tolerance = preview.net_sol / 100;
+        assert!(received >= preview.net_sol - tolerance && received <= preview.net_sol + tolerance);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_skips_zero_weight_leg() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 0, 40]).await;
+
+        // Routes only for the weighted legs, then the placeholder's own
+        // account, then every price feed
+        let routes = create_basket_swap_accounts(&mut context, &basket_pda, &[mints[0], mints[2]]).await;
+        let mut remaining_accounts = routes[..24].to_vec();
+        remaining_accounts.push(AccountMeta::new(
+            get_associated_token_address(&basket_pda, &mints[1]),
+            false,
+        ));
+        for mint in &mints {
+            remaining_accounts.push(AccountMeta::new_readonly(price_feed_for(mint), false));
+        }
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                accounts_per_swap: vec![12, 12],
+                ..deposit_args(1_000_000_000, 3)
+            },
+            remaining_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[&user],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+        assert!(result.result.is_ok());
+
+        let logs = result.metadata.unwrap().log_messages;
+        assert_eq!(logged_in_amounts(&logs).len(), 2);
+    }
+
+    #[test]
+    fn test_to_u64_rejects_truncation() {
+        assert_eq!(nav::to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+
+        // A redemption ratio above 1x on a full balance no longer fits in u64
+        let redemption_ratio = 2 * basket_token::MAGNIFIER;
+        let redeem_amount = (u64::MAX as u128) * redemption_ratio / basket_token::MAGNIFIER;
+        assert_eq!(
+            nav::to_u64(redeem_amount).unwrap_err(),
+            error!(BasketError::MathOverflow)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_lists_baskets() {
+        let (mut context, payer, _, _) = setup().await;
+        let (first_basket, first_mint) = basket_pdas(0);
+        let (second_basket, second_mint) = basket_pdas(1);
+        initialize_basket_with_id(&mut context, &payer, 0, &first_basket, &first_mint)
+            .await
+            .unwrap();
+        initialize_basket_with_id(&mut context, &payer, 1, &second_basket, &second_mint)
+            .await
+            .unwrap();
+
+        let get_registry_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetRegistry {}),
+            GetRegistry { registry: registry_pda() }.to_account_metas(None),
+        );
+        let basket_ids: Vec<u64> = simulate_return_data(&mut context, get_registry_ix(), &payer).await;
+        assert_eq!(basket_ids, vec![0, 1]);
+
+        // Closing an empty basket drops its id
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::CloseBasket {}),
+            CloseBasket {
+                basket: second_basket,
+                registry: registry_pda(),
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let basket_ids: Vec<u64> = simulate_return_data(&mut context, get_registry_ix(), &payer).await;
+        assert_eq!(basket_ids, vec![0]);
+        assert!(context.banks_client.get_account(second_basket).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deposit_max_allocation_refunds_excess() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Deposit {
+                max_allocation_lamports: Some(400_000_000),
+                ..deposit_args(1_000_000_000, 1)
+            },
+            swap_accounts,
+        );
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        // Only the allocation left the user's wallet, less the rent of the
+        // user's new token and deposit accounts and the basket's share account
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let account_rent = 2 * rent.minimum_balance(165) + rent.minimum_balance(UserDeposit::required_space());
+        assert_eq!(user_before - user_after, 400_000_000 + account_rent);
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 400_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_reassigned_user_ata() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // The user's basket-token ATA exists but its owner was handed to someone else
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let user_basket_token = create_token_account(&mut context, &mint_pda, &user.pubkey()).await;
+        let set_owner_ix = token::spl_token::instruction::set_authority(
+            &token::ID,
+            &user_basket_token,
+            Some(&Keypair::new().pubkey()),
+            token::spl_token::instruction::AuthorityType::AccountOwner,
+            &user.pubkey(),
+            &[],
+        ).unwrap();
+        process_ix(&mut context, set_owner_ix, &user).await.unwrap();
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        assert!(process_ix(&mut context, ix, &user).await.is_err());
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 0);
+        assert_eq!(token_balance(&mut context, &user_basket_token).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_fee_recipient_routes_fees() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                entry_fee_bps: 100,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let set_fee_recipient_ix = |new_recipient: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetFeeRecipient {
+                new_recipient,
+            }),
+            SetFeeRecipient {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        let result = process_ix(&mut context, set_fee_recipient_ix(Pubkey::default()), &payer).await;
+        assert_basket_error(result, BasketError::InvalidFeeRecipient);
+
+        let treasury = Pubkey::new_unique();
+        process_ix(&mut context, set_fee_recipient_ix(treasury), &payer).await.unwrap();
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.fee_recipient, treasury);
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let deposit_to = |recipient: Pubkey| {
+            let mut ix = deposit_ix(
+                &user,
+                &basket_pda,
+                &mint_pda,
+                deposit_args(1_000_000_000, 1),
+                swap_accounts.clone(),
+            );
+            ix.accounts
+            .iter_mut()
+            .find(|meta| meta.pubkey == fee_recipient())
+            .unwrap()
+            .pubkey = recipient;
+            ix
+        };
+
+        // The old recipient is no longer accepted
+        let result = process_ix(&mut context, deposit_to(fee_recipient()), &user).await;
+        assert_basket_error(result, BasketError::InvalidFeeRecipient);
+
+        process_ix(&mut context, deposit_to(treasury), &user).await.unwrap();
+        assert_eq!(context.banks_client.get_balance(treasury).await.unwrap(), 10_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_hold_period() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetMinHoldSeconds {
+                min_hold_seconds: 3_600,
+            }),
+            SetMinHoldSeconds {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let redeem = || redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(500_000_000, 1),
+            swap_accounts.clone(),
+        );
+
+        // Inside the window the redeem reverts
+        let result = process_ix(&mut context, redeem(), &user).await;
+        assert_basket_error(result, BasketError::HoldPeriodNotMet);
+
+        // Once the hold has elapsed it goes through
+        let record = context.banks_client
+            .get_account(user_deposit_pda(&basket_pda, &user.pubkey()))
+            .await
+            .unwrap()
+            .unwrap();
+        let user_deposit = UserDeposit::try_deserialize(&mut &record.data[..]).unwrap();
+        let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp = user_deposit.last_deposit_ts + 3_600;
+        context.set_sysvar(&clock);
+        for mint in &mints {
+            set_pyth_price(&mut context, &price_feed_for(mint), 1, 0).await;
+        }
+        process_ix(&mut context, redeem(), &user).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_redeem_across_leg_transactions() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // 1 SOL idle plus 1 SOL of each token
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+        for (mint, basket_token) in mints.iter().zip(&basket_tokens) {
+            mint_tokens(&mut context, mint, &payer, basket_token, 1_000_000_000).await;
+        }
+
+        let pending_redeem = Pubkey::find_program_address(
+            &[b"pending_redeem", basket_pda.as_ref(), user.pubkey().as_ref()],
+            &id(),
+        ).0;
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::BeginRedeem {
+                shares: 500_000_000,
+            }),
+            [
+                BeginRedeem {
+                    basket: basket_pda,
+                    basket_mint: mint_pda,
+                    user_basket_token: get_associated_token_address(&user.pubkey(), &mint_pda),
+                    user_deposit: user_deposit_pda(&basket_pda, &user.pubkey()),
+                    pending_redeem,
+                    user: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                basket_tokens.iter().map(|t| AccountMeta::new_readonly(*t, false)).collect(),
+            ].concat(),
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // Shares are burned up front; half of each holding is reserved
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 500_000_000);
+        assert_eq!(basket_state.tokens[0].reserved_amount, 500_000_000);
+        assert_eq!(basket_state.pending_redeem_lamports, 500_000_000);
+
+        let mut routes = Vec::new();
+        for (mint, basket_token) in mints.iter().zip(&basket_tokens) {
+            let mut route = create_sol_route_accounts(&mut context, &basket_pda, basket_token, mint).await;
+            route.push(AccountMeta::new_readonly(price_feed_for(mint), false));
+            routes.push(route);
+        }
+        let redeem_leg_ix = |leg_index: u8| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::RedeemLeg {
+                leg_index,
+                jupiter_quote: [leg_index; 32],
+                slippage_bps: 100,
+            }),
+            [
+                RedeemLeg {
+                    basket: basket_pda,
+                    pending_redeem,
+                    swap_program: jupiter::JUPITER_V6_ID,
+                    user: user.pubkey(),
+                }.to_account_metas(None),
+                routes[leg_index as usize].clone(),
+            ].concat(),
+        );
+        let finalize_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::FinalizeRedeem {
+                minimum_sol_amount: 0,
+            }),
+            FinalizeRedeem {
+                basket: basket_pda,
+                pending_redeem,
+                fee_recipient: fee_recipient(),
+                user: user.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        process_ix(&mut context, redeem_leg_ix(0), &user).await.unwrap();
+        let result = process_ix(&mut context, redeem_leg_ix(0), &user).await;
+        assert_basket_error(result, BasketError::LegAlreadyCompleted);
+        let result = process_ix(&mut context, finalize_ix.clone(), &user).await;
+        assert_basket_error(result, BasketError::LegsIncomplete);
+        process_ix(&mut context, redeem_leg_ix(1), &user).await.unwrap();
+
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[finalize_ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        // Half the idle SOL plus both sold slices, and the pending account's rent back
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let pending_rent = rent.minimum_balance(PendingRedeem::required_space(2));
+        assert_eq!(user_after - user_before, 1_500_000_000 + pending_rent);
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.pending_redeem_lamports, 0);
+        assert!(basket_state.tokens.iter().all(|t| t.reserved_amount == 0));
+        assert!(context.banks_client.get_account(pending_redeem).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_shares_for_prices_against_nav() {
+        // The first deposit bootstraps 1:1
+        let mut basket = BasketState::default();
+        assert_eq!(basket.shares_for(1_000_000_000, 0).unwrap(), 1_000_000_000);
+
+        // After the basket doubles in value the same deposit buys half as many
+        basket.total_supply = 1_000_000_000;
+        assert_eq!(basket.shares_for(1_000_000_000, 2_000_000_000).unwrap(), 500_000_000);
+
+        // A NAV of one lamport behind 10^18 shares is implausible
+        basket.total_supply = 1_000_000_000_000_000_000;
+        assert_eq!(
+            basket.shares_for(1_000, 1).unwrap_err(),
+            error!(BasketError::MathOverflow)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initialize_all_token_accounts() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mut mints = Vec::new();
+        for weight in [50, 30, 20] {
+            let token_mint = create_mint(&mut context, &payer).await;
+            add_token(&mut context, &payer, &basket_pda, token_mint, weight).await.unwrap();
+            mints.push(token_mint);
+        }
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+
+        // One account already exists; the call skips it and creates the rest
+        create_token_account(&mut context, &mints[1], &basket_pda).await;
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(
+                &crate::instruction::InitializeAllTokenAccounts {},
+            ),
+            [
+                InitializeAllTokenAccounts {
+                    basket: basket_pda,
+                    authority: payer.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: token::ID,
+                    associated_token_program: associated_token::ID,
+                }.to_account_metas(None),
+                mints
+                    .iter()
+                    .zip(&basket_tokens)
+                    .flat_map(|(mint, basket_token)| [
+                        AccountMeta::new_readonly(*mint, false),
+                        AccountMeta::new(*basket_token, false),
+                    ])
+                    .collect(),
+            ].concat(),
+        );
+        process_ix(&mut context, ix.clone(), &payer).await.unwrap();
+
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        for (token_info, basket_token) in basket_state.tokens.iter().zip(&basket_tokens) {
+            assert_eq!(token_info.token_account, *basket_token);
+            assert!(context.banks_client.get_account(*basket_token).await.unwrap().is_some());
+        }
+
+        // Re-running is a no-op
+        process_ix(&mut context, ix, &payer).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_token() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let backing_mint = create_mint(&mut context, &payer).await;
+        add_token(&mut context, &payer, &basket_pda, backing_mint, 100).await.unwrap();
+        let orphan_mint = create_mint(&mut context, &payer).await;
+
+        let withdraw_ix = |mint: Pubkey, basket_token: Pubkey, authority_token: Pubkey, amount: u64| {
+            Instruction::new_with_bytes(
+                id(),
+                &anchor_lang::InstructionData::data(
+                    &crate::instruction::WithdrawToken { amount },
+                ),
+                WithdrawToken {
+                    basket: basket_pda,
+                    basket_token,
+                    authority_token,
+                    mint,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+            )
+        };
+
+        // A token sent to the basket by mistake can be swept
+        let basket_orphan = create_token_account(&mut context, &orphan_mint, &basket_pda).await;
+        let authority_orphan = create_token_account(&mut context, &orphan_mint, &payer.pubkey()).await;
+        mint_tokens(&mut context, &orphan_mint, &payer, &basket_orphan, 1_000).await;
+        let ix = withdraw_ix(orphan_mint, basket_orphan, authority_orphan, 400);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+        assert_eq!(token_balance(&mut context, &basket_orphan).await, 600);
+        assert_eq!(token_balance(&mut context, &authority_orphan).await, 400);
+
+        // Tokens backing shares cannot
+        let basket_backing = create_token_account(&mut context, &backing_mint, &basket_pda).await;
+        let authority_backing = create_token_account(&mut context, &backing_mint, &payer.pubkey()).await;
+        mint_tokens(&mut context, &backing_mint, &payer, &basket_backing, 1_000).await;
+        let ix = withdraw_ix(backing_mint, basket_backing, authority_backing, 400);
+        assert_basket_error(
+            process_ix(&mut context, ix, &payer).await,
+            BasketError::CannotWithdrawBackingToken,
+        );
+        assert_eq!(token_balance(&mut context, &basket_backing).await, 1_000);
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_token_rejects_locked_shares_and_wsol() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // The first deposit locks MINIMUM_LIQUIDITY shares in the basket
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(&user, &basket_pda, &mint_pda, deposit_args(1_000_000_000, 1), swap_accounts);
+        process_ix(&mut context, ix, &user).await.unwrap();
+        let basket_share_token = get_associated_token_address(&basket_pda, &mint_pda);
+        assert_eq!(
+            token_balance(&mut context, &basket_share_token).await,
+            basket_token::MINIMUM_LIQUIDITY
+        );
+
+        let withdraw_ix = |mint: Pubkey, basket_token: Pubkey, authority_token: Pubkey, amount: u64| {
+            Instruction::new_with_bytes(
+                id(),
+                &anchor_lang::InstructionData::data(
+                    &crate::instruction::WithdrawToken { amount },
+                ),
+                WithdrawToken {
+                    basket: basket_pda,
+                    basket_token,
+                    authority_token,
+                    mint,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+            )
+        };
+
+        let authority_shares = create_token_account(&mut context, &mint_pda, &payer.pubkey()).await;
+        let ix = withdraw_ix(mint_pda, basket_share_token, authority_shares, basket_token::MINIMUM_LIQUIDITY);
+        assert_basket_error(
+            process_ix(&mut context, ix, &payer).await,
+            BasketError::CannotWithdrawProtectedToken,
+        );
+        assert_eq!(
+            token_balance(&mut context, &basket_share_token).await,
+            basket_token::MINIMUM_LIQUIDITY
+        );
+
+        let native_mint = token::spl_token::native_mint::ID;
+        let basket_wsol = create_token_account(&mut context, &native_mint, &basket_pda).await;
+        let authority_wsol = create_token_account(&mut context, &native_mint, &payer.pubkey()).await;
+        let ix = withdraw_ix(native_mint, basket_wsol, authority_wsol, 0);
+        assert_basket_error(
+            process_ix(&mut context, ix, &payer).await,
+            BasketError::CannotWithdrawProtectedToken,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_reentrant_call() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        // Simulate being inside an outer call that already holds the guard
+        let mut basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut basket = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        basket.reentrancy_guard = true;
+        let mut data = Vec::new();
+        basket.try_serialize(&mut data).unwrap();
+        basket_account.data[..data.len()].copy_from_slice(&data);
+        context.set_account(&basket_pda, &AccountSharedData::from(basket_account));
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        assert_basket_error(
+            process_ix(&mut context, ix, &user).await,
+            BasketError::ReentrancyDetected,
+        );
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.total_supply, 0);
+    }
+
+    #[test]
+    fn test_redemption_ratio_precision_for_tiny_redemptions() {
+        let basket = BasketState {
+            total_supply: 1_000_000_000_000,
+            ..Default::default()
+        };
+
+        // One share of 10^12 against 10^15 base units of a high-decimal token
+        // is worth 1_000 units; a 1e9 scale would have rounded the ratio to 0
+        let ratio = basket.redemption_ratio(1).unwrap();
+        assert_eq!(ratio, 1_000_000);
+        let token_amount: u128 = 1_000_000_000_000_000;
+        assert_eq!(token_amount * ratio / basket_token::MAGNIFIER, 1_000);
+        assert_eq!(1_000_000_000 / basket.total_supply as u128, 0);
+
+        // Whole-supply redemption of u64::MAX units stays inside u128
+        let ratio = basket.redemption_ratio(basket.total_supply).unwrap();
+        assert_eq!(ratio, basket_token::MAGNIFIER);
+        assert_eq!(
+            (u64::MAX as u128).checked_mul(ratio).unwrap() / basket_token::MAGNIFIER,
+            u64::MAX as u128
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_weight_drift() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let basket_tokens: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&basket_pda, mint))
+            .collect();
+        set_pyth_price(&mut context, &price_feed_for(&mints[0]), 3, 0).await;
+        set_pyth_price(&mut context, &price_feed_for(&mints[1]), 1, 0).await;
+
+        let drift_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetWeightDrift {}),
+            [
+                GetWeightDrift { basket: basket_pda }.to_account_metas(None),
+                basket_tokens
+                    .iter()
+                    .map(|account| AccountMeta::new_readonly(*account, false))
+                    .collect(),
+                mints
+                    .iter()
+                    .map(|mint| AccountMeta::new_readonly(price_feed_for(mint), false))
+                    .collect(),
+            ].concat(),
+        );
+
+        // Nothing held yet: no drift rather than a division by zero
+        let drift: Vec<(Pubkey, i16)> = simulate_return_data(&mut context, drift_ix(), &payer).await;
+        assert_eq!(drift, vec![(mints[0], 0), (mints[1], 0)]);
+
+        // 3_000 + 1_000 lamports of value puts the legs at 75% / 25% against 60% / 40%
+        mint_tokens(&mut context, &mints[0], &payer, &basket_tokens[0], 1_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &basket_tokens[1], 1_000).await;
+        let drift: Vec<(Pubkey, i16)> = simulate_return_data(&mut context, drift_ix(), &payer).await;
+        assert_eq!(drift, vec![(mints[0], 1_500), (mints[1], -1_500)]);
+        assert!(drift[0].1 > 0 && drift[1].1 < 0);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_into_second_basket() {
+        let (mut context, payer, _, _) = setup().await;
+        let (first_basket, first_mint) = basket_pdas(0);
+        let (second_basket, second_mint) = basket_pdas(1);
+        initialize_basket_with_id(&mut context, &payer, 0, &first_basket, &first_mint)
+            .await
+            .unwrap();
+        initialize_basket_with_id(&mut context, &payer, 1, &second_basket, &second_mint)
+            .await
+            .unwrap();
+        setup_basket_with_tokens(&mut context, &payer, &first_basket, &[100]).await;
+        let mints = setup_basket_with_tokens(&mut context, &payer, &second_basket, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &second_basket, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+
+        // The share mint must be the one derived from the basket's own id
+        let ix = deposit_ix(
+            &user,
+            &second_basket,
+            &first_mint,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        assert!(process_ix(&mut context, ix, &user).await.is_err());
+
+        let ix = deposit_ix(
+            &user,
+            &second_basket,
+            &second_mint,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let first = get_basket_state(&mut context, &first_basket).await;
+        let second = get_basket_state(&mut context, &second_basket).await;
+        assert_eq!(first.total_supply, 0);
+        assert_eq!(second.total_supply, 1_000_000_000);
+        assert_eq!(second.basket_id, 1);
+    }
+
+    #[tokio::test]
+    async fn test_first_deposit_locks_minimum_liquidity() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let basket_share_token = get_associated_token_address(&basket_pda, &mint_pda);
+
+        let first_user = Keypair::new();
+        airdrop_sol(&mut context, &first_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &first_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &first_user).await.unwrap();
+
+        // The basket holds the locked shares; the depositor gets the rest
+        assert_eq!(
+            token_balance(&mut context, &basket_share_token).await,
+            basket_token::MINIMUM_LIQUIDITY
+        );
+        let first_shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&first_user.pubkey(), &mint_pda),
+        ).await;
+        assert_eq!(first_shares, 1_000_000_000 - basket_token::MINIMUM_LIQUIDITY);
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.total_supply, 1_000_000_000);
+
+        // Redeeming every share the depositor holds can't drain the supply
+        // down to a few shares whose price a tiny deposit could skew
+        let ix = redeem_ix(
+            &first_user,
+            &basket_pda,
+            &mint_pda,
+            redeem_args(first_shares, 1),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &first_user).await.unwrap();
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, basket_token::MINIMUM_LIQUIDITY);
+
+        // A second deposit is priced against the locked shares' NAV and
+        // locks nothing further
+        let second_user = Keypair::new();
+        airdrop_sol(&mut context, &second_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &second_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(basket_token::MINIMUM_DEPOSIT, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &second_user).await.unwrap();
+        let second_shares = token_balance(
+            &mut context,
+            &get_associated_token_address(&second_user.pubkey(), &mint_pda),
+        ).await;
+        assert!(second_shares > 0);
+        assert_eq!(
+            token_balance(&mut context, &basket_share_token).await,
+            basket_token::MINIMUM_LIQUIDITY
+        );
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(
+            basket_state.total_supply,
+            basket_token::MINIMUM_LIQUIDITY + second_shares
+        );
+    }
+
+    #[test]
+    fn test_locked_liquidity_only_on_first_deposit() {
+        let mut basket = BasketState::default();
+        assert_eq!(
+            basket.locked_liquidity(basket_token::MINIMUM_LIQUIDITY).unwrap_err(),
+            error!(BasketError::InsufficientDeposit)
+        );
+        assert_eq!(
+            basket.locked_liquidity(1_000_000_000).unwrap(),
+            basket_token::MINIMUM_LIQUIDITY
+        );
+
+        basket.total_supply = 1_000_000_000;
+        assert_eq!(basket.locked_liquidity(1).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_remaining_accounts_return_error() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+
+        // Cut off partway through the second route: a clean program error,
+        // not an out-of-bounds panic
+        for truncated_len in [0, 1, 13, swap_accounts.len() - 1] {
+            let ix = deposit_ix(
+                &user,
+                &basket_pda,
+                &mint_pda,
+                deposit_args(1_000_000_000, 2),
+                swap_accounts[..truncated_len].to_vec(),
+            );
+            let result = process_ix(&mut context, ix, &user).await;
+            assert_basket_error(result, BasketError::InvalidAccountCount);
+
+            let ix = redeem_ix(
+                &user,
+                &basket_pda,
+                &mint_pda,
+                redeem_args(1, 2),
+                swap_accounts[..truncated_len].to_vec(),
+            );
+            let result = process_ix(&mut context, ix, &user).await;
+            assert!(result.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dust_deposit_reverts_when_shares_round_to_zero() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                minimum_deposit: 1,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let first_user = Keypair::new();
+        airdrop_sol(&mut context, &first_user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &first_user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts.clone(),
+        );
+        process_ix(&mut context, ix, &first_user).await.unwrap();
+
+        // The basket appreciates to 2 lamports per share, and dust legs stay idle
+        airdrop_sol(&mut context, &basket_pda, 1_000_000_000).await;
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetMinSwapLamports {
+                min_swap_lamports: 1_000,
+                roll_small_legs: false,
+            }),
+            SetMinSwapLamports {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // One lamport buys half a share, which rounds to nothing
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 1_000_000_000).await;
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let ix = deposit_ix(&user, &basket_pda, &mint_pda, deposit_args(1, 2), swap_accounts);
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        let result = context.banks_client.process_transaction(transaction).await;
+        assert_basket_error(result, BasketError::DepositTooSmallForShares);
+
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        assert_eq!(user_after, user_before);
+        let basket_state = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket_state.total_supply, 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_through_second_allowed_swap_program() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let (token_a, token_b) = (mints[0], mints[1]);
+
+        let from_token = get_associated_token_address(&basket_pda, &token_a);
+        let to_token = get_associated_token_address(&basket_pda, &token_b);
+        mint_tokens(&mut context, &token_a, &payer, &from_token, 1_000_000_000).await;
+
+        // The second router mints token B under its own authority PDA
+        let alt_mint_authority =
+            Pubkey::find_program_address(&[b"mint_authority"], &ALT_SWAP_PROGRAM_ID).0;
+        let set_authority_ix = token::spl_token::instruction::set_authority(
+            &token::ID,
+            &token_b,
+            Some(&alt_mint_authority),
+            token::spl_token::instruction::AuthorityType::MintTokens,
+            &payer.pubkey(),
+            &[],
+        ).unwrap();
+        process_ix(&mut context, set_authority_ix, &payer).await.unwrap();
+        create_token_account(&mut context, &token_a, &jupiter_mint_authority()).await;
+        let mut route = token_route(&basket_pda, &from_token, &token_a, &token::ID, &to_token, &token_b);
+        route[7] = AccountMeta::new_readonly(alt_mint_authority, false);
+
+        let rebalance_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::Rebalance {
+                from_mint: token_a,
+                to_mint: token_b,
+                in_amount: 400_000_000,
+                jupiter_quote: [0; 32],
+                slippage_bps: 100,
+            }),
+            [
+                Rebalance {
+                    basket: basket_pda,
+                    from_token,
+                    to_token,
+                    swap_program: ALT_SWAP_PROGRAM_ID,
+                    authority: payer.pubkey(),
+                    token_program: token::ID,
+                }.to_account_metas(None),
+                route.clone(),
+            ].concat(),
+        );
+        let update_accounts = UpdateSwapPrograms {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = |allowed: bool| Instruction::new_with_bytes(
+            id(),
+            &if allowed {
+                anchor_lang::InstructionData::data(&crate::instruction::QueueAddSwapProgram {
+                    program_id: ALT_SWAP_PROGRAM_ID,
+                })
+            } else {
+                anchor_lang::InstructionData::data(&crate::instruction::QueueRemoveSwapProgram {
+                    program_id: ALT_SWAP_PROGRAM_ID,
+                })
+            },
+            update_accounts.clone(),
+        );
+        let execute_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteUpdateSwapProgram {}),
+            update_accounts.clone(),
+        );
+
+        // Only the V6 program is allowed out of the box
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket.allowed_swap_programs, vec![jupiter::JUPITER_V6_ID]);
+        let result = process_ix(&mut context, rebalance_ix(), &payer).await;
+        assert_basket_error(result, BasketError::UnauthorizedSwapProgram);
+
+        // Test baskets have no timelock, so the queued change executes immediately
+        process_ix(&mut context, queue_ix(true), &payer).await.unwrap();
+        process_ix(&mut context, execute_ix(), &payer).await.unwrap();
+        process_ix(&mut context, rebalance_ix(), &payer).await.unwrap();
+
+        assert_eq!(token_balance(&mut context, &from_token).await, 600_000_000);
+        assert_eq!(token_balance(&mut context, &to_token).await, 400_000_000);
+
+        // Removing the program blocks it again
+        process_ix(&mut context, queue_ix(false), &payer).await.unwrap();
+        process_ix(&mut context, execute_ix(), &payer).await.unwrap();
+        let result = process_ix(&mut context, rebalance_ix(), &payer).await;
+        assert_basket_error(result, BasketError::UnauthorizedSwapProgram);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_buy_leg_passes_slippage_check() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+
+        // A SOL-to-token route whose Jupiter-owned input holds the leg's
+        // allocation; the mock mints one token per lamport it spends
+        let mut route = create_buy_route_accounts(&mut context, &payer, &basket_pda, &mints[0]).await;
+        let input = route[0].pubkey;
+        let rent = context.banks_client.get_rent().await.unwrap();
+        context.set_account(
+            &input,
+            &AccountSharedData::new(
+                rent.minimum_balance(0) + 500_000_000,
+                0,
+                &jupiter::JUPITER_V6_ID,
+            ),
+        );
+        route.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+
+        let mut args = deposit_args(500_000_000, 1);
+        args.accounts_per_swap = vec![7];
+        let basket_lamports_before = context.banks_client.get_balance(basket_pda).await.unwrap();
+        let ix = deposit_ix(&payer, &basket_pda, &mint_pda, args, route);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // The leg bought its whole allocation without the basket's lamport
+        // accounting underflowing
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        assert_eq!(token_balance(&mut context, &basket_token).await, 500_000_000);
+        assert_eq!(
+            context.banks_client.get_balance(input).await.unwrap(),
+            rent.minimum_balance(0)
+        );
+        let basket_lamports_after = context.banks_client.get_balance(basket_pda).await.unwrap();
+        assert_eq!(basket_lamports_after - basket_lamports_before, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v1_basket() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        // Rewrite the basket as a version 1 account: none of the fields later
+        // versions appended and only the room that layout needed
+        let mut basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut basket = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        basket.allowed_swap_programs = vec![];
+        basket.version = 0;
+        let mut data = Vec::new();
+        basket.try_serialize(&mut data).unwrap();
+        let current_space = BasketState::required_space(basket.max_tokens as usize);
+        let v1_space = current_space
+            - BasketState::ALLOWED_SWAP_PROGRAMS_SIZE
+            - BasketState::VERSION_SIZE
+            - BasketState::PAUSE_REASON_SIZE
+            - BasketState::SWAP_DISCRIMINATOR_SIZE
+            - BasketState::MAX_SWAPS_PER_TX_SIZE;
+        data.resize(v1_space, 0);
+        basket_account.data = data;
+        context.set_account(&basket_pda, &AccountSharedData::from(basket_account));
+
+        let migrate_ix = |authority: &Keypair| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::Migrate {}),
+            Migrate {
+                basket: basket_pda,
+                authority: authority.pubkey(),
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+        );
+
+        let stranger = Keypair::new();
+        airdrop_sol(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+        let result = process_ix(&mut context, migrate_ix(&stranger), &stranger).await;
+        assert_basket_error(result, BasketError::Unauthorized);
+
+        process_ix(&mut context, migrate_ix(&payer), &payer).await.unwrap();
+
+        // The account grew to the current layout and the new fields took their defaults
+        let basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(basket_account.data.len(), current_space);
+        let rent = context.banks_client.get_rent().await.unwrap();
+        assert!(basket_account.lamports >= rent.minimum_balance(current_space));
+
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket.version, BasketState::CURRENT_VERSION);
+        assert_eq!(basket.allowed_swap_programs, vec![jupiter::JUPITER_V6_ID]);
+        assert!(basket.pause_reason.is_empty());
+        assert_eq!(basket.swap_discriminator, jupiter::ROUTE_DISCRIMINATOR);
+        assert_eq!(basket.max_swaps_per_tx, 0);
+        assert_eq!(basket.authority, payer.pubkey());
+        assert!(basket.initialized);
+
+        // Running it again has nothing to do
+        let result = process_ix(&mut context, migrate_ix(&payer), &payer).await;
+        assert_basket_error(result, BasketError::AlreadyMigrated);
+    }
+
+    #[tokio::test]
+    async fn test_get_deposit_capacity() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                max_deposit_per_user: 1_500_000_000,
+                max_tvl_lamports: 5_000_000_000,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let capacity_ix = |basket_pda: Pubkey, user: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetDepositCapacity { _user: user }),
+            GetDepositCapacity {
+                basket: basket_pda,
+                user_deposit: user_deposit_pda(&basket_pda, &user),
+            }.to_account_metas(None),
+        );
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let capacity: DepositCapacity =
+            simulate_return_data(&mut context, capacity_ix(basket_pda, user.pubkey()), &payer).await;
+        assert_eq!(
+            capacity,
+            DepositCapacity {
+                tvl_remaining: 5_000_000_000,
+                user_remaining: 1_500_000_000,
+            }
+        );
+
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let capacity: DepositCapacity =
+            simulate_return_data(&mut context, capacity_ix(basket_pda, user.pubkey()), &payer).await;
+        assert_eq!(
+            capacity,
+            DepositCapacity {
+                tvl_remaining: 4_000_000_000,
+                user_remaining: 500_000_000,
+            }
+        );
+
+        // A basket without caps reports unlimited headroom
+        let (uncapped_pda, uncapped_mint) = basket_pdas(1);
+        initialize_basket_with_id(&mut context, &payer, 1, &uncapped_pda, &uncapped_mint)
+            .await
+            .unwrap();
+        let capacity: DepositCapacity =
+            simulate_return_data(&mut context, capacity_ix(uncapped_pda, user.pubkey()), &payer).await;
+        assert_eq!(
+            capacity,
+            DepositCapacity {
+                tvl_remaining: u64::MAX,
+                user_remaining: u64::MAX,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeem_sums_sol_from_each_sell_leg() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 2),
+            swap_accounts,
+        );
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        // 1 SOL idle plus 1 SOL worth of each token
+        let token_a = get_associated_token_address(&basket_pda, &mints[0]);
+        let token_b = get_associated_token_address(&basket_pda, &mints[1]);
+        mint_tokens(&mut context, &mints[0], &payer, &token_a, 1_000_000_000).await;
+        mint_tokens(&mut context, &mints[1], &payer, &token_b, 1_000_000_000).await;
+
+        let mut sell_accounts = create_sol_route_accounts(&mut context, &basket_pda, &token_a, &mints[0]).await;
+        sell_accounts.extend(create_sol_route_accounts(&mut context, &basket_pda, &token_b, &mints[1]).await);
+        sell_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+        sell_accounts.push(AccountMeta::new_readonly(price_feed_for(&mints[1]), false));
+
+        // Each leg is held to its own floor, measured from just before its swap
+        let user_before = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        let ix = redeem_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            crate::instruction::Redeem {
+                minimum_sol_amounts: vec![500_000_000, 500_000_000],
+                accounts_per_swap: vec![8, 8],
+                ..redeem_args(500_000_000, 2)
+            },
+            sell_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&payer.pubkey()),
+            &[&payer, &user],
+            context.last_blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        // Half the idle SOL plus both legs' proceeds
+        let user_after = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+        assert_eq!(user_after - user_before, 500_000_000 + 500_000_000 + 500_000_000);
+        assert_eq!(token_balance(&mut context, &token_a).await, 500_000_000);
+        assert_eq!(token_balance(&mut context, &token_b).await, 500_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_pause_reason() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+
+        let pause_ix = |reason: Option<String>| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GuardianPause {
+                deposits: true,
+                redemptions: true,
+                reason,
+            }),
+            GuardianPause {
+                basket: basket_pda,
+                guardian: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        let result = process_ix(&mut context, pause_ix(Some("x".repeat(65))), &payer).await;
+        assert_basket_error(result, BasketError::PauseReasonTooLong);
+
+        let reason = "Oracle outage on token A".to_string();
+        let transaction = Transaction::new_signed_with_payer(
+            &[pause_ix(Some(reason.clone()))],
+            Some(&payer.pubkey()),
+            &[&payer],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        let logs = result.metadata.unwrap().log_messages;
+        let events: Vec<PauseStateChangedEvent> = decode_events(&logs);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].paused);
+        assert!(events[0].deposits_paused && events[0].redemptions_paused);
+        assert_eq!(events[0].reason, reason);
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.pause_reason, reason);
+
+        // Unpausing clears it
+        set_pause_state(&mut context, &payer, &basket_pda, false, false).await;
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert!(!basket.deposits_paused && !basket.redemptions_paused);
+        assert!(basket.pause_reason.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_health() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+
+        let health_ix = || Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::CheckHealth {}),
+            CheckHealth {
+                basket: basket_pda,
+                basket_mint: mint_pda,
+            }.to_account_metas(None),
+        );
+
+        let health_status: u8 = simulate_return_data(&mut context, health_ix(), &payer).await;
+        assert_eq!(health_status, 0);
+
+        // Break every invariant: weights past 100, a token with no account,
+        // a stuck guard and a supply the mint doesn't back
+        let mut basket_account = context.banks_client
+            .get_account(basket_pda)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut basket = BasketState::try_deserialize(&mut &basket_account.data[..]).unwrap();
+        basket.tokens[1].weight = 50;
+        basket.tokens[1].token_account = Pubkey::default();
+        basket.reentrancy_guard = true;
+        basket.total_supply = 1_000_000;
+        let mut data = Vec::new();
+        basket.try_serialize(&mut data).unwrap();
+        basket_account.data[..data.len()].copy_from_slice(&data);
+        context.set_account(&basket_pda, &AccountSharedData::from(basket_account));
+
+        let health_status: u8 = simulate_return_data(&mut context, health_ix(), &payer).await;
+        assert_eq!(
+            health_status,
+            basket_token::HEALTH_WEIGHTS_INCOMPLETE
+                | basket_token::HEALTH_SUPPLY_MISMATCH
+                | basket_token::HEALTH_REENTRANCY_GUARD_SET
+                | basket_token::HEALTH_TOKEN_ACCOUNT_MISSING
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deposit_swaps_from_wrapped_sol() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let native_mint = token::spl_token::native_mint::ID;
+
+        // The route sells the basket's wSOL for the token, so it only
+        // succeeds once that account exists and holds the synced deposit
+        let basket_wsol = get_associated_token_address(&basket_pda, &native_mint);
+        let basket_token = get_associated_token_address(&basket_pda, &mints[0]);
+        give_mint_to_jupiter(&mut context, &payer, &mints[0]).await;
+        let vault = create_token_account(&mut context, &native_mint, &jupiter_mint_authority()).await;
+        let mut route = token_route(&basket_pda, &basket_wsol, &native_mint, &token::ID, &basket_token, &mints[0]);
+        route.push(AccountMeta::new_readonly(price_feed_for(&mints[0]), false));
+
+        let mut args = deposit_args(500_000_000, 1);
+        args.accounts_per_swap = vec![10];
+        let basket_lamports_before = context.banks_client.get_balance(basket_pda).await.unwrap();
+        let ix = deposit_ix(&payer, &basket_pda, &mint_pda, args, route);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // The whole deposit left as wSOL and came back as the token
+        assert_eq!(token_balance(&mut context, &vault).await, 500_000_000);
+        assert_eq!(token_balance(&mut context, &basket_token).await, 500_000_000);
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&payer.pubkey(), &mint_pda)).await,
+            500_000_000 - basket_token::MINIMUM_LIQUIDITY
+        );
+
+        // Nothing was left to unwrap, and the emptied wSOL account is closed
+        assert!(context.banks_client.get_account(basket_wsol).await.unwrap().is_none());
+        assert_eq!(
+            context.banks_client.get_balance(basket_pda).await.unwrap(),
+            basket_lamports_before
+        );
+    }
+
+    #[tokio::test]
+    async fn test_activate_reserved_token_slot() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                min_weight: 10,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+
+        // Weight 0 reserves token B's slot despite the minimum weight
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 0]).await;
+
+        let activate_ix = |weight: u8| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ActivateToken {
+                token_mint: mints[1],
+                weight,
+            }),
+            UpdateTokenWeight {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+
+        let result = process_ix(&mut context, activate_ix(5), &payer).await;
+        assert_basket_error(result, BasketError::WeightBelowMinimum);
+        let result = process_ix(&mut context, activate_ix(50), &payer).await;
+        assert_basket_error(result, BasketError::WeightOverflow);
+
+        process_ix(&mut context, activate_ix(40), &payer).await.unwrap();
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket.weights(), vec![60, 40]);
+
+        let result = process_ix(&mut context, activate_ix(40), &payer).await;
+        assert_basket_error(result, BasketError::TokenAlreadyActive);
+
+        // Deposits now buy the activated leg alongside the first one
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let mut remaining = Vec::new();
+        for mint in &mints {
+            let route = create_buy_route_accounts(&mut context, &payer, &basket_pda, mint).await;
+            context.set_account(
+                &route[0].pubkey,
+                &AccountSharedData::new(
+                    rent.minimum_balance(0) + 1_000_000_000,
+                    0,
+                    &jupiter::JUPITER_V6_ID,
+                ),
+            );
+            remaining.extend(route);
+        }
+        for mint in &mints {
+            remaining.push(AccountMeta::new_readonly(price_feed_for(mint), false));
+        }
+
+        let mut args = deposit_args(500_000_000, 2);
+        args.accounts_per_swap = vec![7, 7];
+        let ix = deposit_ix(&payer, &basket_pda, &mint_pda, args, remaining);
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[0])).await,
+            300_000_000
+        );
+        assert_eq!(
+            token_balance(&mut context, &get_associated_token_address(&basket_pda, &mints[1])).await,
+            200_000_000
+        );
+    }
+
+    // Process `ix` and feed the basket events it emitted into `replay`
+    async fn process_and_replay(
+        context: &mut ProgramTestContext,
+        replay: &mut crate::replay::BasketReplay,
+        ix: Instruction,
+        signer: &Keypair,
+    ) {
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&signer.pubkey()),
+            &[signer],
+            context.get_new_latest_blockhash().await.unwrap(),
+        );
+        let result = process_transaction_with_metadata(context, transaction).await;
+        result.result.unwrap();
+
+        let logs = result.metadata.unwrap().log_messages;
+        for event in logs
+            .iter()
+            .filter_map(|log| event_data(log))
+            .filter_map(|data| anchor_lang::__private::base64::decode(data).ok())
+            .filter_map(|data| crate::replay::BasketEvent::decode(&data))
+        {
+            replay.apply_event(&event).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_matches_onchain_state() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mut replay = crate::replay::BasketReplay::default();
+
+        let mut mints = Vec::new();
+        for weight in [60, 40] {
+            let token_mint = create_mint(&mut context, &payer).await;
+            let ix = Instruction::new_with_bytes(
+                id(),
+                &anchor_lang::InstructionData::data(&crate::instruction::AddToken {
+                    token_mint,
+                    weight,
+                }),
+                AddToken {
+                    basket: basket_pda,
+                    allowlist: allowlist_pda(&basket_pda),
+                    authority: payer.pubkey(),
+                }.to_account_metas(None),
+            );
+            process_and_replay(&mut context, &mut replay, ix, &payer).await;
+            set_price_feed(&mut context, &payer, &basket_pda, token_mint).await;
+            set_pyth_price(&mut context, &price_feed_for(&token_mint), 1, 0).await;
+            initialize_token_account(&mut context, &payer, &basket_pda, token_mint).await.unwrap();
+            mints.push(token_mint);
+        }
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let first = Keypair::new();
+        let second = Keypair::new();
+        for (user, amount) in [(&first, 1_000_000_000), (&second, 400_000_000)] {
+            airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+            let ix = deposit_ix(user, &basket_pda, &mint_pda, deposit_args(amount, 2), swap_accounts.clone());
+            process_and_replay(&mut context, &mut replay, ix, user).await;
+        }
+        let ix = redeem_ix(&first, &basket_pda, &mint_pda, redeem_args(300_000_000, 2), swap_accounts);
+        process_and_replay(&mut context, &mut replay, ix, &first).await;
+
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(replay.total_supply, basket.total_supply);
+        assert_eq!(replay.total_supply, 1_100_000_000);
+        assert_eq!(
+            replay.tokens,
+            basket.tokens.iter().map(|t| (t.mint, t.weight)).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_info() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let basket_token = get_associated_token_address(&basket_pda, &mints[1]);
+        mint_tokens(&mut context, &mints[1], &payer, &basket_token, 5_000).await;
+
+        let token_info_ix = |mint: Pubkey| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::GetTokenInfo { mint }),
+            [
+                GetTokenInfo { basket: basket_pda }.to_account_metas(None),
+                vec![AccountMeta::new_readonly(basket_token, false)],
+            ]
+            .concat(),
+        );
+
+        let lookup: TokenLookup =
+            simulate_return_data(&mut context, token_info_ix(mints[1]), &payer).await;
+        assert_eq!(lookup.info.mint, mints[1]);
+        assert_eq!(lookup.info.weight, 40);
+        assert_eq!(lookup.info.token_account, basket_token);
+        assert_eq!(lookup.balance, 5_000);
+
+        let result = process_ix(&mut context, token_info_ix(Pubkey::new_unique()), &payer).await;
+        assert_basket_error(result, BasketError::TokenNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_custom_swap_discriminator() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[100]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        assert_eq!(
+            get_basket_state(&mut context, &basket_pda).await.swap_discriminator,
+            jupiter::ROUTE_DISCRIMINATOR
+        );
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetSwapDiscriminator {
+                swap_discriminator: 7,
+            }),
+            SetSwapDiscriminator {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 2_000_000_000).await;
+        let ix = deposit_ix(
+            &user,
+            &basket_pda,
+            &mint_pda,
+            deposit_args(1_000_000_000, 1),
+            swap_accounts,
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[&user],
+            context.last_blockhash,
+        );
+        let result = process_transaction_with_metadata(&mut context, transaction).await;
+
+        // The route instruction the mock router saw led with the new index
+        let logs = result.metadata.unwrap().log_messages;
+        let discriminators: Vec<&String> = logs
+            .iter()
+            .filter(|log| log.contains("Jupiter discriminator"))
+            .collect();
+        assert_eq!(discriminators.len(), 1);
+        assert!(discriminators[0].ends_with("Jupiter discriminator: 7"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_deposit() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[60, 40]).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+
+        let validate_ix = |remaining_accounts: Vec<AccountMeta>| Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ValidateDeposit {
+                amount: 1_000_000_000,
+                minimum_token_amounts: vec![0; 2],
+                accounts_per_swap: vec![12; 2],
+            }),
+            [
+                ValidateDeposit { basket: basket_pda }.to_account_metas(None),
+                remaining_accounts,
+            ]
+            .concat(),
+        );
+
+        let basket_before = context.banks_client.get_account(basket_pda).await.unwrap().unwrap();
+        process_ix(&mut context, validate_ix(swap_accounts.clone()), &payer).await.unwrap();
+
+        // Dropping the last price feed breaks the layout
+        let mut short_accounts = swap_accounts;
+        short_accounts.pop();
+        let result = process_ix(&mut context, validate_ix(short_accounts), &payer).await;
+        assert_basket_error(result, BasketError::InvalidAccountCount);
+
+        let basket_after = context.banks_client.get_account(basket_pda).await.unwrap().unwrap();
+        assert_eq!(basket_after, basket_before);
+        assert_eq!(get_basket_state(&mut context, &basket_pda).await.total_supply, 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_swaps_per_tx() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket(&mut context, &payer, &basket_pda, &mint_pda)
+            .await
+            .unwrap();
+        let mints = setup_basket_with_tokens(&mut context, &payer, &basket_pda, &[50, 50]).await;
+
+        let user = Keypair::new();
+        airdrop_sol(&mut context, &user.pubkey(), 3_000_000_000).await;
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let ix = deposit_ix(&user, &basket_pda, &mint_pda, deposit_args(1_000_000_000, 2), swap_accounts);
+        process_ix(&mut context, ix, &user).await.unwrap();
+
+        let ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::SetMaxSwapsPerTx {
+                max_swaps_per_tx: 1,
+            }),
+            SetMaxSwapsPerTx {
+                basket: basket_pda,
+                authority: payer.pubkey(),
+            }.to_account_metas(None),
+        );
+        process_ix(&mut context, ix, &payer).await.unwrap();
+
+        // Two weighted legs no longer fit in one deposit or redeem
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let ix = deposit_ix(&user, &basket_pda, &mint_pda, deposit_args(1_000_000_000, 2), swap_accounts);
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::TooManySwapsForTx);
+
+        let swap_accounts = create_basket_swap_accounts(&mut context, &basket_pda, &mints).await;
+        let ix = redeem_ix(&user, &basket_pda, &mint_pda, redeem_args(1_000, 2), swap_accounts);
+        let result = process_ix(&mut context, ix, &user).await;
+        assert_basket_error(result, BasketError::TooManySwapsForTx);
+    }
+
+    #[tokio::test]
+    async fn test_timelocked_add_swap_program() {
+        let (mut context, payer, basket_pda, mint_pda) = setup().await;
+        initialize_basket_with_args(
+            &mut context,
+            &payer,
+            crate::instruction::Initialize {
+                timelock_seconds: 3_600,
+                ..initialize_args(0)
+            },
+            &basket_pda,
+            &mint_pda,
+        )
+        .await
+        .unwrap();
+
+        let accounts = UpdateSwapPrograms {
+            basket: basket_pda,
+            authority: payer.pubkey(),
+        }.to_account_metas(None);
+        let queue_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::QueueAddSwapProgram {
+                program_id: ALT_SWAP_PROGRAM_ID,
+            }),
+            accounts.clone(),
+        );
+        let execute_ix = Instruction::new_with_bytes(
+            id(),
+            &anchor_lang::InstructionData::data(&crate::instruction::ExecuteUpdateSwapProgram {}),
+            accounts,
+        );
+
+        // The router can't be allowlisted before the delay passes
+        process_ix(&mut context, queue_ix, &payer).await.unwrap();
+        let result = process_ix(&mut context, execute_ix, &payer).await;
+        assert_basket_error(result, BasketError::TimelockNotElapsed);
+
+        let basket = get_basket_state(&mut context, &basket_pda).await;
+        assert_eq!(basket.allowed_swap_programs, vec![jupiter::JUPITER_V6_ID]);
     }
-}
\ No newline at end of file
+}
@@ -1,28 +1,36 @@
 import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
-import { Program, AnchorProvider, web3 } from "@project-serum/anchor";
+import { Program, AnchorProvider, BN, web3 } from "@project-serum/anchor";
 import { SystemProgram } from "@solana/web3.js";
 import { IDL } from "../target/types/basket_token";
 
 const provider = AnchorProvider.env();
 const program = new Program(IDL, "Your_Program_ID", provider);
 
+const basketId = new BN(0);
+
 (async () => {
   const [basketPda, basketBump] = await web3.PublicKey.findProgramAddress(
-    [Buffer.from("basket")],
+    [Buffer.from("basket"), basketId.toArrayLike(Buffer, "le", 8)],
     program.programId
   );
 
   const [basketMintPda, basketMintBump] = await web3.PublicKey.findProgramAddress(
-    [Buffer.from("basket_mint")],
+    [Buffer.from("basket_mint"), basketId.toArrayLike(Buffer, "le", 8)],
+    program.programId
+  );
+
+  const [registryPda] = await web3.PublicKey.findProgramAddress(
+    [Buffer.from("basket_registry")],
     program.programId
   );
 
   try {
     await program.methods
-      .initialize(5) // 5 is the `max_tokens` argument
+      .initialize(basketId, 5, 0, 0, provider.wallet.publicKey, new BN(0), new BN(0), 0, new BN(0), 0, new BN(0), 9, 0) // 5 is the `max_tokens` argument
       .accounts({
         basket: basketPda,
         basketMint: basketMintPda,
+        registry: registryPda,
         authority: provider.wallet.publicKey,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -13,21 +13,29 @@ describe("BasketToken Tests", () => {
 
   let basketState: anchor.web3.PublicKey;
   let basketMint: anchor.web3.PublicKey;
+  let allowlist: anchor.web3.PublicKey;
   let tokenMint: anchor.web3.PublicKey;
   let userTokenAccount: anchor.web3.PublicKey;
   let userBasketToken: anchor.web3.PublicKey;
   let basketBump: number;
+  const basketId = new anchor.BN(0);
 
   before(async () => {
     // Derive Basket PDA
     [basketState, basketBump] = await PublicKey.findProgramAddressSync(
-      [Buffer.from("basket")],
+      [Buffer.from("basket"), basketId.toArrayLike(Buffer, "le", 8)],
       program.programId
     );
 
     // Derive Basket Mint PDA
     [basketMint] = await PublicKey.findProgramAddressSync(
-      [Buffer.from("basket_mint")],
+      [Buffer.from("basket_mint"), basketId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    // Derive the (uncreated) token allowlist PDA
+    [allowlist] = await PublicKey.findProgramAddressSync(
+      [Buffer.from("token_allowlist"), basketState.toBuffer()],
       program.programId
     );
 
@@ -46,11 +54,16 @@ describe("BasketToken Tests", () => {
   });
 
   it("Initializes BasketToken with max_tokens = 5", async () => {
+    const [registry] = PublicKey.findProgramAddressSync(
+      [Buffer.from("basket_registry")],
+      program.programId
+    );
     const tx = await program.methods
-      .initialize(5)
+      .initialize(basketId, 5, 0, 0, payer.publicKey, new anchor.BN(0), new anchor.BN(0), 0, new anchor.BN(0), 0, new anchor.BN(0), 9, 0)
       .accounts({
         basket: basketState,
         basketMint,
+        registry,
         authority: payer.publicKey,
         systemProgram: SystemProgram.programId,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
@@ -66,6 +79,7 @@ describe("BasketToken Tests", () => {
       .addToken(tokenMint, 10)
       .accounts({
         basket: basketState,
+        allowlist,
         authority: payer.publicKey,
       })
       .rpc();
@@ -79,6 +93,7 @@ describe("BasketToken Tests", () => {
         .addToken(tokenMint, 15)
         .accounts({
           basket: basketState,
+          allowlist,
           authority: payer.publicKey,
         })
         .rpc();
@@ -93,6 +108,7 @@ describe("BasketToken Tests", () => {
       .removeToken(tokenMint)
       .accounts({
         basket: basketState,
+        basketToken: null,
         authority: payer.publicKey,
       })
       .rpc();
@@ -106,6 +122,7 @@ describe("BasketToken Tests", () => {
         .removeToken(tokenMint)
         .accounts({
           basket: basketState,
+          basketToken: null,
           authority: payer.publicKey,
         })
         .rpc();
@@ -116,8 +133,15 @@ describe("BasketToken Tests", () => {
   });
 
   it("Pauses the program", async () => {
+    await program.methods
+      .queueSetPauseState(true, true)
+      .accounts({
+        basket: basketState,
+        authority: payer.publicKey,
+      })
+      .rpc();
     const tx = await program.methods
-      .setPaused(true)
+      .executeSetPauseState()
       .accounts({
         basket: basketState,
         authority: payer.publicKey,
@@ -133,6 +157,7 @@ describe("BasketToken Tests", () => {
         .addToken(tokenMint, 10)
         .accounts({
           basket: basketState,
+          allowlist,
           authority: payer.publicKey,
         })
         .rpc();
@@ -143,8 +168,15 @@ describe("BasketToken Tests", () => {
   });
 
   it("Resumes the program", async () => {
+    await program.methods
+      .queueSetPauseState(false, false)
+      .accounts({
+        basket: basketState,
+        authority: payer.publicKey,
+      })
+      .rpc();
     const tx = await program.methods
-      .setPaused(false)
+      .executeSetPauseState()
       .accounts({
         basket: basketState,
         authority: payer.publicKey,